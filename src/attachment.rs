@@ -0,0 +1,286 @@
+//! File attachments: reading files for embedding and recreating them safely.
+
+use crate::format::FileRecord;
+use anyhow::{Context, Result, anyhow};
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use tempfile::NamedTempFile;
+
+/// MIME types for common extensions. Anything else is `application/octet-stream`.
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("json", "application/json"),
+    ("xml", "application/xml"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("svg", "image/svg+xml"),
+    ("webp", "image/webp"),
+    ("wav", "audio/wav"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("opus", "audio/opus"),
+    ("flac", "audio/flac"),
+    ("mp4", "video/mp4"),
+    ("pub", "application/x-zimhide-public-key"),
+];
+
+const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+/// Read a file from disk into a `FileRecord`, capturing its name, MIME type,
+/// modification time and permissions.
+pub fn read_attachment(path: &Path) -> Result<FileRecord> {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow!("Cannot attach {}: invalid file name", path.display()))?
+        .to_string();
+
+    let data =
+        fs::read(path).with_context(|| format!("Failed to read file: {}", path.display()))?;
+    let metadata = fs::metadata(path)?;
+
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+
+    #[cfg(unix)]
+    let mode = {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.permissions().mode() & 0o777
+    };
+    #[cfg(not(unix))]
+    let mode = 0;
+
+    Ok(FileRecord {
+        mime_type: guess_mime_type(&name).to_string(),
+        name,
+        modified,
+        mode,
+        data,
+    })
+}
+
+/// Guess a MIME type from a file name's extension.
+pub fn guess_mime_type(name: &str) -> &'static str {
+    let extension = Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+
+    extension
+        .and_then(|ext| {
+            MIME_TYPES
+                .iter()
+                .find(|(known, _)| *known == ext)
+                .map(|(_, mime)| *mime)
+        })
+        .unwrap_or(DEFAULT_MIME_TYPE)
+}
+
+/// Check that an embedded file name is a single plain path component.
+///
+/// Names come from untrusted input, so anything that could escape the
+/// extraction directory is rejected.
+pub fn validate_file_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains(['/', '\\', '\0']) {
+        return Err(anyhow!(
+            "Refusing to extract file with unsafe name: {name:?}"
+        ));
+    }
+
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => Err(anyhow!(
+            "Refusing to extract file with unsafe name: {name:?}"
+        )),
+    }
+}
+
+/// Recreate an attachment inside `dir`, returning the path written.
+///
+/// Existing files are only replaced when `force` is set.
+pub fn extract_attachment(file: &FileRecord, dir: &Path, force: bool) -> Result<PathBuf> {
+    validate_file_name(&file.name)?;
+
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    let path = dir.join(&file.name);
+
+    write_atomically(&path, force, |out| {
+        out.write_all(&file.data)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        if file.modified > 0 {
+            let modified = UNIX_EPOCH + Duration::from_secs(file.modified);
+            out.as_file().set_modified(modified)?;
+        }
+
+        #[cfg(unix)]
+        if file.mode != 0 {
+            use std::os::unix::fs::PermissionsExt;
+            // Never restore setuid/setgid/sticky bits from untrusted input
+            out.as_file()
+                .set_permissions(fs::Permissions::from_mode(file.mode & 0o777))?;
+        }
+        Ok(())
+    })?;
+
+    Ok(path)
+}
+
+/// Create `path` through a temporary file in the same directory, renamed into
+/// place only once `write` succeeds, so a failure never leaves a partial file.
+/// Without `force`, an existing `path` is an error. With it, the rename
+/// replaces `path` itself and never follows a symlink there.
+pub fn write_atomically(
+    path: &Path,
+    force: bool,
+    write: impl FnOnce(&mut NamedTempFile) -> Result<()>,
+) -> Result<()> {
+    // The usual mode for a new file, rather than the temporary file's 0600
+    create_atomically(path, force, 0o666, write)
+}
+
+/// As `write_atomically`, replacing any existing `path`, for a file readable
+/// by the owner only from the moment it is created.
+pub fn write_private_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut NamedTempFile) -> Result<()>,
+) -> Result<()> {
+    create_atomically(path, true, 0o600, write)
+}
+
+fn create_atomically(
+    path: &Path,
+    force: bool,
+    #[cfg_attr(not(unix), allow(unused_variables))] mode: u32,
+    write: impl FnOnce(&mut NamedTempFile) -> Result<()>,
+) -> Result<()> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if !force && fs::symlink_metadata(path).is_ok() {
+        return Err(already_exists(path));
+    }
+
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(".zimhide-");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(fs::Permissions::from_mode(mode));
+    }
+    let mut temp = builder
+        .tempfile_in(dir)
+        .map_err(|e| anyhow!("Failed to create {}: {}", path.display(), e))?;
+
+    write(&mut temp)?;
+
+    let persisted = if force {
+        temp.persist(path)
+    } else {
+        temp.persist_noclobber(path)
+    };
+    persisted.map_err(|e| {
+        if e.error.kind() == std::io::ErrorKind::AlreadyExists {
+            already_exists(path)
+        } else {
+            anyhow!("Failed to create {}: {}", path.display(), e.error)
+        }
+    })?;
+    Ok(())
+}
+
+fn already_exists(path: &Path) -> anyhow::Error {
+    anyhow!(
+        "{} already exists. Use --force to overwrite.",
+        path.display()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_unsafe_names_rejected() {
+        for name in [
+            "",
+            ".",
+            "..",
+            "../evil",
+            "/etc/passwd",
+            "a/b",
+            "a\\b",
+            "nul\0",
+        ] {
+            assert!(validate_file_name(name).is_err(), "accepted {name:?}");
+        }
+        validate_file_name("report.pdf").unwrap();
+        validate_file_name(".hidden").unwrap();
+    }
+
+    #[test]
+    fn test_extract_refuses_overwrite_without_force() {
+        let dir = tempdir().unwrap();
+        let file = FileRecord {
+            name: "out.bin".to_string(),
+            data: vec![1, 2, 3],
+            ..Default::default()
+        };
+
+        let path = extract_attachment(&file, dir.path(), false).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), vec![1, 2, 3]);
+        assert!(extract_attachment(&file, dir.path(), false).is_err());
+        extract_attachment(&file, dir.path(), true).unwrap();
+    }
+
+    #[test]
+    fn test_failed_write_leaves_nothing_behind() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out.wav");
+        let err = write_atomically(&path, false, |temp| {
+            temp.write_all(b"partial")?;
+            Err(anyhow!("MAC check failed"))
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "MAC check failed");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        fs::write(&path, b"original").unwrap();
+        assert!(write_atomically(&path, true, |_| Err(anyhow!("short write"))).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"original");
+        write_atomically(&path, true, |temp| Ok(temp.write_all(b"replaced")?)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"replaced");
+    }
+
+    #[test]
+    fn test_read_attachment_roundtrip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("notes.TXT");
+        fs::write(&path, b"hello").unwrap();
+
+        let record = read_attachment(&path).unwrap();
+        assert_eq!(record.name, "notes.TXT");
+        assert_eq!(record.mime_type, "text/plain");
+        assert_eq!(record.data, b"hello");
+        assert!(record.modified > 0);
+    }
+}
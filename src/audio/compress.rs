@@ -3,7 +3,9 @@
 //! With the `opus-compression` feature (default): Uses Opus codec at 48kHz for ~10x compression.
 //! Without the feature: Embeds raw WAV bytes (larger but no libopus dependency).
 
-use crate::{Progress, Verbosity};
+#[cfg(feature = "opus-compression")]
+use crate::Progress;
+use crate::Verbosity;
 use anyhow::{Context, Result};
 use std::path::Path;
 
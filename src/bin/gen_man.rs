@@ -20,17 +20,26 @@ fn main() -> std::io::Result<()> {
     fs::write(out_dir.join("zimhide.1"), buffer)?;
     println!("Generated: man/zimhide.1");
 
-    // Generate subcommand man pages
+    // Generate subcommand man pages, including nested ones like `keys compare`
     for subcommand in cmd.get_subcommands() {
-        let name = subcommand.get_name();
-        let man = Man::new(subcommand.clone());
-        let mut buffer = Vec::new();
-        man.render(&mut buffer)?;
-        let filename = format!("zimhide-{}.1", name);
-        fs::write(out_dir.join(&filename), buffer)?;
-        println!("Generated: man/{}", filename);
+        let name = format!("zimhide-{}", subcommand.get_name());
+        render_page(out_dir, &name, subcommand)?;
+        for nested in subcommand.get_subcommands() {
+            let nested_name = format!("{}-{}", name, nested.get_name());
+            render_page(out_dir, &nested_name, nested)?;
+        }
     }
 
     println!("\nInstall with: sudo cp man/*.1 /usr/local/share/man/man1/");
     Ok(())
 }
+
+fn render_page(out_dir: &Path, name: &str, cmd: &clap::Command) -> std::io::Result<()> {
+    let man = Man::new(cmd.clone());
+    let mut buffer = Vec::new();
+    man.render(&mut buffer)?;
+    let filename = format!("{}.1", name);
+    fs::write(out_dir.join(&filename), buffer)?;
+    println!("Generated: man/{}", filename);
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use crate::crypto::KdfParams;
+use crate::crypto::symmetric::calibrate;
+use crate::{Verbosity, status};
+use anyhow::{Result, anyhow};
+use clap::Args;
+use std::time::Duration;
+
+#[derive(Args)]
+pub struct CalibrateArgs {
+    /// Target key derivation time in milliseconds
+    #[arg(long, default_value = "1000")]
+    pub target_ms: u64,
+
+    /// Argon2id memory in MiB
+    #[arg(long, default_value = "256")]
+    pub memory: u32,
+}
+
+pub fn run(args: CalibrateArgs, verbosity: Verbosity) -> Result<()> {
+    if args.target_ms == 0 {
+        return Err(anyhow!("--target-ms must be greater than zero"));
+    }
+    let memory_kib = args
+        .memory
+        .checked_mul(1024)
+        .ok_or_else(|| anyhow!("--memory is too large: {} MiB", args.memory))?;
+
+    status!(
+        verbosity,
+        "Measuring Argon2id with {} MiB (target {} ms)...",
+        args.memory,
+        args.target_ms
+    );
+    let target = Duration::from_millis(args.target_ms);
+    let (params, elapsed) = calibrate(memory_kib, target)?;
+
+    status!(
+        verbosity,
+        "{} took {} ms on this machine",
+        params,
+        elapsed.as_millis()
+    );
+    let max_iterations = KdfParams::max_iterations(memory_kib);
+    if params.iterations == max_iterations && elapsed < target {
+        status!(
+            verbosity,
+            "Note: iterations are capped at {} with {} MiB, so that decoders accept the result",
+            max_iterations,
+            args.memory
+        );
+    }
+
+    // Flags on stdout so they can be captured by scripts
+    println!(
+        "--kdf-memory {} --kdf-iterations {}",
+        args.memory, params.iterations
+    );
+
+    Ok(())
+}
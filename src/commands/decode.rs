@@ -1,31 +1,70 @@
+use crate::attachment::extract_attachment;
+use crate::crypto::asymmetric::envelope_info;
+use crate::crypto::keys::{format_key_id, unix_now, write_private_file};
 use crate::crypto::{
-    PrivateKey, PublicKey, decrypt_asymmetric, decrypt_symmetric, verify_signature,
+    AsymmetricLayout, Credential, Keyfile, PartialDecryption, PrivateKey, PublicKey, Revocation,
+    SecretBytes, SecretString, SlotType, SymmetricLayout, decrypt_combined, decrypt_envelope,
+    decrypt_symmetric, partial_decrypt, verify_signature,
 };
-use crate::format::{EmbeddedData, Payload};
+use crate::format::{EmbeddedData, EmbeddedSignature, Payload, check_integrity};
+use crate::keyring::{
+    Keyring, Validation, find_revocation, find_signer, identity_or_default, resolve_identity,
+    resolve_public_key,
+};
+use crate::passphrase::{KeyPassphraseArgs, PASSPHRASE_HINT, PassphraseArgs, Prompt};
+use crate::stego::locate;
 use crate::stego::traits::{ChannelMode, EmbedOptions};
-use crate::stego::{LsbSteganography, MetadataSteganography, StegoMethod};
+use crate::time::format_timestamp;
+use crate::wav::carrier_hash;
 use crate::{Verbosity, status, verbose};
 use anyhow::{Result, anyhow};
 use clap::Args;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Args)]
 pub struct DecodeArgs {
     /// Input WAV file with embedded data
     pub input: PathBuf,
 
-    /// Passphrase for symmetric decryption
-    #[arg(long, conflicts_with = "key")]
-    pub passphrase: Option<String>,
+    #[command(flatten)]
+    pub passphrase: PassphraseArgs,
 
-    /// Private key file for asymmetric decryption
-    #[arg(long, conflicts_with = "passphrase")]
+    /// Private key file (zimhide, OpenSSH or age) or keyring identity for asymmetric
+    /// decryption [default: the default identity]. Two-factor messages also need the
+    /// passphrase
+    #[arg(long)]
     pub key: Option<PathBuf>,
 
-    /// Public key file to verify signature
+    #[command(flatten)]
+    pub key_passphrase: KeyPassphraseArgs,
+
+    /// Keyfile that opens a key slot of the message
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["key", "passphrase_source"])]
+    pub keyfile: Option<PathBuf>,
+
+    /// Write your partial decryption of a threshold-encrypted message to FILE
+    /// instead of decrypting it
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["passphrase_source", "keyfile", "combine", "extract_dir"])]
+    pub partial: Option<PathBuf>,
+
+    /// Partial decryption from another recipient of a threshold-encrypted message
+    /// (repeatable). Add --key to contribute your own
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["passphrase_source", "keyfile"])]
+    pub combine: Vec<PathBuf>,
+
+    /// Public key to verify the signature (zimhide or ssh-ed25519 file, ssh-ed25519 key, or
+    /// keyring name)
     #[arg(long)]
     pub verify: Option<PathBuf>,
 
+    /// Directory to recreate embedded files in
+    #[arg(long = "extract-dir")]
+    pub extract_dir: Option<PathBuf>,
+
+    /// Overwrite existing files when extracting
+    #[arg(long, requires = "extract_dir")]
+    pub force: bool,
+
     /// Bits per sample for LSB method (must match encoding)
     #[arg(long, default_value = "1")]
     pub bits: u8,
@@ -43,9 +82,18 @@ pub fn run(args: DecodeArgs, verbosity: Verbosity) -> Result<()> {
         ));
     }
 
-    // Try LSB first, then metadata
+    // Try metadata first, then LSB
     let data = try_extract(&args)?;
 
+    let integrity = check_integrity(&data);
+    if integrity.is_corrupted() {
+        return Err(anyhow!(
+            "Embedded data is {}. The carrier was probably modified after encoding.",
+            integrity
+        ));
+    }
+    verbose!(verbosity, "Integrity: {}", integrity);
+
     // Parse embedded data
     let embedded = EmbeddedData::from_bytes(&data)?;
     let flags = &embedded.header.flags;
@@ -53,50 +101,208 @@ pub fn run(args: DecodeArgs, verbosity: Verbosity) -> Result<()> {
     verbose!(verbosity, "Format version: {}", embedded.header.version);
     verbose!(verbosity, "Payload size: {} bytes", embedded.payload.len());
 
-    // Verify signature if requested
+    // Verify the signature against --verify, or else the keyring entry it
+    // names. Content is still shown for untrusted signers, but the exit
+    // status reports them.
+    let mut untrusted = None;
+    let mut signing_key = None;
     if let Some(ref verify_path) = args.verify {
         if !flags.is_signed {
             return Err(anyhow!("Message is not signed"));
         }
-        let public_key = PublicKey::load(verify_path)?;
-        let signature = embedded
-            .signature
-            .as_ref()
-            .ok_or_else(|| anyhow!("No signature found"))?;
-        verify_signature(&embedded.payload, signature, &public_key)?;
-        status!(verbosity, "Signature verified successfully");
-    } else if flags.is_signed {
+        let public_key = resolve_public_key(verify_path)?;
+        let signature = check_signature(&embedded, &args.input, &public_key)?;
         status!(
             verbosity,
-            "Note: Message is signed. Use --verify to verify the signature."
+            "Signature verified successfully (covers {})",
+            signature.scope.describe()
         );
+        signing_key = Some(public_key);
+    } else if let Some(ref signature) = embedded.signature {
+        match signature.signer {
+            Some(ref id) => match find_signer(id)? {
+                Some(signer) => {
+                    check_signature(&embedded, &args.input, &signer.key)?;
+                    signing_key = Some(signer.key.clone());
+                    status!(
+                        verbosity,
+                        "Good signature from {} ({}; covers {})",
+                        signer.name,
+                        signer.trust_label(),
+                        signature.scope.describe()
+                    );
+                    // A contact you added but have not verified only gets a
+                    // note: adding it was your decision, and `untrusted` is
+                    // how a key is rejected
+                    if signer.validation == Validation::Unknown {
+                        status!(
+                            verbosity,
+                            "Note: confirm this key with 'zimhide keys compare', then 'zimhide keys trust {} verified', or import a certification from an introducer.",
+                            signer.name
+                        );
+                    }
+                    if !signer.is_trusted() {
+                        untrusted = Some(format!(
+                            "Signature is from {}, which is marked untrusted",
+                            signer.name
+                        ));
+                    }
+                }
+                None => {
+                    status!(
+                        verbosity,
+                        "Signed by unknown key {}. Add it with 'zimhide keys add' or use --verify.",
+                        format_key_id(id)
+                    );
+                    untrusted = Some("Signer is not in the keyring".to_string());
+                }
+            },
+            None => status!(
+                verbosity,
+                "Note: Message is signed. Use --verify to verify the signature."
+            ),
+        }
+    }
+
+    // Signatures carry no date, so one from a revoked key may have been
+    // made with the stolen key
+    if let Some(ref key) = signing_key {
+        if let Some(revocation) = find_revocation(&key.key_id())? {
+            status!(
+                verbosity,
+                "Warning: the signing key was REVOKED on {} ({}). Do not rely on this signature.",
+                format_timestamp(revocation.revoked),
+                revocation.reason
+            );
+            untrusted = Some(format!(
+                "Signature is from key {}, which was revoked",
+                key.fingerprint()
+            ));
+        } else if let Some(expires) = key.expires().filter(|_| key.is_expired(unix_now())) {
+            status!(
+                verbosity,
+                "Note: the signing key expired on {}.",
+                format_timestamp(expires)
+            );
+        }
+    }
+
+    if (args.partial.is_some() || !args.combine.is_empty()) && !flags.key_slot_encryption {
+        return Err(anyhow!(
+            "--partial and --combine apply to threshold-encrypted messages; this one is not encrypted to public keys"
+        ));
     }
 
     // Decrypt payload
     let payload_bytes = if flags.symmetric_encryption {
-        let passphrase = args
-            .passphrase
-            .as_ref()
-            .ok_or_else(|| anyhow!("Message is encrypted. Use --passphrase to decrypt."))?;
-        decrypt_symmetric(&embedded.payload, passphrase)?
-    } else if flags.asymmetric_encryption {
-        let key_path = args
-            .key
-            .as_ref()
-            .ok_or_else(|| anyhow!("Message is encrypted. Use --key to decrypt."))?;
-        let private_key = PrivateKey::load(key_path)?;
-        decrypt_asymmetric(&embedded.payload, &private_key)?
+        decrypt_symmetric_payload(
+            &embedded,
+            &args.passphrase,
+            args.keyfile.as_deref(),
+            "Message",
+        )?
+    } else if flags.key_slot_encryption {
+        let layout = AsymmetricLayout::for_format_version(embedded.header.version);
+        if layout.kek.is_legacy() {
+            status!(
+                verbosity,
+                "{}",
+                legacy_kek_warning("message", embedded.header.version)
+            );
+        }
+
+        if let Some(ref path) = args.partial {
+            // A threshold recipient hands out their share instead of decrypting
+            let key_path = identity_or_default(args.key.as_deref())?
+                .ok_or_else(|| anyhow!("Message is encrypted. Use --key to decrypt."))?;
+            let private_key = args.key_passphrase.load_private_key(&key_path)?;
+            let partial = partial_decrypt(&embedded.payload, &private_key, layout)?;
+            write_private_file(path, &partial.to_pem())?;
+            status!(
+                verbosity,
+                "Wrote the partial decryption for key slot {} to {} ({} are needed). Send it only to whoever combines them: together with enough others it reveals the message.",
+                partial.index,
+                path.display(),
+                partial.threshold
+            );
+            return untrusted.map_or(Ok(()), |reason| Err(anyhow!(reason)));
+        }
+
+        if args.combine.is_empty() {
+            let unlock = unlock_envelope(
+                &embedded.payload,
+                layout,
+                &args.passphrase,
+                args.keyfile.as_deref(),
+                args.key.as_deref(),
+                &args.key_passphrase,
+                "Message",
+            )?;
+            decrypt_envelope(&embedded.payload, unlock.credential(), layout)?
+        } else {
+            let mut partials = args
+                .combine
+                .iter()
+                .map(|path| PartialDecryption::load(path))
+                .collect::<Result<Vec<_>>>()?;
+            // Only an explicit --key contributes, so a default identity that
+            // is not a recipient does not get in the way
+            if let Some(ref key) = args.key {
+                let key_path = identity_or_default(Some(key))?.expect("key given");
+                let private_key = args.key_passphrase.load_private_key(&key_path)?;
+                partials.push(partial_decrypt(&embedded.payload, &private_key, layout)?);
+            }
+            verbose!(
+                verbosity,
+                "Combining {} partial decryptions",
+                partials.len()
+            );
+            decrypt_combined(&embedded.payload, &partials, layout)?
+        }
     } else {
-        embedded.payload.clone()
+        SecretBytes::from(embedded.payload.as_slice())
     };
 
     // Parse payload
-    let payload = Payload::from_bytes(&payload_bytes)?;
+    let payload = Payload::decode(&payload_bytes, &embedded.header)?;
 
-    if let Some(text) = payload.text {
+    if payload.unknown_records > 0 {
+        status!(
+            verbosity,
+            "Note: Skipped {} record(s) of a type this version does not understand.",
+            payload.unknown_records
+        );
+    }
+
+    for revocation in &payload.revocations {
+        import_revocation(revocation, verbosity)?;
+    }
+
+    if let Some(ref text) = payload.text {
         println!("{}", text);
     }
 
+    if let Some(ref dir) = args.extract_dir {
+        if payload.files.is_empty() {
+            status!(verbosity, "No files are attached.");
+        }
+        for file in &payload.files {
+            let path = extract_attachment(file, dir, args.force)?;
+            status!(
+                verbosity,
+                "Extracted {} ({} bytes)",
+                path.display(),
+                file.data.len()
+            );
+        }
+    } else if !payload.files.is_empty() {
+        status!(
+            verbosity,
+            "Note: {} file(s) attached. Use --extract-dir to save them.",
+            payload.files.len()
+        );
+    }
+
     if payload.audio.is_some() {
         status!(
             verbosity,
@@ -104,33 +310,250 @@ pub fn run(args: DecodeArgs, verbosity: Verbosity) -> Result<()> {
         );
     }
 
-    Ok(())
+    match untrusted {
+        Some(reason) => Err(anyhow!(reason)),
+        None => Ok(()),
+    }
 }
 
-fn try_extract(args: &DecodeArgs) -> Result<Vec<u8>> {
-    // Try metadata first (quick check)
-    let metadata_stego = MetadataSteganography::new();
-    if let Ok(data) = metadata_stego.extract(&args.input)
-        && data.len() >= 4
-        && &data[0..4] == b"ZIMH"
-    {
-        return Ok(data);
+/// The warning for an envelope whose key slots use the pre-HKDF key derivation.
+pub(crate) fn legacy_kek_warning(what: &str, version: u8) -> String {
+    format!(
+        "Warning: this {what} uses the legacy key derivation of format version {version}, which may not decrypt with future builds. Re-encode it to upgrade."
+    )
+}
+
+/// Decrypt a passphrase envelope. Keyfiles only ever open key slots.
+pub(crate) fn decrypt_symmetric_payload(
+    embedded: &EmbeddedData,
+    passphrase: &PassphraseArgs,
+    keyfile: Option<&Path>,
+    what: &str,
+) -> Result<SecretBytes> {
+    if keyfile.is_some() {
+        return Err(anyhow!(
+            "{} is encrypted with a passphrase, not a keyfile. {} to decrypt.",
+            what,
+            PASSPHRASE_HINT
+        ));
     }
+    let passphrase = passphrase
+        .resolve(Prompt::Once)?
+        .ok_or_else(|| anyhow!("{} is encrypted. {} to decrypt.", what, PASSPHRASE_HINT))?;
+    decrypt_symmetric(
+        &embedded.payload,
+        &passphrase,
+        SymmetricLayout::for_format_version(embedded.header.version),
+    )
+}
 
-    // Try LSB
-    let options = EmbedOptions {
-        bits_per_sample: args.bits,
-        channels: args.channels,
+/// What the reader supplied to open a key slot.
+pub(crate) enum Unlock {
+    PrivateKey(Box<PrivateKey>),
+    Passphrase(SecretString),
+    Keyfile(Keyfile),
+    KeyAndPassphrase(Box<PrivateKey>, SecretString),
+}
+
+impl Unlock {
+    pub(crate) fn credential(&self) -> Credential<'_> {
+        match self {
+            Self::PrivateKey(key) => Credential::PrivateKey(key),
+            Self::Passphrase(passphrase) => Credential::Passphrase(passphrase),
+            Self::Keyfile(keyfile) => Credential::Keyfile(keyfile),
+            Self::KeyAndPassphrase(key, passphrase) => {
+                Credential::KeyAndPassphrase(key, passphrase)
+            }
+        }
+    }
+}
+
+/// Choose how to open an asymmetric envelope: both factors for a two-factor
+/// envelope, else `--key` for a public-key slot, `--keyfile` for a keyfile
+/// slot or a given passphrase for a passphrase slot, else the default
+/// identity, else a passphrase prompt. Credentials are only matched against
+/// slot types the envelope has, so an exported `ZIMHIDE_PASSPHRASE` does not
+/// get in the way of a public-key message.
+pub(crate) fn unlock_envelope(
+    payload: &[u8],
+    layout: AsymmetricLayout,
+    passphrase: &PassphraseArgs,
+    keyfile: Option<&Path>,
+    key: Option<&Path>,
+    key_passphrase: &KeyPassphraseArgs,
+    what: &str,
+) -> Result<Unlock> {
+    let slot_types = envelope_info(payload, layout).and_then(|info| info.slot_types);
+    let has_slot = |slot_type: SlotType| {
+        slot_types
+            .as_ref()
+            .map_or(slot_type == SlotType::PublicKey, |types| {
+                types.contains(&slot_type)
+            })
     };
-    let lsb_stego = LsbSteganography::new(options);
-    let data = lsb_stego.extract(&args.input)?;
 
-    // Verify it's valid zimhide data
-    if data.len() >= 4 && &data[0..4] == b"ZIMH" {
-        return Ok(data);
+    if has_slot(SlotType::KeyAndPassphrase) && keyfile.is_none() {
+        let key_path = identity_or_default(key)?;
+        let private_key = match key_path {
+            Some(ref path) => Some(key_passphrase.load_private_key(path)?),
+            None => None,
+        };
+        let passphrase = passphrase.resolve(Prompt::Once)?;
+        return match (private_key, passphrase) {
+            (Some(private_key), Some(passphrase)) => {
+                Ok(Unlock::KeyAndPassphrase(Box::new(private_key), passphrase))
+            }
+            (Some(_), None) => Err(anyhow!(
+                "{} needs a private key and a passphrase. {} to supply the passphrase.",
+                what,
+                PASSPHRASE_HINT
+            )),
+            (None, Some(_)) => Err(anyhow!(
+                "{} needs a private key and a passphrase. Use --key to supply the private key.",
+                what
+            )),
+            (None, None) => Err(anyhow!(
+                "{} needs a private key and a passphrase. Use --key and a passphrase option.",
+                what
+            )),
+        };
+    }
+
+    if let Some(key_path) = key
+        && has_slot(SlotType::PublicKey)
+    {
+        return Ok(Unlock::PrivateKey(Box::new(
+            key_passphrase.load_private_key(&resolve_identity(key_path)?)?,
+        )));
+    }
+    if let Some(path) = keyfile
+        && has_slot(SlotType::Keyfile)
+    {
+        return Ok(Unlock::Keyfile(Keyfile::load(path)?));
+    }
+    if has_slot(SlotType::Passphrase)
+        && let Some(passphrase) = passphrase.resolve(Prompt::Never)?
+    {
+        return Ok(Unlock::Passphrase(passphrase));
+    }
+    if has_slot(SlotType::PublicKey)
+        && let Some(key_path) = identity_or_default(None)?
+    {
+        return Ok(Unlock::PrivateKey(Box::new(
+            key_passphrase.load_private_key(&key_path)?,
+        )));
+    }
+    if has_slot(SlotType::Passphrase)
+        && let Some(passphrase) = passphrase.resolve(Prompt::Once)?
+    {
+        return Ok(Unlock::Passphrase(passphrase));
+    }
+
+    // A key or keyfile for a slot type the envelope lacks: decryption names
+    // the slot types it has
+    if let Some(key_path) = key {
+        return Ok(Unlock::PrivateKey(Box::new(
+            key_passphrase.load_private_key(&resolve_identity(key_path)?)?,
+        )));
+    }
+    if let Some(path) = keyfile {
+        return Ok(Unlock::Keyfile(Keyfile::load(path)?));
     }
 
+    let mut options = Vec::new();
+    if has_slot(SlotType::PublicKey) {
+        options.push("--key");
+    }
+    if has_slot(SlotType::Passphrase) {
+        options.push("a passphrase option");
+    }
+    if has_slot(SlotType::Keyfile) {
+        options.push("--keyfile");
+    }
     Err(anyhow!(
-        "No valid zimhide data found in file. The file may not contain embedded data, or you may need to specify --bits and --channels to match the encoding."
+        "{} is encrypted. Use {} to decrypt.",
+        what,
+        options.join(" or ")
     ))
 }
+
+/// Verify the embedded signature with `public_key`, hashing the carrier at
+/// `input` when the signature is bound to it.
+pub(crate) fn check_signature<'a>(
+    embedded: &'a EmbeddedData,
+    input: &Path,
+    public_key: &PublicKey,
+) -> Result<&'a EmbeddedSignature> {
+    let signature = embedded
+        .signature
+        .as_ref()
+        .ok_or_else(|| anyhow!("No signature found"))?;
+    let carrier = if signature.scope.covers_carrier() {
+        Some(carrier_hash(input)?)
+    } else {
+        None
+    };
+    let message =
+        embedded.signed_message(signature.scope, signature.signer.as_ref(), carrier.as_ref())?;
+    verify_signature(&message, &signature.bytes, public_key).map_err(|e| {
+        if signature.scope.covers_carrier() {
+            anyhow!("{e}, or the payload was moved to a different carrier")
+        } else {
+            e
+        }
+    })?;
+    Ok(signature)
+}
+
+/// Import an embedded revocation certificate if it revokes a key in the keyring.
+fn import_revocation(bytes: &[u8], verbosity: Verbosity) -> Result<()> {
+    let revocation = match Revocation::from_bytes(bytes) {
+        Ok(revocation) => revocation,
+        Err(e) => {
+            status!(
+                verbosity,
+                "Warning: Ignored a revocation certificate: {}",
+                e
+            );
+            return Ok(());
+        }
+    };
+    let details = format!(
+        "revoked {}, {}",
+        format_timestamp(revocation.revoked),
+        revocation.reason
+    );
+    match find_signer(&revocation.key_id())? {
+        Some(entry) => {
+            Keyring::open()?.add_revocation(&revocation)?;
+            status!(
+                verbosity,
+                "Imported revocation of {} ({})",
+                entry.name,
+                details
+            );
+        }
+        None => status!(
+            verbosity,
+            "Note: Carries a revocation of key {} ({}), which is not in your keyring.",
+            revocation.key.fingerprint(),
+            details
+        ),
+    }
+    Ok(())
+}
+
+fn try_extract(args: &DecodeArgs) -> Result<Vec<u8>> {
+    let options = EmbedOptions {
+        bits_per_sample: args.bits,
+        channels: args.channels,
+    };
+
+    locate(&args.input, options)?
+        .map(|located| located.data)
+        .ok_or_else(|| {
+            anyhow!(
+                "No valid zimhide data found in file. The file may not contain embedded data, or you may need to specify --bits and --channels to match the encoding."
+            )
+        })
+}
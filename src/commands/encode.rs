@@ -1,12 +1,23 @@
-use crate::crypto::{PrivateKey, PublicKey, encrypt_asymmetric, encrypt_symmetric, sign_message};
-use crate::format::{EmbeddedData, Flags, Header, Payload};
+use crate::attachment::read_attachment;
+use crate::crypto::asymmetric::padded_slot_count;
+use crate::crypto::{
+    EnvelopeOptions, KdfProfile, Keyfile, PublicKey, Recipient, Revocation, encrypt_asymmetric,
+    encrypt_symmetric, encrypt_to_slots, sign_message,
+};
+use crate::format::{
+    EmbeddedData, EmbeddedSignature, Flags, Header, Payload, SignatureScope, compress_payload,
+};
+use crate::keyring::{check_usable, identity_or_default, resolve_recipients};
+use crate::passphrase::{KeyPassphraseArgs, PASSPHRASE_HINT, PassphraseArgs, Prompt};
 use crate::stego::traits::{ChannelMode, EmbedOptions};
 use crate::stego::{LsbSteganography, MetadataSteganography, StegoMethod, StegoMethodType};
+use crate::wav::carrier_hash;
 use crate::{Verbosity, status, verbose};
 use anyhow::{Result, anyhow};
 use clap::Args;
 use std::fs;
 use std::path::PathBuf;
+use zeroize::Zeroizing;
 
 #[derive(Args)]
 pub struct EncodeArgs {
@@ -36,22 +47,79 @@ pub struct EncodeArgs {
     #[arg(long)]
     pub audio: Option<PathBuf>,
 
-    /// Passphrase for symmetric encryption
-    #[arg(long, conflicts_with = "encrypt_to")]
-    pub passphrase: Option<String>,
+    /// File to attach with its name, MIME type and modification time (can be repeated)
+    #[arg(long = "file")]
+    pub files: Vec<PathBuf>,
 
-    /// Public key file(s) for asymmetric encryption (can be repeated)
-    #[arg(long = "encrypt-to", conflicts_with = "passphrase")]
+    /// Revocation certificate to pass on; decoders import it into their keyring
+    /// if they know the key (can be repeated)
+    #[arg(long = "revocation", value_name = "CERTIFICATE")]
+    pub revocations: Vec<PathBuf>,
+
+    /// Encrypt with a passphrase, prompting for it unless a passphrase option or ZIMHIDE_PASSPHRASE supplies it
+    #[arg(long)]
+    pub symmetric: bool,
+
+    #[command(flatten)]
+    pub passphrase: PassphraseArgs,
+
+    /// Argon2id cost profile for passphrase encryption [default: interactive]
+    #[arg(long, value_enum)]
+    pub kdf_profile: Option<KdfProfile>,
+
+    /// Argon2id memory in MiB, overriding the profile
+    #[arg(long)]
+    pub kdf_memory: Option<u32>,
+
+    /// Argon2id iterations, overriding the profile
+    #[arg(long)]
+    pub kdf_iterations: Option<u32>,
+
+    /// Recipient public key (zimhide or ssh-ed25519 file, ssh-ed25519/age1 key, or keyring
+    /// contact or group; repeatable). With a passphrase or --keyfile, either also opens the
+    /// message
+    #[arg(long = "encrypt-to")]
     pub encrypt_to: Vec<PathBuf>,
 
+    /// Encrypt with a keyfile (see 'zimhide keygen --symmetric'), which skips Argon2. With
+    /// a passphrase or --encrypt-to, either opens the message
+    #[arg(long, value_name = "FILE")]
+    pub keyfile: Option<PathBuf>,
+
+    /// Require the passphrase as well as a recipient's private key to decrypt, instead of
+    /// either one
+    #[arg(long, requires = "encrypt_to", conflicts_with_all = ["keyfile", "threshold"])]
+    pub require_all: bool,
+
+    /// Pad the recipient list with dummy key slots so its size is hidden
+    #[arg(long, requires = "encrypt_to")]
+    pub hide_recipients: bool,
+
+    /// Tag key slots with short recipient hints so decoders find theirs directly.
+    /// Anyone holding a recipient's public key can then tell they are one
+    #[arg(long, requires = "encrypt_to")]
+    pub recipient_hints: bool,
+
+    /// Require this many recipients to decrypt together, each contributing a
+    /// partial decryption (see 'zimhide decode --partial')
+    #[arg(long, value_name = "K", requires = "encrypt_to", value_parser = clap::value_parser!(u8).range(2..))]
+    pub threshold: Option<u8>,
+
     /// Sign the message
-    #[arg(long, requires = "key")]
+    #[arg(long)]
     pub sign: bool,
 
-    /// Private key file for signing
-    #[arg(long)]
+    /// Private key file or keyring identity for signing [default: the default identity]
+    #[arg(long, requires = "sign")]
     pub key: Option<PathBuf>,
 
+    #[command(flatten)]
+    pub key_passphrase: KeyPassphraseArgs,
+
+    /// Bind the signature to the carrier audio, so it fails if the payload is moved to another file
+    #[arg(long, requires = "sign")]
+    pub bind_carrier: bool,
+
     /// Steganography method
     #[arg(long, value_enum, default_value = "lsb")]
     pub method: StegoMethodType,
@@ -90,45 +158,209 @@ pub fn run(args: EncodeArgs, verbosity: Verbosity) -> Result<()> {
         None
     };
 
-    if text.is_none() && audio.is_none() {
+    // Get file attachments
+    let files = args
+        .files
+        .iter()
+        .map(|p| read_attachment(p))
+        .collect::<Result<Vec<_>>>()?;
+    for file in &files {
+        verbose!(
+            verbosity,
+            "Attaching {} ({} bytes, {})",
+            file.name,
+            file.data.len(),
+            file.mime_type
+        );
+    }
+
+    let revocations = args
+        .revocations
+        .iter()
+        .map(|p| Revocation::load(p).map(|revocation| revocation.to_bytes()))
+        .collect::<Result<Vec<_>>>()?;
+
+    if text.is_none() && audio.is_none() && files.is_empty() && revocations.is_empty() {
         return Err(anyhow!(
-            "Nothing to embed. Use --message, --message-file, or --audio"
+            "Nothing to embed. Use --message, --message-file, --audio, --file or --revocation"
         ));
     }
 
-    // Build payload
-    let payload = Payload { text, audio };
-    let mut payload_bytes = payload.to_bytes();
+    // Build payload, wiped on drop like the decoded one
+    let payload = Zeroizing::new(Payload {
+        text,
+        audio,
+        files,
+        revocations,
+        ..Default::default()
+    });
+    // Plaintext until encryption replaces it; wiped whenever it is replaced
+    let mut payload_bytes = Zeroizing::new(payload.to_bytes());
 
-    // Encryption
     let mut flags = Flags {
         has_text: payload.text.is_some(),
         has_audio: payload.audio.is_some(),
+        has_files: !payload.files.is_empty(),
         ..Default::default()
     };
 
-    if let Some(ref passphrase) = args.passphrase {
-        payload_bytes = encrypt_symmetric(&payload_bytes, passphrase)?;
+    // Compression (before encryption, while the data still has structure)
+    let uncompressed_size = payload_bytes.len();
+    if let Some(compressed) = compress_payload(&payload_bytes)? {
+        verbose!(
+            verbosity,
+            "Compression: {} -> {} bytes ({:.1}% of original)",
+            uncompressed_size,
+            compressed.len(),
+            (compressed.len() as f64 / uncompressed_size as f64) * 100.0
+        );
+        payload_bytes = Zeroizing::new(compressed);
+        flags.compressed = true;
+    } else {
+        verbose!(
+            verbosity,
+            "Compression: skipped ({} bytes would not shrink)",
+            uncompressed_size
+        );
+    }
+
+    // Encryption
+
+    let symmetric = args.symmetric || args.passphrase.is_given();
+    let keyfile = args.keyfile.as_deref().map(Keyfile::load).transpose()?;
+    if args.require_all && !symmetric {
+        return Err(anyhow!(
+            "--require-all needs a passphrase as the second factor. Add --symmetric or a passphrase option"
+        ));
+    }
+    if !symmetric
+        && (args.kdf_profile.is_some()
+            || args.kdf_memory.is_some()
+            || args.kdf_iterations.is_some())
+    {
+        return Err(anyhow!(
+            "--kdf-profile, --kdf-memory and --kdf-iterations apply to passphrase encryption only"
+        ));
+    }
+
+    let passphrase = if symmetric {
+        Some(args.passphrase.resolve(Prompt::Confirm)?.ok_or_else(|| {
+            anyhow!(
+                "No passphrase given. {}, or run in a terminal to be prompted.",
+                PASSPHRASE_HINT
+            )
+        })?)
+    } else {
+        None
+    };
+    let mut kdf = args.kdf_profile.unwrap_or_default().params();
+    if let Some(memory) = args.kdf_memory {
+        kdf.memory_kib = memory
+            .checked_mul(1024)
+            .ok_or_else(|| anyhow!("--kdf-memory is too large: {} MiB", memory))?;
+    }
+    if let Some(iterations) = args.kdf_iterations {
+        kdf.iterations = iterations;
+    }
+    if passphrase.is_some() {
+        verbose!(verbosity, "Key derivation: {}", kdf);
+    }
+
+    if !args.encrypt_to.is_empty() {
+        let public_keys: Vec<PublicKey> = resolve_recipients(&args.encrypt_to)?;
+        let options = EnvelopeOptions {
+            hide_recipients: args.hide_recipients,
+            recipient_hints: args.recipient_hints,
+            threshold: args.threshold,
+        };
+        if args.hide_recipients {
+            verbose!(
+                verbosity,
+                "Recipients: {} in {} key slots",
+                public_keys.len(),
+                padded_slot_count(public_keys.len())
+            );
+        }
+        if let Some(threshold) = args.threshold {
+            verbose!(
+                verbosity,
+                "Threshold: any {} of {} recipients decrypt together",
+                threshold,
+                public_keys.len()
+            );
+        }
+        payload_bytes = Zeroizing::new(if passphrase.is_none() && keyfile.is_none() {
+            encrypt_asymmetric(&payload_bytes, &public_keys, &options)?
+        } else if args.require_all {
+            // Two-factor envelope: each recipient needs their key and the passphrase
+            let passphrase = passphrase.as_ref().expect("checked above");
+            let recipients: Vec<Recipient> = public_keys
+                .iter()
+                .map(|key| Recipient::KeyAndPassphrase(key, passphrase, kdf))
+                .collect();
+            verbose!(
+                verbosity,
+                "Key slots: {} public key{}, each also requiring the passphrase",
+                public_keys.len(),
+                if public_keys.len() == 1 { "" } else { "s" }
+            );
+            encrypt_to_slots(&payload_bytes, &recipients, &options)?
+        } else {
+            // Mixed envelope: each public key, the passphrase and the keyfile
+            // get a key slot of their own
+            let mut recipients: Vec<Recipient> =
+                public_keys.iter().map(Recipient::PublicKey).collect();
+            if let Some(ref passphrase) = passphrase {
+                recipients.push(Recipient::Passphrase(passphrase, kdf));
+            }
+            if let Some(ref keyfile) = keyfile {
+                recipients.push(Recipient::Keyfile(keyfile));
+            }
+            verbose!(
+                verbosity,
+                "Key slots: {} public key{}{}{}",
+                public_keys.len(),
+                if public_keys.len() == 1 { "" } else { "s" },
+                if passphrase.is_some() {
+                    ", passphrase"
+                } else {
+                    ""
+                },
+                if keyfile.is_some() { ", keyfile" } else { "" }
+            );
+            encrypt_to_slots(&payload_bytes, &recipients, &options)?
+        });
+        flags.key_slot_encryption = true;
+    } else if let Some(ref keyfile) = keyfile {
+        // A keyfile slot, its key wrapped with HKDF rather than Argon2, and
+        // beside it a passphrase slot if one was given: either opens it
+        let mut recipients = Vec::with_capacity(2);
+        if let Some(ref passphrase) = passphrase {
+            recipients.push(Recipient::Passphrase(passphrase, kdf));
+            verbose!(verbosity, "Key slots: passphrase, keyfile");
+        }
+        recipients.push(Recipient::Keyfile(keyfile));
+        payload_bytes = Zeroizing::new(encrypt_to_slots(
+            &payload_bytes,
+            &recipients,
+            &EnvelopeOptions::default(),
+        )?);
+        flags.key_slot_encryption = true;
+    } else if let Some(passphrase) = passphrase {
+        payload_bytes = Zeroizing::new(encrypt_symmetric(&payload_bytes, &passphrase, &kdf)?);
         flags.symmetric_encryption = true;
-    } else if !args.encrypt_to.is_empty() {
-        let recipients: Vec<PublicKey> = args
-            .encrypt_to
-            .iter()
-            .map(|p| PublicKey::load(p))
-            .collect::<Result<Vec<_>>>()?;
-        payload_bytes = encrypt_asymmetric(&payload_bytes, &recipients)?;
-        flags.asymmetric_encryption = true;
     }
 
-    // Signing
-    let signature = if args.sign {
-        let key_path = args
-            .key
-            .as_ref()
-            .ok_or_else(|| anyhow!("--key is required for signing"))?;
-        let private_key = PrivateKey::load(key_path)?;
+    // Load the signing key up front so the header can record the signature
+    let signing_key = if args.sign {
+        let key_path = identity_or_default(args.key.as_deref())?.ok_or_else(|| {
+            anyhow!("--sign needs --key, or a default identity (see 'zimhide keys add')")
+        })?;
         flags.is_signed = true;
-        Some(sign_message(&payload_bytes, &private_key))
+        flags.names_signer = true;
+        let private_key = args.key_passphrase.load_private_key(&key_path)?;
+        check_usable(&private_key.public_key(), "Signing key")?;
+        Some(private_key)
     } else {
         None
     };
@@ -143,15 +375,34 @@ pub fn run(args: EncodeArgs, verbosity: Verbosity) -> Result<()> {
         version: crate::format::payload::VERSION,
         flags,
         method: method_id,
-        payload_length: payload_bytes.len() as u32,
+        payload_length: Header::payload_length_for(payload_bytes.len())?,
     };
 
-    let embedded = EmbeddedData {
+    let mut embedded = EmbeddedData {
         header,
-        payload: payload_bytes,
-        signature,
+        payload: std::mem::take(&mut *payload_bytes),
+        signature: None,
     };
 
+    // Signing covers the header, the payload and optionally the carrier audio
+    if let Some(private_key) = signing_key {
+        let (scope, carrier) = if args.bind_carrier {
+            (
+                SignatureScope::HeaderAndCarrier,
+                Some(carrier_hash(&args.input)?),
+            )
+        } else {
+            (SignatureScope::Header, None)
+        };
+        let signer = private_key.public_key().key_id();
+        let message = embedded.signed_message(scope, Some(&signer), carrier.as_ref())?;
+        embedded.signature = Some(EmbeddedSignature {
+            scope,
+            signer: Some(signer),
+            bytes: sign_message(&message, &private_key)?,
+        });
+    }
+
     let data_bytes = embedded.to_bytes();
 
     // Choose steganography method
@@ -194,13 +445,22 @@ pub fn run(args: EncodeArgs, verbosity: Verbosity) -> Result<()> {
         "Encryption: {}",
         if flags.symmetric_encryption {
             "symmetric"
-        } else if flags.asymmetric_encryption {
+        } else if keyfile.is_some() && args.encrypt_to.is_empty() {
+            if symmetric {
+                "keyfile or passphrase"
+            } else {
+                "keyfile"
+            }
+        } else if flags.key_slot_encryption {
             "asymmetric"
         } else {
             "none"
         }
     );
-    verbose!(verbosity, "Signed: {}", flags.is_signed);
+    match &embedded.signature {
+        Some(sig) => verbose!(verbosity, "Signed: yes (covers {})", sig.scope.describe()),
+        None => verbose!(verbosity, "Signed: no"),
+    }
 
     Ok(())
 }
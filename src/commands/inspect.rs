@@ -1,16 +1,44 @@
+use super::decode::{check_signature, legacy_kek_warning};
 use crate::Verbosity;
-use crate::crypto::asymmetric::recipient_count;
-use crate::format::EmbeddedData;
-use crate::stego::{LsbSteganography, MetadataSteganography, StegoMethod, StegoMethodType};
+use crate::crypto::asymmetric::{EnvelopeInfo, SlotCount, envelope_info, find_slot};
+use crate::crypto::keys::format_key_id;
+use crate::crypto::symmetric::kdf_params;
+use crate::crypto::{
+    AsymmetricLayout, Credential, Keyfile, PrivateKey, SecretBytes, SlotType, SymmetricLayout,
+    decrypt_envelope, decrypt_symmetric,
+};
+use crate::format::{EmbeddedData, Payload, check_integrity};
+use crate::keyring::{find_revocation, find_signer, resolve_identity};
+use crate::passphrase::{KeyPassphraseArgs, PassphraseArgs, Prompt};
+use crate::stego::traits::EmbedOptions;
+use crate::stego::{StegoMethodType, locate};
+use crate::time::format_timestamp;
 use anyhow::{Result, anyhow};
 use clap::Args;
 use std::fmt::Write;
 use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
 
 #[derive(Args)]
 pub struct InspectArgs {
     /// Input WAV file to inspect
     pub input: PathBuf,
+
+    /// Passphrase options, to list the contents of an encrypted payload
+    #[command(flatten)]
+    pub passphrase: PassphraseArgs,
+
+    /// Private key file or keyring identity, to find your key slot and list the contents of
+    /// an encrypted payload. Two-factor messages also need the passphrase
+    #[arg(long)]
+    pub key: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub key_passphrase: KeyPassphraseArgs,
+
+    /// Keyfile that opens a key slot, to list the contents of an encrypted payload
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["key", "passphrase_source"])]
+    pub keyfile: Option<PathBuf>,
 }
 
 pub fn run(args: InspectArgs, _verbosity: Verbosity) -> Result<()> {
@@ -23,9 +51,25 @@ pub fn run(args: InspectArgs, _verbosity: Verbosity) -> Result<()> {
 
     // Try to extract and parse the embedded data
     let (data, method_used, capacity) = try_extract_with_info(&args.input)?;
-    let embedded = EmbeddedData::from_bytes(&data)?;
+    let integrity = check_integrity(&data);
+    let embedded = EmbeddedData::from_bytes(&data)
+        .map_err(|e| anyhow!("Embedded data is {}: {}", integrity, e))?;
     let flags = &embedded.header.flags;
 
+    // Loaded once, for both the key slot lookup and the attachment listing
+    let private_key = match args.key {
+        Some(ref key_path) if flags.key_slot_encryption => {
+            let key_path = resolve_identity(key_path)?;
+            Some(args.key_passphrase.load_private_key(&key_path)?)
+        }
+        _ => None,
+    };
+    let keyfile = match args.keyfile {
+        Some(ref path) if flags.key_slot_encryption => Some(Keyfile::load(path)?),
+        _ => None,
+    };
+    let passphrase = args.passphrase.resolve(Prompt::Never)?;
+
     println!("Zimhide Embedded Data");
     println!("=====================");
     println!();
@@ -46,6 +90,9 @@ pub fn run(args: InspectArgs, _verbosity: Verbosity) -> Result<()> {
     if flags.has_audio {
         content_parts.push("audio");
     }
+    if flags.has_files {
+        content_parts.push("files");
+    }
     if content_parts.is_empty() {
         println!("none");
     } else {
@@ -53,29 +100,102 @@ pub fn run(args: InspectArgs, _verbosity: Verbosity) -> Result<()> {
     }
 
     // Payload size
-    println!(
-        "Payload size: {} bytes{}",
-        embedded.header.payload_length,
-        if flags.symmetric_encryption || flags.asymmetric_encryption {
-            " (encrypted)"
-        } else {
-            ""
-        }
-    );
+    let mut qualifiers = Vec::new();
+    if flags.compressed {
+        qualifiers.push("compressed");
+    }
+    if flags.symmetric_encryption || flags.key_slot_encryption {
+        qualifiers.push("encrypted");
+    }
+    if qualifiers.is_empty() {
+        println!("Payload size: {} bytes", embedded.header.payload_length);
+    } else {
+        println!(
+            "Payload size: {} bytes ({})",
+            embedded.header.payload_length,
+            qualifiers.join(", ")
+        );
+    }
 
     // Encryption info
     print!("Encryption: ");
     if flags.symmetric_encryption {
-        println!("symmetric (passphrase)");
-    } else if flags.asymmetric_encryption {
-        if let Some(count) = recipient_count(&embedded.payload) {
-            println!(
-                "asymmetric ({} recipient{})",
-                count,
-                if count == 1 { "" } else { "s" }
-            );
+        let layout = SymmetricLayout::for_format_version(embedded.header.version);
+        match kdf_params(&embedded.payload, layout) {
+            Some(kdf) => println!("symmetric (passphrase, {kdf})"),
+            None => println!("symmetric (passphrase)"),
+        }
+    } else if flags.key_slot_encryption {
+        let layout = AsymmetricLayout::for_format_version(embedded.header.version);
+        let kdf = if layout.kek.is_legacy() {
+            "legacy key derivation"
+        } else {
+            "HKDF-SHA256"
+        };
+        // Padded envelopes hide the recipient count, so newer versions can
+        // only report key slots
+        let noun = match layout.slot_count {
+            SlotCount::Byte => "recipient",
+            SlotCount::Varint => "key slot",
+        };
+        if let Some(info) = envelope_info(&embedded.payload, layout) {
+            let threshold = info
+                .threshold
+                .map(|k| format!("any {k} recipients decrypt together, "))
+                .unwrap_or_default();
+            match info.slot_types {
+                Some(ref slot_types) => println!(
+                    "{} ({} key slot{}: {}, {}{})",
+                    if slot_types.contains(&SlotType::KeyAndPassphrase) {
+                        "two-factor"
+                    } else if slot_types.iter().all(|&t| t == SlotType::Keyfile) {
+                        "keyfile"
+                    } else {
+                        "mixed"
+                    },
+                    info.slots,
+                    if info.slots == 1 { "" } else { "s" },
+                    describe_slot_types(slot_types),
+                    if info.hints { "recipient hints, " } else { "" },
+                    kdf
+                ),
+                None => println!(
+                    "asymmetric ({} {}{}, {}{}{})",
+                    info.slots,
+                    noun,
+                    if info.slots == 1 { "" } else { "s" },
+                    threshold,
+                    if info.hints { "recipient hints, " } else { "" },
+                    kdf
+                ),
+            }
+            if private_key.is_some() || keyfile.is_some() {
+                match slot_credential(
+                    &info,
+                    private_key.as_ref(),
+                    keyfile.as_ref(),
+                    passphrase.as_deref().map(String::as_str),
+                ) {
+                    Some(credential) => match find_slot(&embedded.payload, credential, layout)? {
+                        Some(index) => println!(
+                            "Recipient: encrypted to you (slot {} of {})",
+                            index + 1,
+                            info.slots
+                        ),
+                        None if keyfile.is_some() => {
+                            println!("Recipient: not encrypted to this keyfile")
+                        }
+                        None => println!("Recipient: not encrypted to this key"),
+                    },
+                    None => println!("Recipient: unknown (add the passphrase to find your slot)"),
+                }
+            }
         } else {
-            println!("asymmetric");
+            println!("asymmetric ({kdf})");
+        }
+        // Only recipients can act on it, by re-encoding
+        if private_key.is_some() && layout.kek.is_legacy() {
+            println!("{}", legacy_kek_warning("message", embedded.header.version));
         }
     } else {
         println!("none");
@@ -85,11 +205,37 @@ pub fn run(args: InspectArgs, _verbosity: Verbosity) -> Result<()> {
     print!("Signed: ");
     if flags.is_signed {
         if let Some(sig) = &embedded.signature {
-            let fingerprint = sig.iter().take(6).fold(String::new(), |mut s, b| {
+            let fingerprint = sig.bytes.iter().take(6).fold(String::new(), |mut s, b| {
                 let _ = write!(s, "{b:02x}");
                 s
             });
-            println!("yes (sig: {fingerprint}...)");
+            println!(
+                "yes (covers {}; sig: {fingerprint}...)",
+                sig.scope.describe()
+            );
+            if let Some(ref id) = sig.signer {
+                match find_signer(id) {
+                    Ok(Some(signer)) => {
+                        let verdict = match check_signature(&embedded, &args.input, &signer.key) {
+                            Ok(_) => "good signature",
+                            Err(_) => "BAD signature",
+                        };
+                        let revoked = match find_revocation(&signer.key.key_id()) {
+                            Ok(Some(_)) => ", REVOKED",
+                            _ => "",
+                        };
+                        println!(
+                            "Signer: {} ({}, {}{})",
+                            signer.name,
+                            signer.trust_label(),
+                            verdict,
+                            revoked
+                        );
+                    }
+                    Ok(None) => println!("Signer: unknown key {}", format_key_id(id)),
+                    Err(e) => println!("Signer: unavailable ({e})"),
+                }
+            }
         } else {
             println!("yes");
         }
@@ -97,6 +243,41 @@ pub fn run(args: InspectArgs, _verbosity: Verbosity) -> Result<()> {
         println!("no");
     }
 
+    println!("Integrity: {integrity}");
+
+    // Attachments (only visible once the payload is readable)
+    if flags.has_files {
+        match readable_payload(
+            &embedded,
+            private_key.as_ref(),
+            keyfile.as_ref(),
+            passphrase.as_deref().map(String::as_str),
+        ) {
+            Ok(Some(payload)) => {
+                println!("Attachments: {}", payload.files.len());
+                for file in &payload.files {
+                    let modified = if file.modified > 0 {
+                        format_timestamp(file.modified)
+                    } else {
+                        "unknown".to_string()
+                    };
+                    println!(
+                        "  {} ({} bytes, {}, modified {}, mode {:o})",
+                        file.name,
+                        file.data.len(),
+                        file.mime_type,
+                        modified,
+                        file.mode
+                    );
+                }
+            }
+            Ok(None) => {
+                println!("Attachments: encrypted (use --passphrase, --key or --keyfile to list)")
+            }
+            Err(e) => println!("Attachments: unavailable ({e})"),
+        }
+    }
+
     // Capacity info
     let total_size = embedded.total_size();
     let capacity_used = (total_size as f64 / capacity as f64) * 100.0;
@@ -108,25 +289,87 @@ pub fn run(args: InspectArgs, _verbosity: Verbosity) -> Result<()> {
     Ok(())
 }
 
-fn try_extract_with_info(path: &Path) -> Result<(Vec<u8>, StegoMethodType, usize)> {
-    // Try metadata first
-    let metadata_stego = MetadataSteganography::new();
-    if let Ok(data) = metadata_stego.extract(path)
-        && data.len() >= 4
-        && &data[0..4] == b"ZIMH"
-    {
-        let capacity = metadata_stego.capacity(path)?;
-        return Ok((data, StegoMethodType::Metadata, capacity));
-    }
+/// Parse the payload if it is unencrypted or the caller supplied a way to decrypt it.
+fn readable_payload(
+    embedded: &EmbeddedData,
+    private_key: Option<&PrivateKey>,
+    keyfile: Option<&Keyfile>,
+    passphrase: Option<&str>,
+) -> Result<Option<Zeroizing<Payload>>> {
+    let flags = &embedded.header.flags;
+    let payload_bytes = if flags.symmetric_encryption {
+        let Some(passphrase) = passphrase else {
+            return Ok(None);
+        };
+        decrypt_symmetric(
+            &embedded.payload,
+            passphrase,
+            SymmetricLayout::for_format_version(embedded.header.version),
+        )?
+    } else if flags.key_slot_encryption {
+        let layout = AsymmetricLayout::for_format_version(embedded.header.version);
+        let Some(info) = envelope_info(&embedded.payload, layout) else {
+            return Ok(None);
+        };
+        // One key cannot open a threshold envelope
+        if info.threshold.is_some() {
+            return Ok(None);
+        }
+        let Some(credential) = slot_credential(&info, private_key, keyfile, passphrase) else {
+            return Ok(None);
+        };
+        decrypt_envelope(&embedded.payload, credential, layout)?
+    } else {
+        SecretBytes::from(embedded.payload.as_slice())
+    };
 
-    // Try LSB with default options
-    let lsb_stego = LsbSteganography::default();
-    let data = lsb_stego.extract(path)?;
+    Payload::decode(&payload_bytes, &embedded.header).map(Some)
+}
 
-    if data.len() >= 4 && &data[0..4] == b"ZIMH" {
-        let capacity = lsb_stego.capacity(path)?;
-        return Ok((data, StegoMethodType::Lsb, capacity));
+/// The credential the given key, keyfile and passphrase make for this envelope, if any.
+fn slot_credential<'a>(
+    info: &EnvelopeInfo,
+    private_key: Option<&'a PrivateKey>,
+    keyfile: Option<&'a Keyfile>,
+    passphrase: Option<&'a str>,
+) -> Option<Credential<'a>> {
+    if let Some(keyfile) = keyfile {
+        return Some(Credential::Keyfile(keyfile));
+    }
+    let two_factor = info
+        .slot_types
+        .as_ref()
+        .is_some_and(|types| types.contains(&SlotType::KeyAndPassphrase));
+    match (private_key, passphrase) {
+        (Some(private_key), Some(passphrase)) if two_factor => {
+            Some(Credential::KeyAndPassphrase(private_key, passphrase))
+        }
+        _ if two_factor => None,
+        (Some(private_key), _) => Some(Credential::PrivateKey(private_key)),
+        (None, Some(passphrase)) => Some(Credential::Passphrase(passphrase)),
+        (None, None) => None,
     }
+}
 
-    Err(anyhow!("No valid zimhide data found in file"))
+/// Slot counts by type, e.g. "2 public key, 1 passphrase".
+fn describe_slot_types(slot_types: &[SlotType]) -> String {
+    let mut counts: Vec<(SlotType, usize)> = Vec::new();
+    for &slot_type in slot_types {
+        match counts.iter_mut().find(|(seen, _)| *seen == slot_type) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((slot_type, 1)),
+        }
+    }
+    counts
+        .iter()
+        .map(|(slot_type, count)| format!("{count} {slot_type}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn try_extract_with_info(path: &Path) -> Result<(Vec<u8>, StegoMethodType, usize)> {
+    // Metadata first, then LSB with default options
+    locate(path, EmbedOptions::default())?
+        .map(|located| (located.data, located.method, located.capacity))
+        .ok_or_else(|| anyhow!("No valid zimhide data found in file"))
 }
@@ -0,0 +1,759 @@
+use crate::commands::keygen::protection_passphrase;
+use crate::crypto::keys::{format_key_id, unix_now, write_private_file};
+use crate::crypto::{
+    Certification, KdfProfile, KeyFormat, KeyShare, PrivateKey, PublicKey, Revocation,
+    RevocationReason,
+};
+use crate::keyring::{
+    EntryKind, Keyring, Trust, as_name, check_usable, identity_or_default, read_private_text,
+    resolve_identity, resolve_public_key, validate_name,
+};
+use crate::passphrase::KeyPassphraseArgs;
+use crate::time::format_timestamp;
+use crate::{Verbosity, status};
+use anyhow::{Result, anyhow};
+use clap::{Args, Subcommand};
+use std::fs;
+use std::io::{IsTerminal, Read};
+use std::path::PathBuf;
+use zeroize::Zeroizing;
+
+#[derive(Args)]
+pub struct KeysArgs {
+    #[command(subcommand)]
+    pub command: KeysCommand,
+}
+
+#[derive(Subcommand)]
+pub enum KeysCommand {
+    /// Add a contact (public key), identity (private key) or group to the keyring
+    Add(AddArgs),
+
+    /// List keyring entries with their fingerprints
+    List,
+
+    /// Remove a keyring entry
+    Remove(RemoveArgs),
+
+    /// Rename a keyring entry, updating groups that contain it
+    Rename(RenameArgs),
+
+    /// Print the public key of a contact or identity
+    Export(ExportArgs),
+
+    /// Show or set the identity used when --key is omitted
+    Default(DefaultArgs),
+
+    /// Set how far a contact is trusted as a signer
+    Trust(TrustArgs),
+
+    /// Show the safety number two people read to each other to confirm their keys
+    Compare(CompareArgs),
+
+    /// Make a revocation certificate for one of your keys, or import one
+    Revoke(RevokeArgs),
+
+    /// Vouch that a public key belongs to someone, or import someone else's certification
+    Certify(CertifyArgs),
+
+    /// Print a private key as words to write down
+    Backup(BackupArgs),
+
+    /// Rebuild a private key from its backup words
+    Restore(RestoreArgs),
+
+    /// Split a private key into shares, any threshold of which rebuild it
+    Split(SplitArgs),
+
+    /// Rebuild a private key from enough of its shares
+    Combine(CombineArgs),
+}
+
+#[derive(Args)]
+pub struct AddArgs {
+    /// Name to use in place of a key path
+    pub name: String,
+
+    /// Key file or ssh-ed25519/age1 key. Private key files become identities
+    #[arg(required_unless_present = "members")]
+    pub key: Option<PathBuf>,
+
+    /// Create a group of existing contacts and identities instead (repeatable)
+    #[arg(long = "member", value_name = "NAME", conflicts_with = "key")]
+    pub members: Vec<String>,
+
+    /// Make this identity the default (the first identity added always is)
+    #[arg(long, conflicts_with = "members")]
+    pub default: bool,
+
+    #[command(flatten)]
+    pub key_passphrase: KeyPassphraseArgs,
+}
+
+#[derive(Args)]
+pub struct RemoveArgs {
+    /// Entry to remove
+    pub name: String,
+}
+
+#[derive(Args)]
+pub struct RenameArgs {
+    /// Current name
+    pub old: String,
+
+    /// New name
+    pub new: String,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Contact or identity to export
+    pub name: String,
+
+    /// Key format
+    #[arg(long, value_enum, default_value_t)]
+    pub format: KeyFormat,
+}
+
+#[derive(Args)]
+pub struct DefaultArgs {
+    /// Identity to make the default; omit to show the current one
+    pub name: Option<String>,
+}
+
+#[derive(Args)]
+pub struct TrustArgs {
+    /// Contact to update
+    pub name: String,
+
+    /// Trust level. Decoding fails for signatures from untrusted keys; keys an
+    /// introducer certifies are trusted too
+    #[arg(value_enum)]
+    pub level: Trust,
+}
+
+#[derive(Args)]
+pub struct CompareArgs {
+    /// Your public key (zimhide or ssh-ed25519 file, ssh-ed25519/age1 key, or keyring name)
+    pub first: PathBuf,
+
+    /// Their public key, in any of the same forms
+    pub second: PathBuf,
+}
+
+#[derive(Args)]
+pub struct RevokeArgs {
+    /// Private key file or keyring identity to revoke
+    #[arg(required_unless_present = "import")]
+    pub key: Option<PathBuf>,
+
+    /// Why the key is revoked
+    #[arg(long, value_enum, default_value_t, conflicts_with = "import")]
+    pub reason: RevocationReason,
+
+    /// Write the certificate here instead of to standard output
+    #[arg(short, long, conflicts_with = "import")]
+    pub output: Option<PathBuf>,
+
+    /// Import a revocation certificate into the keyring instead
+    #[arg(long, value_name = "CERTIFICATE", conflicts_with = "key")]
+    pub import: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub key_passphrase: KeyPassphraseArgs,
+}
+
+#[derive(Args)]
+pub struct CertifyArgs {
+    /// Their public key (zimhide or ssh-ed25519 file, ssh-ed25519/age1 key, or keyring contact)
+    #[arg(required_unless_present = "import")]
+    pub subject: Option<PathBuf>,
+
+    /// Name to certify the key under [default: the contact's keyring name]
+    #[arg(long, conflicts_with = "import")]
+    pub name: Option<String>,
+
+    /// Private key file or keyring identity to certify with [default: the default identity]
+    #[arg(long, conflicts_with = "import")]
+    pub key: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub key_passphrase: KeyPassphraseArgs,
+
+    /// Also write the certification to this file, to pass on
+    #[arg(short, long, conflicts_with = "import")]
+    pub output: Option<PathBuf>,
+
+    /// Import a certification made by someone else instead
+    #[arg(long, value_name = "CERTIFICATION", conflicts_with = "subject")]
+    pub import: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct BackupArgs {
+    /// Private key file or keyring identity [default: the default identity]
+    pub key: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub key_passphrase: KeyPassphraseArgs,
+}
+
+#[derive(Args)]
+pub struct RestoreArgs {
+    /// Add the restored key to the keyring as this identity
+    #[arg(required_unless_present = "output")]
+    pub name: Option<String>,
+
+    /// Write <output>.priv and <output>.pub instead
+    #[arg(short, long, conflicts_with = "name")]
+    pub output: Option<PathBuf>,
+
+    /// Read the words from this file instead of standard input
+    #[arg(long, value_name = "FILE")]
+    pub words_file: Option<PathBuf>,
+
+    /// Protect the restored private key with a passphrase
+    #[arg(long)]
+    pub protect: bool,
+
+    #[command(flatten)]
+    pub key_passphrase: KeyPassphraseArgs,
+}
+
+#[derive(Args)]
+pub struct SplitArgs {
+    /// Private key file or keyring identity [default: the default identity]
+    pub key: Option<PathBuf>,
+
+    /// Number of shares needed to rebuild the key
+    #[arg(short = 'k', long, value_parser = clap::value_parser!(u8).range(2..))]
+    pub threshold: u8,
+
+    /// Number of shares to make
+    #[arg(short = 'n', long, value_parser = clap::value_parser!(u8).range(2..))]
+    pub shares: u8,
+
+    /// Write <output>-1.share to <output>-N.share [default: the key's name]
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub key_passphrase: KeyPassphraseArgs,
+}
+
+#[derive(Args)]
+pub struct CombineArgs {
+    /// Share files, at least as many as the split's threshold
+    #[arg(required = true)]
+    pub shares: Vec<PathBuf>,
+
+    /// Add the rebuilt key to the keyring as this identity
+    #[arg(long, required_unless_present = "output")]
+    pub name: Option<String>,
+
+    /// Write <output>.priv and <output>.pub instead
+    #[arg(short, long, conflicts_with = "name")]
+    pub output: Option<PathBuf>,
+
+    /// Protect the rebuilt private key with a passphrase
+    #[arg(long)]
+    pub protect: bool,
+
+    #[command(flatten)]
+    pub key_passphrase: KeyPassphraseArgs,
+}
+
+pub fn run(args: KeysArgs, verbosity: Verbosity) -> Result<()> {
+    match args.command {
+        KeysCommand::Add(args) => add(args, verbosity),
+        KeysCommand::List => list(verbosity),
+        KeysCommand::Remove(args) => {
+            let kind = Keyring::open()?.remove(&args.name)?;
+            status!(verbosity, "Removed {} {}", kind, args.name);
+            Ok(())
+        }
+        KeysCommand::Rename(args) => {
+            let kind = Keyring::open()?.rename(&args.old, &args.new)?;
+            status!(verbosity, "Renamed {} {} to {}", kind, args.old, args.new);
+            Ok(())
+        }
+        KeysCommand::Export(args) => {
+            let key = Keyring::open()?.public_key(&args.name)?;
+            print!("{}", key.encode(args.format)?);
+            Ok(())
+        }
+        KeysCommand::Default(args) => default(args, verbosity),
+        KeysCommand::Trust(args) => {
+            Keyring::open()?.set_trust(&args.name, args.level)?;
+            status!(verbosity, "{} is now {}", args.name, args.level);
+            Ok(())
+        }
+        KeysCommand::Compare(args) => compare(args, verbosity),
+        KeysCommand::Revoke(args) => revoke(args, verbosity),
+        KeysCommand::Certify(args) => certify(args, verbosity),
+        KeysCommand::Backup(args) => backup(args, verbosity),
+        KeysCommand::Restore(args) => restore(args, verbosity),
+        KeysCommand::Split(args) => split(args, verbosity),
+        KeysCommand::Combine(args) => combine(args, verbosity),
+    }
+}
+
+fn add(args: AddArgs, verbosity: Verbosity) -> Result<()> {
+    let keyring = Keyring::open()?;
+
+    if !args.members.is_empty() {
+        keyring.add_group(&args.name, &args.members)?;
+        status!(
+            verbosity,
+            "Added group {} ({})",
+            args.name,
+            args.members.join(", ")
+        );
+        return Ok(());
+    }
+
+    let key_path = args
+        .key
+        .as_ref()
+        .ok_or_else(|| anyhow!("Give a key file, or --member to create a group"))?;
+    if let Some(private_text) = read_private_text(key_path)? {
+        // Loading checks the key and yields its public half
+        let private_key = args.key_passphrase.load_private_key(key_path)?;
+        let public_key = private_key.public_key();
+        keyring.add_identity(&args.name, &private_text, &public_key)?;
+        let is_default = args.default || keyring.default_identity()?.is_none();
+        if is_default {
+            keyring.set_default(&args.name)?;
+        }
+        status!(
+            verbosity,
+            "Added identity {} ({}){}",
+            args.name,
+            public_key.fingerprint(),
+            if is_default { " as the default" } else { "" }
+        );
+    } else {
+        if args.default {
+            return Err(anyhow!("--default needs a private key"));
+        }
+        let public_key = PublicKey::load(key_path)?;
+        keyring.add_contact(&args.name, &public_key)?;
+        status!(
+            verbosity,
+            "Added contact {} ({})",
+            args.name,
+            public_key.fingerprint()
+        );
+    }
+    status!(verbosity, "Keyring: {}", keyring.dir().display());
+
+    Ok(())
+}
+
+fn list(verbosity: Verbosity) -> Result<()> {
+    let keyring = Keyring::open()?;
+    let default = keyring.default_identity()?;
+    let sections = [
+        ("Identities", EntryKind::Identity),
+        ("Contacts", EntryKind::Contact),
+        ("Groups", EntryKind::Group),
+    ];
+
+    let mut empty = true;
+    for (title, kind) in sections {
+        let names = keyring.names(kind)?;
+        if names.is_empty() {
+            continue;
+        }
+        empty = false;
+        let width = names.iter().map(String::len).max().unwrap_or(0);
+        println!("{title}:");
+        for name in &names {
+            let detail = match kind {
+                EntryKind::Group => keyring.members(name)?.join(", "),
+                _ => match keyring.public_key(name) {
+                    Ok(key) => {
+                        let mut detail = key.fingerprint();
+                        if kind == EntryKind::Contact
+                            && let Some(signer) = keyring.find_key_id(&key.key_id())?
+                        {
+                            detail = format!("{detail}  {}", signer.trust_label());
+                        }
+                        if keyring.revocation(&key.key_id())?.is_some() {
+                            detail.push_str("  REVOKED");
+                        } else if let Some(expires) = key.expires() {
+                            let state = if key.is_expired(unix_now()) {
+                                "expired"
+                            } else {
+                                "expires"
+                            };
+                            detail = format!("{detail}  {state} {}", format_timestamp(expires));
+                        }
+                        detail
+                    }
+                    Err(e) => format!("unreadable: {e}"),
+                },
+            };
+            let marker = if default.as_deref() == Some(name.as_str()) {
+                " (default)"
+            } else {
+                ""
+            };
+            println!("  {name:width$}  {detail}{marker}");
+        }
+    }
+
+    if empty {
+        status!(
+            verbosity,
+            "The keyring is empty. Add keys with 'zimhide keys add NAME KEY'."
+        );
+    }
+
+    Ok(())
+}
+
+fn default(args: DefaultArgs, verbosity: Verbosity) -> Result<()> {
+    let keyring = Keyring::open()?;
+    match args.name {
+        Some(name) => {
+            keyring.set_default(&name)?;
+            status!(verbosity, "Default identity: {}", name);
+        }
+        None => match keyring.default_identity()? {
+            Some(name) => println!("{name}"),
+            None => status!(verbosity, "No default identity is set"),
+        },
+    }
+    Ok(())
+}
+
+fn compare(args: CompareArgs, verbosity: Verbosity) -> Result<()> {
+    let first = resolve_public_key(&args.first)?;
+    let second = resolve_public_key(&args.second)?;
+
+    let names = [
+        args.first.display().to_string(),
+        args.second.display().to_string(),
+    ];
+    let width = names.iter().map(String::len).max().unwrap_or(0);
+    for (name, key) in names.iter().zip([&first, &second]) {
+        println!("{:width$}  {}", name, key.fingerprint());
+    }
+    println!();
+
+    // Twelve 5-digit groups, four per line; the same whichever key is first
+    println!("Safety number:");
+    let number = first.safety_number(&second);
+    for line in number.as_bytes().chunks(20) {
+        let groups: Vec<&str> = line
+            .chunks(5)
+            .map(|group| std::str::from_utf8(group).expect("safety numbers are ASCII digits"))
+            .collect();
+        println!("  {}", groups.join(" "));
+    }
+
+    status!(
+        verbosity,
+        "\nRead the safety number to each other over a channel you trust. If it matches, each of you holds the other's real key."
+    );
+
+    Ok(())
+}
+
+fn revoke(args: RevokeArgs, verbosity: Verbosity) -> Result<()> {
+    if let Some(ref path) = args.import {
+        let revocation = Revocation::load(path)?;
+        let keyring = Keyring::open()?;
+        keyring.add_revocation(&revocation)?;
+        let label = match keyring.find_key_id(&revocation.key_id())? {
+            Some(signer) => signer.name,
+            None => revocation.key.fingerprint(),
+        };
+        status!(
+            verbosity,
+            "Imported revocation of {} (revoked {}, {})",
+            label,
+            format_timestamp(revocation.revoked),
+            revocation.reason
+        );
+        return Ok(());
+    }
+
+    let key_path = resolve_identity(args.key.as_deref().expect("required without --import"))?;
+    let private_key = args.key_passphrase.load_private_key(&key_path)?;
+    let revocation = Revocation::create(&private_key, args.reason)?;
+    match args.output {
+        Some(ref path) => {
+            revocation.save(path)?;
+            status!(
+                verbosity,
+                "Wrote revocation certificate for {} to {}",
+                revocation.key.fingerprint(),
+                path.display()
+            );
+        }
+        None => print!("{}", revocation.to_pem()),
+    }
+    status!(
+        verbosity,
+        "Keep it safe until needed: anyone holding it can revoke the key. Publish it with 'zimhide keys revoke --import' or 'zimhide encode --revocation'."
+    );
+
+    Ok(())
+}
+
+fn certify(args: CertifyArgs, verbosity: Verbosity) -> Result<()> {
+    let keyring = Keyring::open()?;
+
+    if let Some(ref path) = args.import {
+        let certification = Certification::load(path)?;
+        let certifier = keyring.find_key_id(&certification.certifier)?;
+        if let Some(ref certifier) = certifier {
+            certification.verify(&certifier.key)?;
+        }
+        keyring.add_certification(&certification)?;
+        match certifier {
+            Some(certifier) => status!(
+                verbosity,
+                "Imported {}'s certification of {} ({})",
+                certifier.name,
+                certification.name,
+                certification.subject.fingerprint()
+            ),
+            None => status!(
+                verbosity,
+                "Imported a certification of {} ({}) by unknown key {}. It counts once that key is in the keyring as an introducer.",
+                certification.name,
+                certification.subject.fingerprint(),
+                format_key_id(&certification.certifier)
+            ),
+        }
+        return Ok(());
+    }
+
+    let subject_arg = args.subject.as_deref().expect("required without --import");
+    let subject = resolve_public_key(subject_arg)?;
+    let name = match args.name {
+        Some(name) => name,
+        None => as_name(subject_arg)
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("Give --name for a key that is not in the keyring"))?,
+    };
+    validate_name(&name)?;
+
+    let key_path = identity_or_default(args.key.as_deref())?.ok_or_else(|| {
+        anyhow!("Certifying needs --key, or a default identity (see 'zimhide keys add')")
+    })?;
+    let private_key = args.key_passphrase.load_private_key(&key_path)?;
+    check_usable(&private_key.public_key(), "Certifying key")?;
+
+    let certification = Certification::create(&subject, &name, &private_key)?;
+    keyring.add_certification(&certification)?;
+    if let Some(ref path) = args.output {
+        certification.save(path)?;
+    }
+    status!(
+        verbosity,
+        "Certified {} as {}{}",
+        subject.fingerprint(),
+        name,
+        args.output
+            .as_ref()
+            .map(|path| format!(" (written to {})", path.display()))
+            .unwrap_or_default()
+    );
+
+    Ok(())
+}
+
+/// Words per line when printing a backup.
+const BACKUP_WORDS_PER_LINE: usize = 6;
+
+fn backup(args: BackupArgs, verbosity: Verbosity) -> Result<()> {
+    let key_path = identity_or_default(args.key.as_deref())?
+        .ok_or_else(|| anyhow!("Give a private key, or set a default identity"))?;
+    let private_key = args.key_passphrase.load_private_key(&key_path)?;
+    let phrase = private_key.to_mnemonic()?;
+
+    // Numbered, so a missing or swapped word is easy to spot
+    let words: Vec<&str> = phrase.split(' ').collect();
+    for (line, chunk) in words.chunks(BACKUP_WORDS_PER_LINE).enumerate() {
+        let numbered: Vec<String> = chunk
+            .iter()
+            .enumerate()
+            .map(|(i, word)| format!("{:>2}. {:<8}", line * BACKUP_WORDS_PER_LINE + i + 1, word))
+            .collect();
+        println!("{}", numbered.join("  ").trim_end());
+    }
+
+    status!(
+        verbosity,
+        "\n{} words for {}. Write them down in order and keep them offline: anyone who has them holds the key. Restore with 'zimhide keys restore'.",
+        words.len(),
+        private_key.public_key().fingerprint()
+    );
+
+    Ok(())
+}
+
+fn restore(args: RestoreArgs, verbosity: Verbosity) -> Result<()> {
+    let text = Zeroizing::new(match args.words_file {
+        Some(ref path) => fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?,
+        None => {
+            if std::io::stdin().is_terminal() {
+                status!(
+                    verbosity,
+                    "Enter the backup words, then press Ctrl-D on an empty line:"
+                );
+            }
+            let mut text = String::new();
+            std::io::stdin().read_to_string(&mut text)?;
+            text
+        }
+    });
+
+    // Accept the numbered layout `keys backup` prints
+    let phrase = Zeroizing::new(
+        text.split_whitespace()
+            .filter(|token| {
+                !token
+                    .trim_end_matches('.')
+                    .bytes()
+                    .all(|b| b.is_ascii_digit())
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+    );
+    let private_key = PrivateKey::from_mnemonic(&phrase)?;
+    let rebuilt = RebuiltKey {
+        name: args.name,
+        output: args.output,
+        protect: args.protect,
+        key_passphrase: &args.key_passphrase,
+    };
+    rebuilt.save(&private_key, "Restored", verbosity)
+}
+
+fn split(args: SplitArgs, verbosity: Verbosity) -> Result<()> {
+    let key_path = identity_or_default(args.key.as_deref())?
+        .ok_or_else(|| anyhow!("Give a private key, or set a default identity"))?;
+    let private_key = args.key_passphrase.load_private_key(&key_path)?;
+    let shares = KeyShare::split(&private_key, args.threshold, args.shares)?;
+
+    let base = match args.output {
+        Some(base) => base,
+        None => PathBuf::from(key_path.file_stem().unwrap_or("key".as_ref())),
+    };
+    let paths: Vec<PathBuf> = shares
+        .iter()
+        .map(|share| {
+            let mut path = base.clone().into_os_string();
+            path.push(format!("-{}.share", share.index));
+            PathBuf::from(path)
+        })
+        .collect();
+    if let Some(path) = paths.iter().find(|path| path.exists()) {
+        return Err(anyhow!(
+            "{} already exists; choose another --output",
+            path.display()
+        ));
+    }
+    for (share, path) in shares.iter().zip(&paths) {
+        write_private_file(path, &share.to_pem())?;
+    }
+
+    status!(
+        verbosity,
+        "Split {} into {} shares, any {} of which rebuild it:",
+        private_key.public_key().fingerprint(),
+        args.shares,
+        args.threshold
+    );
+    for path in &paths {
+        status!(verbosity, "  {}", path.display());
+    }
+    status!(
+        verbosity,
+        "Give each share to a different person, then remove the original private key. Rebuild it with 'zimhide keys combine'."
+    );
+
+    Ok(())
+}
+
+fn combine(args: CombineArgs, verbosity: Verbosity) -> Result<()> {
+    let shares = args
+        .shares
+        .iter()
+        .map(|path| KeyShare::load(path))
+        .collect::<Result<Vec<_>>>()?;
+    let private_key = KeyShare::combine(&shares)?;
+    let rebuilt = RebuiltKey {
+        name: args.name,
+        output: args.output,
+        protect: args.protect,
+        key_passphrase: &args.key_passphrase,
+    };
+    rebuilt.save(&private_key, "Rebuilt", verbosity)
+}
+
+/// Where `restore` and `combine` put the key they rebuild.
+struct RebuiltKey<'a> {
+    name: Option<String>,
+    output: Option<PathBuf>,
+    protect: bool,
+    key_passphrase: &'a KeyPassphraseArgs,
+}
+
+impl RebuiltKey<'_> {
+    fn save(self, private_key: &PrivateKey, verb: &str, verbosity: Verbosity) -> Result<()> {
+        let public_key = private_key.public_key();
+
+        let label = match (&self.name, &self.output) {
+            (_, Some(base)) => base.with_extension("priv"),
+            (Some(name), None) => PathBuf::from(name),
+            (None, None) => unreachable!("clap requires a name or --output"),
+        };
+        let private_text = if self.protect || self.key_passphrase.is_given() {
+            let passphrase = protection_passphrase(self.key_passphrase, &label)?;
+            Zeroizing::new(
+                private_key.to_encrypted_pem(&passphrase, &KdfProfile::default().params())?,
+            )
+        } else {
+            private_key.to_pem()?
+        };
+
+        match (self.name, self.output) {
+            (_, Some(base)) => {
+                let priv_path = base.with_extension("priv");
+                let pub_path = base.with_extension("pub");
+                for path in [&priv_path, &pub_path] {
+                    if path.exists() {
+                        return Err(anyhow!(
+                            "{} already exists; write to another path",
+                            path.display()
+                        ));
+                    }
+                }
+                write_private_file(&priv_path, &private_text)?;
+                public_key.save(&pub_path)?;
+                status!(verbosity, "{} keypair:", verb);
+                status!(verbosity, "  Public key:  {}", pub_path.display());
+                status!(verbosity, "  Private key: {}", priv_path.display());
+            }
+            (Some(name), None) => {
+                let keyring = Keyring::open()?;
+                keyring.add_identity(&name, &private_text, &public_key)?;
+                if keyring.default_identity()?.is_none() {
+                    keyring.set_default(&name)?;
+                }
+                status!(verbosity, "{} identity {}", verb, name);
+            }
+            (None, None) => unreachable!("clap requires a name or --output"),
+        }
+        status!(verbosity, "  Fingerprint: {}", public_key.fingerprint());
+
+        Ok(())
+    }
+}
@@ -1,6 +1,9 @@
+pub mod calibrate;
 pub mod completions;
 pub mod decode;
 pub mod encode;
 pub mod inspect;
 pub mod keygen;
+pub mod keys;
+pub mod passwd;
 pub mod play;
@@ -0,0 +1,63 @@
+use crate::crypto::{KdfProfile, PrivateKey};
+use crate::keyring::resolve_identity;
+use crate::passphrase::{KeyPassphraseArgs, new_key_passphrase};
+use crate::{Verbosity, status};
+use anyhow::{Context, Result, anyhow};
+use clap::Args;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct PasswdArgs {
+    /// Private key file or keyring identity to update
+    pub key: PathBuf,
+
+    /// Remove passphrase protection, storing the key unencrypted
+    #[arg(long)]
+    pub remove: bool,
+
+    /// Argon2id cost profile for the new passphrase [default: interactive]
+    #[arg(long, value_enum, conflicts_with = "remove")]
+    pub kdf_profile: Option<KdfProfile>,
+
+    /// Current passphrase, if the key is already protected
+    #[command(flatten)]
+    pub key_passphrase: KeyPassphraseArgs,
+
+    /// Read the new passphrase from the first line of a file
+    #[arg(long, value_name = "PATH", conflicts_with = "remove")]
+    pub new_passphrase_file: Option<PathBuf>,
+}
+
+pub fn run(args: PasswdArgs, verbosity: Verbosity) -> Result<()> {
+    let key = resolve_identity(&args.key)?;
+    if !key.exists() {
+        return Err(anyhow!("Key file does not exist: {}", key.display()));
+    }
+
+    let was_protected = PrivateKey::is_protected(&key)?;
+    let private_key = args.key_passphrase.load_private_key(&key)?;
+
+    // Write beside the original and rename, so a failure never loses the key
+    let tmp_path = key.with_extension("priv.tmp");
+    if args.remove {
+        if !was_protected {
+            return Err(anyhow!("{} is not passphrase-protected", key.display()));
+        }
+        private_key.save(&tmp_path)?;
+    } else {
+        let passphrase = new_key_passphrase(args.new_passphrase_file.as_deref(), &key)?;
+        let kdf = args.kdf_profile.unwrap_or_default().params();
+        private_key.save_encrypted(&tmp_path, &passphrase, &kdf)?;
+    }
+    fs::rename(&tmp_path, &key).with_context(|| format!("Failed to replace {}", key.display()))?;
+
+    let action = match (args.remove, was_protected) {
+        (true, _) => "Removed passphrase from",
+        (false, true) => "Changed passphrase of",
+        (false, false) => "Added passphrase to",
+    };
+    status!(verbosity, "{} {}", action, key.display());
+
+    Ok(())
+}
@@ -1,7 +1,10 @@
-use crate::crypto::{PrivateKey, decrypt_asymmetric, decrypt_symmetric};
-use crate::format::{EmbeddedData, Payload};
+use super::decode::{decrypt_symmetric_payload, legacy_kek_warning, unlock_envelope};
+use crate::attachment::write_atomically;
+use crate::crypto::{AsymmetricLayout, SecretBytes, decrypt_envelope};
+use crate::format::{EmbeddedData, Payload, check_integrity};
+use crate::passphrase::{KeyPassphraseArgs, PassphraseArgs};
+use crate::stego::locate;
 use crate::stego::traits::{ChannelMode, EmbedOptions};
-use crate::stego::{LsbSteganography, MetadataSteganography, StegoMethod};
 use crate::{Verbosity, status, verbose};
 use anyhow::{Result, anyhow};
 use clap::Args;
@@ -13,14 +16,22 @@ pub struct PlayArgs {
     /// Input WAV file with embedded audio
     pub input: PathBuf,
 
-    /// Passphrase for symmetric decryption
-    #[arg(long, conflicts_with = "key")]
-    pub passphrase: Option<String>,
+    #[command(flatten)]
+    pub passphrase: PassphraseArgs,
 
-    /// Private key file for asymmetric decryption
-    #[arg(long, conflicts_with = "passphrase")]
+    /// Private key file (zimhide, OpenSSH or age) or keyring identity for asymmetric
+    /// decryption [default: the default identity]. Two-factor messages also need the
+    /// passphrase
+    #[arg(long)]
     pub key: Option<PathBuf>,
 
+    #[command(flatten)]
+    pub key_passphrase: KeyPassphraseArgs,
+
+    /// Keyfile that opens a key slot of the audio's message
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["key", "passphrase_source"])]
+    pub keyfile: Option<PathBuf>,
+
     /// Extract to file instead of playing
     #[arg(long = "extract-to")]
     pub extract_to: Option<PathBuf>,
@@ -48,6 +59,16 @@ pub fn run(args: PlayArgs, verbosity: Verbosity) -> Result<()> {
 
     // Extract embedded data
     let data = try_extract(&args)?;
+
+    let integrity = check_integrity(&data);
+    if integrity.is_corrupted() {
+        return Err(anyhow!(
+            "Embedded data is {}. The carrier was probably modified after encoding.",
+            integrity
+        ));
+    }
+    verbose!(verbosity, "Integrity: {}", integrity);
+
     let embedded = EmbeddedData::from_bytes(&data)?;
     let flags = &embedded.header.flags;
 
@@ -64,37 +85,54 @@ pub fn run(args: PlayArgs, verbosity: Verbosity) -> Result<()> {
 
     // Decrypt payload
     let payload_bytes = if flags.symmetric_encryption {
-        let passphrase = args
-            .passphrase
-            .as_ref()
-            .ok_or_else(|| anyhow!("Audio is encrypted. Use --passphrase to decrypt."))?;
-        decrypt_symmetric(&embedded.payload, passphrase)?
-    } else if flags.asymmetric_encryption {
-        let key_path = args
-            .key
-            .as_ref()
-            .ok_or_else(|| anyhow!("Audio is encrypted. Use --key to decrypt."))?;
-        let private_key = PrivateKey::load(key_path)?;
-        decrypt_asymmetric(&embedded.payload, &private_key)?
+        decrypt_symmetric_payload(
+            &embedded,
+            &args.passphrase,
+            args.keyfile.as_deref(),
+            "Audio",
+        )?
+    } else if flags.key_slot_encryption {
+        let layout = AsymmetricLayout::for_format_version(embedded.header.version);
+        if layout.kek.is_legacy() {
+            status!(
+                verbosity,
+                "{}",
+                legacy_kek_warning("audio", embedded.header.version)
+            );
+        }
+        let unlock = unlock_envelope(
+            &embedded.payload,
+            layout,
+            &args.passphrase,
+            args.keyfile.as_deref(),
+            args.key.as_deref(),
+            &args.key_passphrase,
+            "Audio",
+        )?;
+        decrypt_envelope(&embedded.payload, unlock.credential(), layout)?
     } else {
-        embedded.payload.clone()
+        SecretBytes::from(embedded.payload.as_slice())
     };
 
     // Parse payload
-    let payload = Payload::from_bytes(&payload_bytes)?;
+    let payload = Payload::decode(&payload_bytes, &embedded.header)?;
     let audio_data = payload
         .audio
+        .as_deref()
         .ok_or_else(|| anyhow!("No audio content found in payload"))?;
 
     // Output to file or play
     if let Some(ref output_path) = args.extract_to {
-        crate::audio::decompress_audio(&audio_data, output_path, verbosity)?;
+        // Overwrites as before, but never leaves a partial file behind
+        write_atomically(output_path, true, |temp| {
+            crate::audio::decompress_audio(audio_data, temp.path(), verbosity)
+        })?;
         status!(verbosity, "Extracted audio to: {}", output_path.display());
     } else {
         // Create temp file and play
         let temp_dir = tempfile::tempdir()?;
         let temp_path = temp_dir.path().join("extracted.wav");
-        crate::audio::decompress_audio(&audio_data, &temp_path, verbosity)?;
+        crate::audio::decompress_audio(audio_data, &temp_path, verbosity)?;
 
         // Find and run player
         let player = find_player(&args.player)?;
@@ -111,28 +149,14 @@ pub fn run(args: PlayArgs, verbosity: Verbosity) -> Result<()> {
 }
 
 fn try_extract(args: &PlayArgs) -> Result<Vec<u8>> {
-    // Try metadata first
-    let metadata_stego = MetadataSteganography::new();
-    if let Ok(data) = metadata_stego.extract(&args.input)
-        && data.len() >= 4
-        && &data[0..4] == b"ZIMH"
-    {
-        return Ok(data);
-    }
-
-    // Try LSB
     let options = EmbedOptions {
         bits_per_sample: args.bits,
         channels: args.channels,
     };
-    let lsb_stego = LsbSteganography::new(options);
-    let data = lsb_stego.extract(&args.input)?;
-
-    if data.len() >= 4 && &data[0..4] == b"ZIMH" {
-        return Ok(data);
-    }
 
-    Err(anyhow!("No valid zimhide data found in file"))
+    locate(&args.input, options)?
+        .map(|located| located.data)
+        .ok_or_else(|| anyhow!("No valid zimhide data found in file"))
 }
 
 fn find_player(preferred: &str) -> Result<String> {
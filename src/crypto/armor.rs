@@ -0,0 +1,70 @@
+//! The PEM-like armor of zimhide key and certificate files: base64 between
+//! `-----BEGIN ZIMHIDE <LABEL>-----` and `-----END ZIMHIDE <LABEL>-----`.
+
+use super::secret::Zeroizing;
+use anyhow::{Context, Result, anyhow};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+
+pub(crate) fn header(label: &str) -> String {
+    format!("-----BEGIN ZIMHIDE {label}-----")
+}
+
+fn footer(label: &str) -> String {
+    format!("-----END ZIMHIDE {label}-----")
+}
+
+/// `bytes` armored with `label`. Zeroized, since some labels hold secrets.
+pub(crate) fn armor(label: &str, bytes: &[u8]) -> Zeroizing<String> {
+    let encoded = Zeroizing::new(BASE64.encode(bytes));
+    Zeroizing::new(format!(
+        "{}\n{}\n{}\n",
+        header(label),
+        *encoded,
+        footer(label)
+    ))
+}
+
+/// Whether `content` is armored with `label`.
+pub(crate) fn is_armored(content: &str, label: &str) -> bool {
+    body(content, label).is_some()
+}
+
+/// The decoded bytes inside `label` armor.
+pub(crate) fn dearmor(content: &str, label: &str) -> Result<Zeroizing<Vec<u8>>> {
+    let what = label.to_lowercase();
+    let encoded = body(content, label).ok_or_else(|| anyhow!("Not a zimhide {}", what))?;
+    BASE64
+        .decode(encoded)
+        .map(Zeroizing::new)
+        .with_context(|| format!("Invalid base64 in zimhide {}", what))
+}
+
+fn body<'a>(content: &'a str, label: &str) -> Option<&'a str> {
+    content
+        .trim()
+        .strip_prefix(&header(label))?
+        .strip_suffix(&footer(label))
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_armor_roundtrip() {
+        let armored = armor("KEY SHARE", b"share bytes");
+        assert!(armored.starts_with("-----BEGIN ZIMHIDE KEY SHARE-----\n"));
+        assert!(is_armored(&format!("\n{}\n", *armored), "KEY SHARE"));
+        assert_eq!(
+            dearmor(&armored, "KEY SHARE").unwrap().as_slice(),
+            b"share bytes"
+        );
+
+        let err = dearmor(&armored, "REVOCATION").unwrap_err();
+        assert_eq!(err.to_string(), "Not a zimhide revocation");
+        let damaged = armored.replace("c2hh", "c2h!");
+        let err = dearmor(&damaged, "KEY SHARE").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid base64 in zimhide key share");
+    }
+}
@@ -1,155 +1,1274 @@
+use super::keyfile::Keyfile;
 use super::keys::{PrivateKey, PublicKey};
+use super::secret::{SecretBytes, SecretKey, Zeroizing, open};
+use super::shamir::{combine_secret, split_secret};
+use super::symmetric::{KdfParams, PARAMS_SIZE};
+use super::threshold::{PartialDecryption, message_id};
+use crate::format::varint;
 use anyhow::{Result, anyhow};
 use chacha20poly1305::{
     XChaCha20Poly1305, XNonce,
     aead::{Aead, KeyInit},
 };
+use hkdf::Hkdf;
 use rand::RngCore;
-use x25519_dalek::{PublicKey as X25519Public, StaticSecret as X25519Secret};
+use rand::seq::SliceRandom;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use x25519_dalek::{PublicKey as X25519Public, SharedSecret, StaticSecret as X25519Secret};
 
 const XNONCE_SIZE: usize = 24;
 const KEY_SIZE: usize = 32;
 const EPHEMERAL_PUBLIC_SIZE: usize = 32;
 const WRAPPED_KEY_SIZE: usize = KEY_SIZE + 16; // Key + auth tag
+const SLOT_SIZE: usize = EPHEMERAL_PUBLIC_SIZE + XNONCE_SIZE + WRAPPED_KEY_SIZE;
+const SLOT_SALT_SIZE: usize = 16;
+/// Argon2id parameters, salt, nonce and wrapped key.
+const PASSPHRASE_SLOT_SIZE: usize = PARAMS_SIZE + SLOT_SALT_SIZE + XNONCE_SIZE + WRAPPED_KEY_SIZE;
+/// Argon2id parameters and salt ahead of a public-key slot.
+const SECOND_FACTOR_SIZE: usize = PARAMS_SIZE + SLOT_SALT_SIZE;
+/// Salt, nonce and wrapped key.
+const KEYFILE_SLOT_SIZE: usize = SLOT_SALT_SIZE + XNONCE_SIZE + WRAPPED_KEY_SIZE;
 
-pub fn encrypt_asymmetric(plaintext: &[u8], recipients: &[PublicKey]) -> Result<Vec<u8>> {
+/// First container format version whose key wrapping uses HKDF.
+pub const HKDF_FORMAT_VERSION: u8 = 5;
+
+/// First container format version that stores the key slot count as a
+/// varint, lifting the 255-recipient limit.
+pub const VARINT_SLOTS_FORMAT_VERSION: u8 = 7;
+
+/// First container format version whose envelopes start with a flags byte,
+/// which can announce recipient hints.
+pub const ENVELOPE_FLAGS_FORMAT_VERSION: u8 = 8;
+
+/// Smallest key slot block when recipients are hidden.
+pub const MIN_HIDDEN_SLOTS: usize = 8;
+
+/// Size of the recipient hint that prefixes each key slot when enabled.
+pub const HINT_SIZE: usize = 4;
+
+/// Envelope flag: every key slot starts with a recipient hint.
+const FLAG_HINTS: u8 = 1 << 0;
+/// Envelope flag: a threshold byte follows, and each key slot wraps a share
+/// of the content key rather than the key itself.
+const FLAG_THRESHOLD: u8 = 1 << 1;
+/// Envelope flag: every key slot starts with its type and length, so
+/// passphrase and keyfile slots can sit beside public-key ones.
+const FLAG_SLOT_TYPES: u8 = 1 << 2;
+
+const HKDF_INFO: &[u8] = b"zimhide x25519 key wrap v1";
+const KEYFILE_HKDF_INFO: &[u8] = b"zimhide keyfile key wrap v1";
+const TWO_FACTOR_HKDF_INFO: &[u8] = b"zimhide two-factor key wrap v1";
+const HINT_DOMAIN: &[u8] = b"zimhide recipient hint v1";
+
+/// How the key-encryption key is derived from the X25519 shared secret.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KekDerivation {
+    /// `DefaultHasher` rounds (format versions 1-4). Decryption only: the
+    /// algorithm is unspecified across Rust releases.
+    Legacy,
+    /// HKDF-SHA256 over the shared secret, bound to both public keys
+    HkdfSha256,
+}
+
+impl KekDerivation {
+    pub fn for_format_version(version: u8) -> Self {
+        if version >= HKDF_FORMAT_VERSION {
+            Self::HkdfSha256
+        } else {
+            Self::Legacy
+        }
+    }
+
+    pub fn is_legacy(self) -> bool {
+        self == Self::Legacy
+    }
+
+    fn derive(
+        self,
+        shared_secret: &[u8; 32],
+        ephemeral_public: &X25519Public,
+        recipient_public: &X25519Public,
+    ) -> SecretKey {
+        match self {
+            Self::Legacy => derive_legacy_key_encryption_key(shared_secret),
+            Self::HkdfSha256 => {
+                derive_key_encryption_key(shared_secret, ephemeral_public, recipient_public)
+            }
+        }
+    }
+}
+
+/// How the key slot count is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotCount {
+    /// One byte (format versions 1-6)
+    Byte,
+    /// LEB128 varint
+    Varint,
+}
+
+/// Layout of an asymmetric envelope, selected by the container format version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AsymmetricLayout {
+    pub kek: KekDerivation,
+    pub slot_count: SlotCount,
+    /// Whether the envelope starts with a flags byte
+    pub flags: bool,
+}
+
+impl AsymmetricLayout {
+    /// The layout new envelopes are written with.
+    pub const CURRENT: Self = Self {
+        kek: KekDerivation::HkdfSha256,
+        slot_count: SlotCount::Varint,
+        flags: true,
+    };
+
+    pub fn for_format_version(version: u8) -> Self {
+        Self {
+            kek: KekDerivation::for_format_version(version),
+            slot_count: if version >= VARINT_SLOTS_FORMAT_VERSION {
+                SlotCount::Varint
+            } else {
+                SlotCount::Byte
+            },
+            flags: version >= ENVELOPE_FLAGS_FORMAT_VERSION,
+        }
+    }
+}
+
+/// What opens a key slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotType {
+    PublicKey,
+    Passphrase,
+    Keyfile,
+    /// Opens only with the private key and the passphrase together
+    KeyAndPassphrase,
+    /// Written by a newer version; skipped
+    Unknown(u8),
+}
+
+impl SlotType {
+    fn id(self) -> u8 {
+        match self {
+            Self::PublicKey => 0,
+            Self::Passphrase => 1,
+            Self::Keyfile => 2,
+            Self::KeyAndPassphrase => 3,
+            Self::Unknown(id) => id,
+        }
+    }
+
+    fn from_id(id: u8) -> Self {
+        match id {
+            0 => Self::PublicKey,
+            1 => Self::Passphrase,
+            2 => Self::Keyfile,
+            3 => Self::KeyAndPassphrase,
+            _ => Self::Unknown(id),
+        }
+    }
+}
+
+impl fmt::Display for SlotType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PublicKey => f.write_str("public key"),
+            Self::Passphrase => f.write_str("passphrase"),
+            Self::Keyfile => f.write_str("keyfile"),
+            Self::KeyAndPassphrase => f.write_str("public key + passphrase"),
+            Self::Unknown(id) => write!(f, "unknown type {id}"),
+        }
+    }
+}
+
+/// Who a key slot is locked to.
+pub enum Recipient<'a> {
+    PublicKey(&'a PublicKey),
+    Passphrase(&'a str, KdfParams),
+    Keyfile(&'a Keyfile),
+    /// Both factors are needed: the slot's wrapping key is derived from the
+    /// key exchange and the passphrase together
+    KeyAndPassphrase(&'a PublicKey, &'a str, KdfParams),
+}
+
+impl Recipient<'_> {
+    fn slot_type(&self) -> SlotType {
+        match self {
+            Self::PublicKey(_) => SlotType::PublicKey,
+            Self::Passphrase(..) => SlotType::Passphrase,
+            Self::Keyfile(_) => SlotType::Keyfile,
+            Self::KeyAndPassphrase(..) => SlotType::KeyAndPassphrase,
+        }
+    }
+}
+
+/// What a reader offers to open a key slot.
+#[derive(Clone, Copy)]
+pub enum Credential<'a> {
+    PrivateKey(&'a PrivateKey),
+    Passphrase(&'a str),
+    Keyfile(&'a Keyfile),
+    KeyAndPassphrase(&'a PrivateKey, &'a str),
+}
+
+impl Credential<'_> {
+    fn slot_type(self) -> SlotType {
+        match self {
+            Self::PrivateKey(_) => SlotType::PublicKey,
+            Self::Passphrase(_) => SlotType::Passphrase,
+            Self::Keyfile(_) => SlotType::Keyfile,
+            Self::KeyAndPassphrase(..) => SlotType::KeyAndPassphrase,
+        }
+    }
+}
+
+/// Choices for `encrypt_asymmetric`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EnvelopeOptions {
+    /// Pad the key slots with random dummies up to a power of two (at least
+    /// `MIN_HIDDEN_SLOTS`) and shuffle them, so the recipient count is hidden
+    pub hide_recipients: bool,
+    /// Prefix each key slot with a hint derived from its recipient's public
+    /// key, so decryption can skip the other slots. Anyone holding a
+    /// recipient's public key can then tell that they are a recipient
+    pub recipient_hints: bool,
+    /// Split the content key so that this many recipients must combine
+    /// their shares to decrypt
+    pub threshold: Option<u8>,
+}
+
+/// What an envelope reveals without a private key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvelopeInfo {
+    /// Number of key slots; padded envelopes hold more than recipients
+    pub slots: usize,
+    /// Whether the key slots carry recipient hints
+    pub hints: bool,
+    /// Recipients needed to decrypt together, for threshold envelopes
+    pub threshold: Option<u8>,
+    /// Type of each key slot, for envelopes that mix them
+    pub slot_types: Option<Vec<SlotType>>,
+}
+
+/// Number of key slots for `recipients` real ones.
+pub fn padded_slot_count(recipients: usize) -> usize {
+    recipients.max(MIN_HIDDEN_SLOTS).next_power_of_two()
+}
+
+pub fn encrypt_asymmetric(
+    plaintext: &[u8],
+    recipients: &[PublicKey],
+    options: &EnvelopeOptions,
+) -> Result<Vec<u8>> {
+    let recipients: Vec<Recipient> = recipients.iter().map(Recipient::PublicKey).collect();
+    encrypt_with(plaintext, &recipients, options, AsymmetricLayout::CURRENT)
+}
+
+/// Encrypt to key slots of any type, any one of which opens the message.
+/// Public keys alone give the same envelope as `encrypt_asymmetric`.
+pub fn encrypt_to_slots(
+    plaintext: &[u8],
+    recipients: &[Recipient],
+    options: &EnvelopeOptions,
+) -> Result<Vec<u8>> {
+    encrypt_with(plaintext, recipients, options, AsymmetricLayout::CURRENT)
+}
+
+fn encrypt_with(
+    plaintext: &[u8],
+    recipients: &[Recipient],
+    options: &EnvelopeOptions,
+    layout: AsymmetricLayout,
+) -> Result<Vec<u8>> {
     if recipients.is_empty() {
         return Err(anyhow!("At least one recipient is required"));
     }
+    let slot_count = if options.hide_recipients {
+        padded_slot_count(recipients.len())
+    } else {
+        recipients.len()
+    };
+    if options.recipient_hints && !layout.flags {
+        return Err(anyhow!("This format does not support recipient hints"));
+    }
+    if layout.slot_count == SlotCount::Byte && slot_count > usize::from(u8::MAX) {
+        return Err(anyhow!(
+            "This format supports at most 255 key slots, got {}",
+            slot_count
+        ));
+    }
+    let typed = recipients
+        .iter()
+        .any(|recipient| recipient.slot_type() != SlotType::PublicKey);
+    if typed && !layout.flags {
+        return Err(anyhow!(
+            "This format does not support passphrase or keyfile key slots"
+        ));
+    }
+    // Readers refuse envelopes that ask for more than one Argon2 derivation
+    if recipients
+        .iter()
+        .filter(|recipient| recipient.slot_type() == SlotType::Passphrase)
+        .count()
+        > 1
+    {
+        return Err(anyhow!("At most one passphrase key slot is allowed"));
+    }
+    let mut second_factors = recipients.iter().filter_map(|recipient| match recipient {
+        Recipient::KeyAndPassphrase(_, passphrase, kdf) => Some((*passphrase, *kdf)),
+        _ => None,
+    });
+    if let Some(first) = second_factors.next()
+        && second_factors.any(|factor| factor != first)
+    {
+        return Err(anyhow!(
+            "Two-factor key slots must share one passphrase and KDF parameters"
+        ));
+    }
+    if let Some(threshold) = options.threshold {
+        if !layout.flags {
+            return Err(anyhow!("This format does not support threshold encryption"));
+        }
+        if typed {
+            return Err(anyhow!(
+                "Threshold encryption needs public-key recipients only"
+            ));
+        }
+        if threshold < 2 || usize::from(threshold) > recipients.len() {
+            return Err(anyhow!(
+                "The threshold must be at least 2 and at most the number of recipients ({})",
+                recipients.len()
+            ));
+        }
+        // Shares are evaluated at the slot positions
+        if slot_count > usize::from(u8::MAX) {
+            return Err(anyhow!(
+                "Threshold encryption supports at most 255 key slots, got {}",
+                slot_count
+            ));
+        }
+    }
 
     // Generate random symmetric key
-    let mut symmetric_key = [0u8; KEY_SIZE];
-    rand::thread_rng().fill_bytes(&mut symmetric_key);
+    let mut symmetric_key = Zeroizing::new([0u8; KEY_SIZE]);
+    rand::thread_rng().fill_bytes(symmetric_key.as_mut_slice());
 
     // Encrypt payload with symmetric key
     let mut payload_nonce = [0u8; XNONCE_SIZE];
     rand::thread_rng().fill_bytes(&mut payload_nonce);
 
-    let cipher = XChaCha20Poly1305::new_from_slice(&symmetric_key)
+    let cipher = XChaCha20Poly1305::new_from_slice(symmetric_key.as_slice())
         .map_err(|e| anyhow!("Cipher creation failed: {}", e))?;
 
     let ciphertext = cipher
         .encrypt(XNonce::from_slice(&payload_nonce), plaintext)
         .map_err(|e| anyhow!("Encryption failed: {}", e))?;
 
-    // Build output
-    let mut output = Vec::new();
+    // Real slots take random positions among the dummies when recipients
+    // are hidden
+    let mut positions: Vec<usize> = (0..slot_count).collect();
+    if options.hide_recipients {
+        positions.shuffle(&mut rand::thread_rng());
+    }
+    positions.truncate(recipients.len());
 
-    // Recipient count
-    output.push(recipients.len() as u8);
+    // Each slot wraps the symmetric key, or in a threshold envelope the
+    // share for its position
+    let shares = options.threshold.map(|threshold| {
+        let xs: Vec<u8> = positions.iter().map(|&p| p as u8 + 1).collect();
+        split_secret(symmetric_key.as_slice(), threshold, &xs)
+    });
 
-    // For each recipient, wrap the symmetric key
-    for recipient in recipients {
-        // Generate ephemeral keypair
-        let ephemeral_secret = X25519Secret::random_from_rng(rand::thread_rng());
-        let ephemeral_public = X25519Public::from(&ephemeral_secret);
+    let mut second_factor: Option<(&str, KdfParams, [u8; SLOT_SALT_SIZE], SecretKey)> = None;
+    let mut slots: Vec<Option<Vec<u8>>> = vec![None; slot_count];
+    for (i, (recipient, &position)) in recipients.iter().zip(&positions).enumerate() {
+        let wrapped_secret = match shares {
+            Some(ref shares) => shares[i].as_slice(),
+            None => symmetric_key.as_slice(),
+        };
 
-        // Perform key exchange
-        let shared_secret = ephemeral_secret.diffie_hellman(&recipient.x25519);
+        let body = match recipient {
+            Recipient::PublicKey(public_key) => public_key_slot(
+                public_key,
+                None,
+                wrapped_secret,
+                options.recipient_hints,
+                layout.kek,
+            )?,
+            Recipient::Passphrase(passphrase, kdf) => {
+                kdf.check_limits()?;
+                let mut salt = [0u8; SLOT_SALT_SIZE];
+                rand::thread_rng().fill_bytes(&mut salt);
+                let key_encryption_key = kdf.derive_key(passphrase, &salt)?;
 
-        // Derive encryption key from shared secret
-        let key_encryption_key = derive_key_encryption_key(shared_secret.as_bytes());
+                // Slot: KDF parameters + salt + nonce + wrapped key
+                let mut slot = Vec::with_capacity(PASSPHRASE_SLOT_SIZE);
+                slot.extend_from_slice(&kdf.to_bytes());
+                slot.extend_from_slice(&salt);
+                slot.extend_from_slice(&wrap_key(&key_encryption_key, wrapped_secret)?);
+                slot
+            }
+            Recipient::Keyfile(keyfile) => {
+                let mut salt = [0u8; SLOT_SALT_SIZE];
+                rand::thread_rng().fill_bytes(&mut salt);
+                let key_encryption_key = derive_keyfile_key_encryption_key(keyfile.key(), &salt);
 
-        // Encrypt symmetric key with derived key
-        let mut key_nonce = [0u8; XNONCE_SIZE];
-        rand::thread_rng().fill_bytes(&mut key_nonce);
+                // Slot: salt + nonce + wrapped key
+                let mut slot = Vec::with_capacity(KEYFILE_SLOT_SIZE);
+                slot.extend_from_slice(&salt);
+                slot.extend_from_slice(&wrap_key(&key_encryption_key, wrapped_secret)?);
+                slot
+            }
+            Recipient::KeyAndPassphrase(public_key, passphrase, kdf) => {
+                // Recipients sharing a passphrase share one derivation, so
+                // readers run Argon2 once however many slots they try
+                if !second_factor
+                    .as_ref()
+                    .is_some_and(|(p, k, ..)| p == passphrase && k == kdf)
+                {
+                    kdf.check_limits()?;
+                    let mut salt = [0u8; SLOT_SALT_SIZE];
+                    rand::thread_rng().fill_bytes(&mut salt);
+                    let secret = kdf.derive_key(passphrase, &salt)?;
+                    second_factor = Some((*passphrase, *kdf, salt, secret));
+                }
+                let (_, kdf, salt, secret) = second_factor.as_ref().expect("derived above");
 
-        let key_cipher = XChaCha20Poly1305::new_from_slice(&key_encryption_key)
-            .map_err(|e| anyhow!("Key cipher creation failed: {}", e))?;
+                // Slot: KDF parameters + salt + public-key slot
+                let mut slot = Vec::with_capacity(SECOND_FACTOR_SIZE + HINT_SIZE + SLOT_SIZE);
+                slot.extend_from_slice(&kdf.to_bytes());
+                slot.extend_from_slice(salt);
+                slot.extend_from_slice(&public_key_slot(
+                    public_key,
+                    Some(secret),
+                    wrapped_secret,
+                    options.recipient_hints,
+                    layout.kek,
+                )?);
+                slot
+            }
+        };
+        slots[position] = Some(if typed {
+            typed_slot(recipient.slot_type(), &body)
+        } else {
+            body
+        });
+    }
 
-        let wrapped_key = key_cipher
-            .encrypt(XNonce::from_slice(&key_nonce), symmetric_key.as_slice())
-            .map_err(|e| anyhow!("Key wrapping failed: {}", e))?;
+    // A real ephemeral key followed by random bytes is indistinguishable
+    // from a wrapped key (and its hint) without the matching public key.
+    // Dummies of a two-factor envelope repeat its KDF parameters and salt
+    let slot_size = if options.recipient_hints {
+        HINT_SIZE + SLOT_SIZE
+    } else {
+        SLOT_SIZE
+    };
+    let slots: Vec<Vec<u8>> = slots
+        .into_iter()
+        .map(|slot| {
+            slot.unwrap_or_else(|| {
+                let mut slot = vec![0u8; slot_size];
+                let ephemeral_secret = X25519Secret::random_from_rng(rand::thread_rng());
+                let key_offset = slot_size - SLOT_SIZE;
+                slot[key_offset..key_offset + EPHEMERAL_PUBLIC_SIZE]
+                    .copy_from_slice(X25519Public::from(&ephemeral_secret).as_bytes());
+                rand::thread_rng().fill_bytes(&mut slot[..key_offset]);
+                rand::thread_rng().fill_bytes(&mut slot[key_offset + EPHEMERAL_PUBLIC_SIZE..]);
+                match second_factor {
+                    Some((_, kdf, salt, _)) => {
+                        let mut body = Vec::with_capacity(SECOND_FACTOR_SIZE + slot_size);
+                        body.extend_from_slice(&kdf.to_bytes());
+                        body.extend_from_slice(&salt);
+                        body.extend_from_slice(&slot);
+                        typed_slot(SlotType::KeyAndPassphrase, &body)
+                    }
+                    None if typed => typed_slot(SlotType::PublicKey, &slot),
+                    None => slot,
+                }
+            })
+        })
+        .collect();
 
-        // Write: ephemeral public + nonce + wrapped key
-        output.extend_from_slice(ephemeral_public.as_bytes());
-        output.extend_from_slice(&key_nonce);
-        output.extend_from_slice(&wrapped_key);
+    // Build output: flags, [threshold,] slot count, slots, payload nonce and
+    // ciphertext
+    let mut output = Vec::with_capacity(
+        2 + varint::MAX_VARINT_SIZE
+            + slots.iter().map(Vec::len).sum::<usize>()
+            + XNONCE_SIZE
+            + ciphertext.len(),
+    );
+    if layout.flags {
+        let mut flags = 0;
+        if options.recipient_hints {
+            flags |= FLAG_HINTS;
+        }
+        if options.threshold.is_some() {
+            flags |= FLAG_THRESHOLD;
+        }
+        if typed {
+            flags |= FLAG_SLOT_TYPES;
+        }
+        output.push(flags);
+    }
+    if let Some(threshold) = options.threshold {
+        output.push(threshold);
+    }
+    match layout.slot_count {
+        SlotCount::Byte => output.push(slot_count as u8),
+        SlotCount::Varint => varint::encode(slot_count as u64, &mut output),
+    }
+    for slot in &slots {
+        output.extend_from_slice(slot);
     }
 
-    // Write payload nonce and ciphertext
     output.extend_from_slice(&payload_nonce);
     output.extend_from_slice(&ciphertext);
 
     Ok(output)
 }
 
-pub fn decrypt_asymmetric(data: &[u8], private_key: &PrivateKey) -> Result<Vec<u8>> {
-    if data.is_empty() {
-        return Err(anyhow!("Empty ciphertext"));
+/// Encrypt `secret` with `key_encryption_key` under a fresh nonce, returning
+/// the nonce followed by the wrapped secret.
+fn wrap_key(key_encryption_key: &SecretKey, secret: &[u8]) -> Result<Vec<u8>> {
+    let mut key_nonce = [0u8; XNONCE_SIZE];
+    rand::thread_rng().fill_bytes(&mut key_nonce);
+
+    let key_cipher = XChaCha20Poly1305::new_from_slice(key_encryption_key.as_slice())
+        .map_err(|e| anyhow!("Key cipher creation failed: {}", e))?;
+    let wrapped_key = key_cipher
+        .encrypt(XNonce::from_slice(&key_nonce), secret)
+        .map_err(|e| anyhow!("Key wrapping failed: {}", e))?;
+
+    let mut wrapped = Vec::with_capacity(XNONCE_SIZE + WRAPPED_KEY_SIZE);
+    wrapped.extend_from_slice(&key_nonce);
+    wrapped.extend_from_slice(&wrapped_key);
+    Ok(wrapped)
+}
+
+/// A public-key slot body: [hint +] ephemeral public + nonce + wrapped key.
+/// With a passphrase secret the wrapping key needs both factors.
+fn public_key_slot(
+    public_key: &PublicKey,
+    passphrase_secret: Option<&SecretKey>,
+    secret: &[u8],
+    recipient_hints: bool,
+    kek: KekDerivation,
+) -> Result<Vec<u8>> {
+    // Generate ephemeral keypair
+    let ephemeral_secret = X25519Secret::random_from_rng(rand::thread_rng());
+    let ephemeral_public = X25519Public::from(&ephemeral_secret);
+
+    // Perform key exchange
+    let shared_secret = ephemeral_secret.diffie_hellman(&public_key.x25519);
+
+    // Derive encryption key from shared secret
+    let key_encryption_key = match passphrase_secret {
+        Some(passphrase_secret) => derive_two_factor_key_encryption_key(
+            shared_secret.as_bytes(),
+            passphrase_secret,
+            &ephemeral_public,
+            &public_key.x25519,
+        ),
+        None => kek.derive(
+            shared_secret.as_bytes(),
+            &ephemeral_public,
+            &public_key.x25519,
+        ),
+    };
+
+    let mut slot = Vec::with_capacity(HINT_SIZE + SLOT_SIZE);
+    if recipient_hints {
+        slot.extend_from_slice(&recipient_hint(&ephemeral_public, &public_key.x25519));
+    }
+    slot.extend_from_slice(ephemeral_public.as_bytes());
+    slot.extend_from_slice(&wrap_key(&key_encryption_key, secret)?);
+    Ok(slot)
+}
+
+/// A slot of a mixed envelope: type, varint body length and body.
+fn typed_slot(slot_type: SlotType, body: &[u8]) -> Vec<u8> {
+    let mut slot = Vec::with_capacity(1 + varint::MAX_VARINT_SIZE + body.len());
+    slot.push(slot_type.id());
+    varint::encode(body.len() as u64, &mut slot);
+    slot.extend_from_slice(body);
+    slot
+}
+
+/// Decrypt an asymmetric envelope laid out as the container format version
+/// that carried it specifies. Without recipient hints every key slot is
+/// tried, so padded envelopes need no hint about which slots are real.
+pub fn decrypt_asymmetric(
+    data: &[u8],
+    private_key: &PrivateKey,
+    layout: AsymmetricLayout,
+) -> Result<SecretBytes> {
+    decrypt_envelope(data, Credential::PrivateKey(private_key), layout)
+}
+
+/// Decrypt an envelope with whichever kind of key slot `credential` opens.
+pub fn decrypt_envelope(
+    data: &[u8],
+    credential: Credential,
+    layout: AsymmetricLayout,
+) -> Result<SecretBytes> {
+    let envelope = Envelope::parse(data, layout)?;
+    if let Some(threshold) = envelope.threshold {
+        return Err(anyhow!(
+            "This message needs {} of its recipients to decrypt together. Each runs 'zimhide decode --partial', then one of them combines the results with --combine.",
+            threshold
+        ));
+    }
+    let (_, symmetric_key) = unwrap_key(&envelope, credential, layout.kek)?
+        .ok_or_else(|| no_slot_error(&envelope, credential))?;
+    open_payload(&envelope, &symmetric_key, "corrupted data")
+}
+
+/// Why `credential` opened none of the envelope's slots.
+fn no_slot_error(envelope: &Envelope, credential: Credential) -> anyhow::Error {
+    let wanted = credential.slot_type();
+    let mut available: Vec<SlotType> = Vec::new();
+    for slot in &envelope.slots {
+        let slot_type = slot.lock.slot_type();
+        if !available.contains(&slot_type) {
+            available.push(slot_type);
+        }
     }
+    if !available.contains(&wanted) {
+        // Say which factor of a two-factor message is missing
+        if available.contains(&SlotType::KeyAndPassphrase) {
+            match credential {
+                Credential::PrivateKey(_) => {
+                    return anyhow!(
+                        "Could not decrypt: this message needs a passphrase as well as the private key"
+                    );
+                }
+                Credential::Passphrase(_) => {
+                    return anyhow!(
+                        "Could not decrypt: this message needs a private key as well as the passphrase"
+                    );
+                }
+                _ => {}
+            }
+        }
+        let available: Vec<String> = available.iter().map(ToString::to_string).collect();
+        return anyhow!(
+            "Could not decrypt: this message has no {} key slot. It opens with: {}",
+            wanted,
+            available.join(", ")
+        );
+    }
+    match credential {
+        Credential::PrivateKey(_) => anyhow!("Could not decrypt: you may not be a recipient"),
+        Credential::Passphrase(_) => anyhow!("Could not decrypt: wrong passphrase"),
+        Credential::Keyfile(_) => anyhow!("Could not decrypt: wrong keyfile"),
+        Credential::KeyAndPassphrase(..) => {
+            anyhow!("Could not decrypt: wrong passphrase, or you may not be a recipient")
+        }
+    }
+}
+
+/// Unwrap this recipient's share of a threshold envelope's content key.
+pub fn partial_decrypt(
+    data: &[u8],
+    private_key: &PrivateKey,
+    layout: AsymmetricLayout,
+) -> Result<PartialDecryption> {
+    let envelope = Envelope::parse(data, layout)?;
+    let threshold = envelope
+        .threshold
+        .ok_or_else(|| anyhow!("This message is not threshold-encrypted; decrypt it directly"))?;
+    let credential = Credential::PrivateKey(private_key);
+    let (index, share) = unwrap_key(&envelope, credential, layout.kek)?
+        .ok_or_else(|| no_slot_error(&envelope, credential))?;
+    Ok(PartialDecryption {
+        message_id: message_id(envelope.payload_nonce),
+        threshold,
+        index: index as u8 + 1,
+        share,
+    })
+}
+
+/// Decrypt a threshold envelope from enough recipients' partial decryptions.
+pub fn decrypt_combined(
+    data: &[u8],
+    partials: &[PartialDecryption],
+    layout: AsymmetricLayout,
+) -> Result<SecretBytes> {
+    let envelope = Envelope::parse(data, layout)?;
+    let threshold = envelope
+        .threshold
+        .ok_or_else(|| anyhow!("This message is not threshold-encrypted; decrypt it directly"))?;
+    let id = message_id(envelope.payload_nonce);
+
+    let mut points: Vec<(u8, &[u8])> = Vec::with_capacity(partials.len());
+    for partial in partials {
+        if partial.message_id != id {
+            return Err(anyhow!(
+                "Partial decryption of slot {} was made for a different message",
+                partial.index
+            ));
+        }
+        if usize::from(partial.index) > envelope.slots.len() {
+            return Err(anyhow!(
+                "Partial decryption of slot {} does not match this message, which has {} key slots",
+                partial.index,
+                envelope.slots.len()
+            ));
+        }
+        if partial.threshold != threshold {
+            return Err(anyhow!(
+                "Partial decryption of slot {} expects {} of the shares, but this message needs {}; it is damaged",
+                partial.index,
+                partial.threshold,
+                threshold
+            ));
+        }
+        if points.iter().any(|&(x, _)| x == partial.index) {
+            return Err(anyhow!("Slot {} was given more than once", partial.index));
+        }
+        points.push((partial.index, partial.share.as_slice()));
+    }
+    if points.len() < usize::from(threshold) {
+        return Err(anyhow!(
+            "Need partial decryptions from {} recipients, got {}",
+            threshold,
+            points.len()
+        ));
+    }
+    points.truncate(usize::from(threshold));
+
+    let combined = combine_secret(&points);
+    let mut symmetric_key = Zeroizing::new([0u8; KEY_SIZE]);
+    symmetric_key.copy_from_slice(&combined);
+    open_payload(
+        &envelope,
+        &symmetric_key,
+        "a partial decryption is damaged, or the data is corrupted",
+    )
+}
+
+/// Decrypt the payload, explaining an authentication failure with `failure`.
+fn open_payload(
+    envelope: &Envelope,
+    symmetric_key: &SecretKey,
+    failure: &str,
+) -> Result<SecretBytes> {
+    let cipher = XChaCha20Poly1305::new_from_slice(symmetric_key.as_slice())
+        .map_err(|e| anyhow!("Cipher creation failed: {}", e))?;
+
+    open(
+        &cipher,
+        XNonce::from_slice(envelope.payload_nonce),
+        envelope.ciphertext,
+    )
+    .map_err(|_| anyhow!("Payload decryption failed: {}", failure))
+}
+
+/// The index of the key slot `credential` can open, if any.
+pub fn find_slot(
+    data: &[u8],
+    credential: Credential,
+    layout: AsymmetricLayout,
+) -> Result<Option<usize>> {
+    let envelope = Envelope::parse(data, layout)?;
+    Ok(unwrap_key(&envelope, credential, layout.kek)?.map(|(index, _)| index))
+}
+
+/// Slot count, hint presence and slot types of an envelope.
+pub fn envelope_info(data: &[u8], layout: AsymmetricLayout) -> Option<EnvelopeInfo> {
+    let envelope = Envelope::parse(data, layout).ok()?;
+    Some(EnvelopeInfo {
+        slots: envelope.slots.len(),
+        hints: envelope.hints,
+        threshold: envelope.threshold,
+        slot_types: envelope.typed.then(|| {
+            envelope
+                .slots
+                .iter()
+                .map(|slot| slot.lock.slot_type())
+                .collect()
+        }),
+    })
+}
+
+/// What a key slot's wrapping key is derived from.
+enum SlotLock<'a> {
+    PublicKey {
+        hint: Option<&'a [u8]>,
+        ephemeral_public: X25519Public,
+    },
+    Passphrase {
+        kdf: KdfParams,
+        salt: &'a [u8],
+    },
+    Keyfile {
+        salt: &'a [u8],
+    },
+    KeyAndPassphrase {
+        kdf: KdfParams,
+        salt: &'a [u8],
+        hint: Option<&'a [u8]>,
+        ephemeral_public: X25519Public,
+    },
+    Unknown(u8),
+}
+
+impl SlotLock<'_> {
+    fn slot_type(&self) -> SlotType {
+        match self {
+            Self::PublicKey { .. } => SlotType::PublicKey,
+            Self::Passphrase { .. } => SlotType::Passphrase,
+            Self::Keyfile { .. } => SlotType::Keyfile,
+            Self::KeyAndPassphrase { .. } => SlotType::KeyAndPassphrase,
+            Self::Unknown(id) => SlotType::Unknown(*id),
+        }
+    }
+}
+
+/// A key slot, borrowed from the envelope.
+struct Slot<'a> {
+    lock: SlotLock<'a>,
+    key_nonce: &'a [u8],
+    wrapped_key: &'a [u8],
+}
 
-    let recipient_count = data[0] as usize;
-    if recipient_count == 0 {
-        return Err(anyhow!("No recipients in ciphertext"));
+impl<'a> Slot<'a> {
+    /// A public-key slot: [hint +] ephemeral public + nonce + wrapped key.
+    fn public_key(slot: &'a [u8], hint_size: usize) -> Self {
+        let (hint, key) = slot.split_at(hint_size);
+        let ephemeral_bytes: [u8; 32] = key[..EPHEMERAL_PUBLIC_SIZE].try_into().unwrap();
+        Self {
+            lock: SlotLock::PublicKey {
+                hint: (hint_size > 0).then_some(hint),
+                ephemeral_public: X25519Public::from(ephemeral_bytes),
+            },
+            key_nonce: &key[EPHEMERAL_PUBLIC_SIZE..EPHEMERAL_PUBLIC_SIZE + XNONCE_SIZE],
+            wrapped_key: &key[EPHEMERAL_PUBLIC_SIZE + XNONCE_SIZE..],
+        }
     }
 
-    // Size per recipient: ephemeral public (32) + nonce (24) + wrapped key (48)
-    let per_recipient_size = EPHEMERAL_PUBLIC_SIZE + XNONCE_SIZE + WRAPPED_KEY_SIZE;
-    let recipients_section_size = recipient_count * per_recipient_size;
-    let header_size = 1 + recipients_section_size;
+    /// A slot of a mixed envelope, from its type and body.
+    fn typed(slot_type: SlotType, body: &'a [u8], hint_size: usize) -> Result<Self> {
+        let expected = match slot_type {
+            SlotType::PublicKey => hint_size + SLOT_SIZE,
+            SlotType::Passphrase => PASSPHRASE_SLOT_SIZE,
+            SlotType::Keyfile => KEYFILE_SLOT_SIZE,
+            SlotType::KeyAndPassphrase => SECOND_FACTOR_SIZE + hint_size + SLOT_SIZE,
+            SlotType::Unknown(id) => {
+                return Ok(Self {
+                    lock: SlotLock::Unknown(id),
+                    key_nonce: &[],
+                    wrapped_key: &[],
+                });
+            }
+        };
+        if body.len() != expected {
+            return Err(anyhow!(
+                "Invalid {} key slot: expected {} bytes, got {}",
+                slot_type,
+                expected,
+                body.len()
+            ));
+        }
 
-    if data.len() < header_size + XNONCE_SIZE + 16 {
-        return Err(anyhow!("Ciphertext too short"));
+        let (lock, key) = match slot_type {
+            SlotType::PublicKey => return Ok(Self::public_key(body, hint_size)),
+            SlotType::Passphrase => {
+                let (params, rest) = body.split_at(PARAMS_SIZE);
+                let (salt, key) = rest.split_at(SLOT_SALT_SIZE);
+                let kdf = KdfParams::from_bytes(params);
+                (SlotLock::Passphrase { kdf, salt }, key)
+            }
+            SlotType::KeyAndPassphrase => {
+                let (params, rest) = body.split_at(PARAMS_SIZE);
+                let (salt, rest) = rest.split_at(SLOT_SALT_SIZE);
+                let kdf = KdfParams::from_bytes(params);
+                let slot = Self::public_key(rest, hint_size);
+                let SlotLock::PublicKey {
+                    hint,
+                    ephemeral_public,
+                } = slot.lock
+                else {
+                    unreachable!("public_key builds public-key slots");
+                };
+                return Ok(Self {
+                    lock: SlotLock::KeyAndPassphrase {
+                        kdf,
+                        salt,
+                        hint,
+                        ephemeral_public,
+                    },
+                    ..slot
+                });
+            }
+            _ => {
+                let (salt, key) = body.split_at(SLOT_SALT_SIZE);
+                (SlotLock::Keyfile { salt }, key)
+            }
+        };
+        let (key_nonce, wrapped_key) = key.split_at(XNONCE_SIZE);
+        Ok(Self {
+            lock,
+            key_nonce,
+            wrapped_key,
+        })
     }
+}
 
-    // Try to decrypt the symmetric key with our private key
-    let mut symmetric_key: Option<[u8; KEY_SIZE]> = None;
+/// An envelope split into its parts.
+struct Envelope<'a> {
+    threshold: Option<u8>,
+    /// Whether public-key slots carry recipient hints
+    hints: bool,
+    /// Whether the slots are of mixed types
+    typed: bool,
+    slots: Vec<Slot<'a>>,
+    payload_nonce: &'a [u8],
+    ciphertext: &'a [u8],
+}
 
-    for i in 0..recipient_count {
-        let offset = 1 + i * per_recipient_size;
+impl<'a> Envelope<'a> {
+    fn parse(data: &'a [u8], layout: AsymmetricLayout) -> Result<Self> {
+        if data.is_empty() {
+            return Err(anyhow!("Empty ciphertext"));
+        }
 
-        let ephemeral_bytes: [u8; 32] = data[offset..offset + 32].try_into().unwrap();
-        let ephemeral_public = X25519Public::from(ephemeral_bytes);
+        let (flags, flags_size) = if layout.flags { (data[0], 1) } else { (0, 0) };
+        if flags & !(FLAG_HINTS | FLAG_THRESHOLD | FLAG_SLOT_TYPES) != 0 {
+            return Err(anyhow!("Unknown envelope flags: {:#04x}", flags));
+        }
+        let hint_size = if flags & FLAG_HINTS != 0 {
+            HINT_SIZE
+        } else {
+            0
+        };
+        let typed = flags & FLAG_SLOT_TYPES != 0;
+        if typed && flags & FLAG_THRESHOLD != 0 {
+            return Err(anyhow!("Threshold envelopes cannot have typed key slots"));
+        }
 
-        let key_nonce = &data[offset + 32..offset + 32 + XNONCE_SIZE];
-        let wrapped_key = &data[offset + 32 + XNONCE_SIZE..offset + per_recipient_size];
+        let (threshold, flags_size) = if flags & FLAG_THRESHOLD != 0 {
+            let threshold = *data
+                .get(flags_size)
+                .ok_or_else(|| anyhow!("Ciphertext too short"))?;
+            (Some(threshold), flags_size + 1)
+        } else {
+            (None, flags_size)
+        };
 
-        // Perform key exchange
-        let shared_secret = private_key.x25519.diffie_hellman(&ephemeral_public);
-        let key_encryption_key = derive_key_encryption_key(shared_secret.as_bytes());
+        let (slot_count, count_size) = read_slot_count(&data[flags_size..], layout.slot_count)?;
+        if slot_count == 0 {
+            return Err(anyhow!("No recipients in ciphertext"));
+        }
+        if let Some(threshold) = threshold
+            && (threshold < 2 || usize::from(threshold) > slot_count || slot_count > 255)
+        {
+            return Err(anyhow!(
+                "Invalid threshold {} for {} key slots",
+                threshold,
+                slot_count
+            ));
+        }
+        let slots_start = flags_size + count_size;
+
+        let (slots, header_size) = if typed {
+            // Each slot: type (1) + varint body length + body
+            let mut slots = Vec::new();
+            let mut offset = slots_start;
+            for _ in 0..slot_count {
+                let slot_type = SlotType::from_id(
+                    *data
+                        .get(offset)
+                        .ok_or_else(|| anyhow!("Ciphertext too short"))?,
+                );
+                let (length, used) = varint::decode(&data[offset + 1..])?;
+                let body_start = offset + 1 + used;
+                let body_end = usize::try_from(length)
+                    .ok()
+                    .and_then(|length| body_start.checked_add(length))
+                    .filter(|&end| end <= data.len())
+                    .ok_or_else(|| anyhow!("Ciphertext too short"))?;
+                slots.push(Slot::typed(
+                    slot_type,
+                    &data[body_start..body_end],
+                    hint_size,
+                )?);
+                offset = body_end;
+            }
+            check_derivations(&slots)?;
+            (slots, offset)
+        } else {
+            // Each slot: [hint +] ephemeral public (32) + nonce (24) + wrapped key (48)
+            let slot_size = hint_size + SLOT_SIZE;
+            let header_size = slot_count
+                .checked_mul(slot_size)
+                .and_then(|size| size.checked_add(slots_start))
+                .filter(|&size| size <= data.len())
+                .ok_or_else(|| anyhow!("Ciphertext too short"))?;
+            let slots = data[slots_start..header_size]
+                .chunks_exact(slot_size)
+                .map(|slot| Slot::public_key(slot, hint_size))
+                .collect();
+            (slots, header_size)
+        };
+
+        if data.len() < header_size + XNONCE_SIZE + 16 {
+            return Err(anyhow!("Ciphertext too short"));
+        }
+
+        Ok(Self {
+            threshold,
+            hints: hint_size > 0,
+            typed,
+            slots,
+            payload_nonce: &data[header_size..header_size + XNONCE_SIZE],
+            ciphertext: &data[header_size + XNONCE_SIZE..],
+        })
+    }
+}
+
+/// Each Argon2 derivation a reader may run costs up to the `KdfParams` cap, so
+/// an envelope may ask for at most one: a single passphrase slot, and one
+/// salt and parameter set shared by every two-factor slot.
+fn check_derivations(slots: &[Slot]) -> Result<()> {
+    let passphrase_slots = slots
+        .iter()
+        .filter(|slot| matches!(slot.lock, SlotLock::Passphrase { .. }))
+        .count();
+    if passphrase_slots > 1 {
+        return Err(anyhow!(
+            "Invalid envelope: {} passphrase key slots, but at most one is allowed",
+            passphrase_slots
+        ));
+    }
+
+    let mut second_factor = None;
+    for slot in slots {
+        if let SlotLock::KeyAndPassphrase { kdf, salt, .. } = &slot.lock {
+            match second_factor {
+                None => second_factor = Some((kdf, salt)),
+                Some(first) if first != (kdf, salt) => {
+                    return Err(anyhow!(
+                        "Invalid envelope: two-factor key slots must share one salt and KDF parameters"
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Find the slot `credential` can open and unwrap the symmetric key from it.
+/// Slots of other types, and public-key slots whose hint does not match, are
+/// skipped without deriving a key.
+fn unwrap_key(
+    envelope: &Envelope,
+    credential: Credential,
+    kek: KekDerivation,
+) -> Result<Option<(usize, SecretKey)>> {
+    let recipient_public = match credential {
+        Credential::PrivateKey(private_key) | Credential::KeyAndPassphrase(private_key, _) => {
+            Some(X25519Public::from(&private_key.x25519))
+        }
+        _ => None,
+    };
+    // Two-factor slots of one envelope normally share their salt
+    let mut passphrase_secrets: Vec<(KdfParams, &[u8], SecretKey)> = Vec::new();
+
+    for (index, slot) in envelope.slots.iter().enumerate() {
+        let key_encryption_key = match (&slot.lock, credential) {
+            (
+                SlotLock::PublicKey {
+                    hint,
+                    ephemeral_public,
+                },
+                Credential::PrivateKey(private_key),
+            ) => {
+                let recipient_public = recipient_public.as_ref().expect("private key given");
+                let Some(shared_secret) =
+                    key_exchange(private_key, recipient_public, *hint, ephemeral_public, kek)
+                else {
+                    continue;
+                };
+                kek.derive(shared_secret.as_bytes(), ephemeral_public, recipient_public)
+            }
+            (
+                SlotLock::KeyAndPassphrase {
+                    kdf,
+                    salt,
+                    hint,
+                    ephemeral_public,
+                },
+                Credential::KeyAndPassphrase(private_key, passphrase),
+            ) => {
+                let recipient_public = recipient_public.as_ref().expect("private key given");
+                let Some(shared_secret) =
+                    key_exchange(private_key, recipient_public, *hint, ephemeral_public, kek)
+                else {
+                    continue;
+                };
+                let cached = passphrase_secrets
+                    .iter()
+                    .position(|(k, s, _)| k == kdf && s == salt);
+                let cached = match cached {
+                    Some(cached) => cached,
+                    None => {
+                        kdf.check_limits()?;
+                        let secret = kdf.derive_key(passphrase, salt)?;
+                        passphrase_secrets.push((*kdf, salt, secret));
+                        passphrase_secrets.len() - 1
+                    }
+                };
+                derive_two_factor_key_encryption_key(
+                    shared_secret.as_bytes(),
+                    &passphrase_secrets[cached].2,
+                    ephemeral_public,
+                    recipient_public,
+                )
+            }
+            (SlotLock::Passphrase { kdf, salt }, Credential::Passphrase(passphrase)) => {
+                kdf.check_limits()?;
+                kdf.derive_key(passphrase, salt)?
+            }
+            (SlotLock::Keyfile { salt }, Credential::Keyfile(keyfile)) => {
+                derive_keyfile_key_encryption_key(keyfile.key(), salt)
+            }
+            _ => continue,
+        };
 
         // Try to decrypt
-        let key_cipher = XChaCha20Poly1305::new_from_slice(&key_encryption_key)
+        let key_cipher = XChaCha20Poly1305::new_from_slice(key_encryption_key.as_slice())
             .map_err(|e| anyhow!("Key cipher creation failed: {}", e))?;
 
-        if let Ok(decrypted_key) = key_cipher.decrypt(XNonce::from_slice(key_nonce), wrapped_key)
-            && decrypted_key.len() == KEY_SIZE
+        if let Ok(decrypted_key) = open(
+            &key_cipher,
+            XNonce::from_slice(slot.key_nonce),
+            slot.wrapped_key,
+        ) && decrypted_key.len() == KEY_SIZE
         {
-            let mut key = [0u8; KEY_SIZE];
+            let mut key = Zeroizing::new([0u8; KEY_SIZE]);
             key.copy_from_slice(&decrypted_key);
-            symmetric_key = Some(key);
-            break;
+            return Ok(Some((index, key)));
         }
     }
 
-    let symmetric_key =
-        symmetric_key.ok_or_else(|| anyhow!("Could not decrypt: you may not be a recipient"))?;
+    Ok(None)
+}
 
-    // Decrypt payload
-    let payload_nonce = &data[header_size..header_size + XNONCE_SIZE];
-    let ciphertext = &data[header_size + XNONCE_SIZE..];
+/// The shared secret with a public-key slot's ephemeral key, unless the
+/// slot's hint or ephemeral key rules the slot out.
+fn key_exchange(
+    private_key: &PrivateKey,
+    recipient_public: &X25519Public,
+    hint: Option<&[u8]>,
+    ephemeral_public: &X25519Public,
+    kek: KekDerivation,
+) -> Option<SharedSecret> {
+    if let Some(hint) = hint
+        && hint != recipient_hint(ephemeral_public, recipient_public)
+    {
+        return None;
+    }
 
-    let cipher = XChaCha20Poly1305::new_from_slice(&symmetric_key)
-        .map_err(|e| anyhow!("Cipher creation failed: {}", e))?;
+    // Perform key exchange
+    let shared_secret = private_key.x25519.diffie_hellman(ephemeral_public);
+    if !kek.is_legacy() && !shared_secret.was_contributory() {
+        // Low-order ephemeral key: never a slot we could have been sent
+        return None;
+    }
+    Some(shared_secret)
+}
+
+/// Truncated SHA-256 of the recipient's public key, keyed by the slot's
+/// ephemeral key so hints cannot be linked across envelopes.
+fn recipient_hint(
+    ephemeral_public: &X25519Public,
+    recipient_public: &X25519Public,
+) -> [u8; HINT_SIZE] {
+    let digest = Sha256::new()
+        .chain_update(HINT_DOMAIN)
+        .chain_update(ephemeral_public.as_bytes())
+        .chain_update(recipient_public.as_bytes())
+        .finalize();
+    let mut hint = [0u8; HINT_SIZE];
+    hint.copy_from_slice(&digest[..HINT_SIZE]);
+    hint
+}
 
-    let plaintext = cipher
-        .decrypt(XNonce::from_slice(payload_nonce), ciphertext)
-        .map_err(|_| anyhow!("Payload decryption failed: corrupted data"))?;
+/// The slot count and the number of bytes it occupies.
+fn read_slot_count(data: &[u8], encoding: SlotCount) -> Result<(usize, usize)> {
+    match encoding {
+        SlotCount::Byte => data
+            .first()
+            .map(|&count| (usize::from(count), 1))
+            .ok_or_else(|| anyhow!("Empty ciphertext")),
+        SlotCount::Varint => {
+            let (count, used) = varint::decode(data)?;
+            let count = usize::try_from(count).map_err(|_| anyhow!("Too many key slots"))?;
+            Ok((count, used))
+        }
+    }
+}
 
-    Ok(plaintext)
+/// HKDF-SHA256 with both public keys as salt, so a wrapped key is only valid
+/// for the exchange that produced it.
+fn derive_key_encryption_key(
+    shared_secret: &[u8; 32],
+    ephemeral_public: &X25519Public,
+    recipient_public: &X25519Public,
+) -> SecretKey {
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(ephemeral_public.as_bytes());
+    salt[32..].copy_from_slice(recipient_public.as_bytes());
+
+    let mut result = Zeroizing::new([0u8; KEY_SIZE]);
+    Hkdf::<Sha256>::new(Some(&salt), shared_secret)
+        .expand(HKDF_INFO, result.as_mut_slice())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    result
 }
 
-pub fn recipient_count(data: &[u8]) -> Option<u8> {
-    data.first().copied()
+/// HKDF-SHA256 over the shared secret and the passphrase's Argon2id output,
+/// so neither factor alone yields the wrapping key.
+fn derive_two_factor_key_encryption_key(
+    shared_secret: &[u8; 32],
+    passphrase_secret: &SecretKey,
+    ephemeral_public: &X25519Public,
+    recipient_public: &X25519Public,
+) -> SecretKey {
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(ephemeral_public.as_bytes());
+    salt[32..].copy_from_slice(recipient_public.as_bytes());
+    let mut ikm = Zeroizing::new([0u8; 64]);
+    ikm[..32].copy_from_slice(shared_secret);
+    ikm[32..].copy_from_slice(passphrase_secret.as_slice());
+
+    let mut result = Zeroizing::new([0u8; KEY_SIZE]);
+    Hkdf::<Sha256>::new(Some(&salt), ikm.as_slice())
+        .expand(TWO_FACTOR_HKDF_INFO, result.as_mut_slice())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    result
 }
 
-fn derive_key_encryption_key(shared_secret: &[u8]) -> [u8; KEY_SIZE] {
+/// HKDF-SHA256 over the keyfile's key, salted per slot.
+fn derive_keyfile_key_encryption_key(key: &SecretKey, salt: &[u8]) -> SecretKey {
+    let mut result = Zeroizing::new([0u8; KEY_SIZE]);
+    Hkdf::<Sha256>::new(Some(salt), key.as_slice())
+        .expand(KEYFILE_HKDF_INFO, result.as_mut_slice())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    result
+}
+
+fn derive_legacy_key_encryption_key(shared_secret: &[u8]) -> SecretKey {
     // Simple key derivation: hash the shared secret with a domain separator
     use std::collections::hash_map::DefaultHasher;
     use std::hash::{Hash, Hasher};
 
-    let mut result = [0u8; KEY_SIZE];
+    let mut result = Zeroizing::new([0u8; KEY_SIZE]);
 
     // Use multiple rounds to fill the key
     for i in 0..4 {
@@ -174,11 +1293,16 @@ mod tests {
         let keypair = Keypair::generate();
         let plaintext = b"Secret message for one recipient";
 
-        let encrypted =
-            encrypt_asymmetric(plaintext, std::slice::from_ref(&keypair.public)).unwrap();
-        let decrypted = decrypt_asymmetric(&encrypted, &keypair.private).unwrap();
+        let encrypted = encrypt_asymmetric(
+            plaintext,
+            std::slice::from_ref(&keypair.public),
+            &EnvelopeOptions::default(),
+        )
+        .unwrap();
+        let decrypted =
+            decrypt_asymmetric(&encrypted, &keypair.private, AsymmetricLayout::CURRENT).unwrap();
 
-        assert_eq!(plaintext.as_slice(), decrypted.as_slice());
+        assert_eq!(plaintext.as_slice(), &*decrypted);
     }
 
     #[test]
@@ -188,14 +1312,17 @@ mod tests {
         let plaintext = b"Secret message for multiple recipients";
 
         let recipients = [keypair1.public.clone(), keypair2.public.clone()];
-        let encrypted = encrypt_asymmetric(plaintext, &recipients).unwrap();
+        let encrypted =
+            encrypt_asymmetric(plaintext, &recipients, &EnvelopeOptions::default()).unwrap();
 
         // Both recipients should be able to decrypt
-        let decrypted1 = decrypt_asymmetric(&encrypted, &keypair1.private).unwrap();
-        let decrypted2 = decrypt_asymmetric(&encrypted, &keypair2.private).unwrap();
+        let decrypted1 =
+            decrypt_asymmetric(&encrypted, &keypair1.private, AsymmetricLayout::CURRENT).unwrap();
+        let decrypted2 =
+            decrypt_asymmetric(&encrypted, &keypair2.private, AsymmetricLayout::CURRENT).unwrap();
 
-        assert_eq!(plaintext.as_slice(), decrypted1.as_slice());
-        assert_eq!(plaintext.as_slice(), decrypted2.as_slice());
+        assert_eq!(plaintext.as_slice(), &*decrypted1);
+        assert_eq!(plaintext.as_slice(), &*decrypted2);
     }
 
     #[test]
@@ -204,10 +1331,533 @@ mod tests {
         let non_recipient = Keypair::generate();
         let plaintext = b"Secret message";
 
-        let encrypted =
-            encrypt_asymmetric(plaintext, std::slice::from_ref(&recipient.public)).unwrap();
-        let result = decrypt_asymmetric(&encrypted, &non_recipient.private);
+        let encrypted = encrypt_asymmetric(
+            plaintext,
+            std::slice::from_ref(&recipient.public),
+            &EnvelopeOptions::default(),
+        )
+        .unwrap();
+        let result = decrypt_asymmetric(
+            &encrypted,
+            &non_recipient.private,
+            AsymmetricLayout::CURRENT,
+        );
 
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_legacy_derivation_still_decrypts() {
+        let keypair = Keypair::generate();
+        let plaintext = b"Archived message";
+        let legacy = AsymmetricLayout::for_format_version(4);
+
+        let encrypted = encrypt_with(
+            plaintext,
+            &[Recipient::PublicKey(&keypair.public)],
+            &EnvelopeOptions::default(),
+            legacy,
+        )
+        .unwrap();
+        let decrypted = decrypt_asymmetric(&encrypted, &keypair.private, legacy).unwrap();
+        assert_eq!(plaintext.as_slice(), &*decrypted);
+
+        // The derivations are not interchangeable
+        let hkdf = AsymmetricLayout {
+            kek: KekDerivation::HkdfSha256,
+            ..legacy
+        };
+        assert!(decrypt_asymmetric(&encrypted, &keypair.private, hkdf).is_err());
+    }
+
+    #[test]
+    fn test_hidden_recipients_padded() {
+        let keypair1 = Keypair::generate();
+        let keypair2 = Keypair::generate();
+        let recipients = [keypair1.public.clone(), keypair2.public.clone()];
+        let options = EnvelopeOptions {
+            hide_recipients: true,
+            ..Default::default()
+        };
+
+        let encrypted = encrypt_asymmetric(b"hidden", &recipients, &options).unwrap();
+        let layout = AsymmetricLayout::CURRENT;
+        let info = envelope_info(&encrypted, layout).unwrap();
+        assert_eq!(info.slots, MIN_HIDDEN_SLOTS);
+        assert!(!info.hints);
+        for keypair in [&keypair1, &keypair2] {
+            let decrypted = decrypt_asymmetric(&encrypted, &keypair.private, layout).unwrap();
+            assert_eq!(&*decrypted, b"hidden");
+        }
+
+        assert_eq!(padded_slot_count(1), 8);
+        assert_eq!(padded_slot_count(9), 16);
+        assert_eq!(padded_slot_count(300), 512);
+    }
+
+    #[test]
+    fn test_more_than_255_recipients() {
+        let keypairs: Vec<Keypair> = (0..300).map(|_| Keypair::generate()).collect();
+        let recipients: Vec<PublicKey> = keypairs.iter().map(|k| k.public.clone()).collect();
+
+        let encrypted =
+            encrypt_asymmetric(b"crowd", &recipients, &EnvelopeOptions::default()).unwrap();
+        let info = envelope_info(&encrypted, AsymmetricLayout::CURRENT).unwrap();
+        assert_eq!(info.slots, 300);
+        let last = &keypairs[299].private;
+        let decrypted = decrypt_asymmetric(&encrypted, last, AsymmetricLayout::CURRENT).unwrap();
+        assert_eq!(&*decrypted, b"crowd");
+
+        // The one-byte count of older versions cannot express it
+        let legacy = AsymmetricLayout::for_format_version(6);
+        let recipients: Vec<Recipient> = recipients.iter().map(Recipient::PublicKey).collect();
+        assert!(encrypt_with(b"crowd", &recipients, &EnvelopeOptions::default(), legacy).is_err());
+    }
+
+    #[test]
+    fn test_recipient_hints() {
+        let keypairs: Vec<Keypair> = (0..3).map(|_| Keypair::generate()).collect();
+        let recipients: Vec<PublicKey> = keypairs.iter().map(|k| k.public.clone()).collect();
+        let layout = AsymmetricLayout::CURRENT;
+
+        for hide_recipients in [false, true] {
+            let options = EnvelopeOptions {
+                hide_recipients,
+                recipient_hints: true,
+                ..Default::default()
+            };
+            let encrypted = encrypt_asymmetric(b"hinted", &recipients, &options).unwrap();
+            assert!(envelope_info(&encrypted, layout).unwrap().hints);
+
+            for keypair in &keypairs {
+                let decrypted = decrypt_asymmetric(&encrypted, &keypair.private, layout).unwrap();
+                assert_eq!(&*decrypted, b"hinted");
+            }
+            let slots: Vec<usize> = keypairs
+                .iter()
+                .map(|k| {
+                    find_slot(&encrypted, Credential::PrivateKey(&k.private), layout)
+                        .unwrap()
+                        .unwrap()
+                })
+                .collect();
+            if !hide_recipients {
+                assert_eq!(slots, [0, 1, 2]);
+            }
+
+            let stranger = Keypair::generate();
+            assert_eq!(
+                find_slot(
+                    &encrypted,
+                    Credential::PrivateKey(&stranger.private),
+                    layout
+                )
+                .unwrap(),
+                None
+            );
+            assert!(decrypt_asymmetric(&encrypted, &stranger.private, layout).is_err());
+        }
+
+        // Older containers have no flags byte to announce hints
+        let options = EnvelopeOptions {
+            recipient_hints: true,
+            ..Default::default()
+        };
+        let legacy = AsymmetricLayout::for_format_version(7);
+        assert!(!legacy.flags);
+        let recipients: Vec<Recipient> = recipients.iter().map(Recipient::PublicKey).collect();
+        assert!(encrypt_with(b"hinted", &recipients, &options, legacy).is_err());
+    }
+
+    #[test]
+    fn test_mismatched_hints_skip_slots() {
+        let keypairs: Vec<Keypair> = (0..4).map(|_| Keypair::generate()).collect();
+        let recipients: Vec<PublicKey> = keypairs.iter().map(|k| k.public.clone()).collect();
+        let layout = AsymmetricLayout::CURRENT;
+        let options = EnvelopeOptions {
+            recipient_hints: true,
+            ..Default::default()
+        };
+        let mut encrypted = encrypt_asymmetric(b"hinted", &recipients, &options).unwrap();
+
+        // The slots whose hint lets a key exchange go ahead for `keypair`
+        let tried = |encrypted: &[u8], keypair: &Keypair| -> Vec<usize> {
+            let envelope = Envelope::parse(encrypted, layout).unwrap();
+            let recipient_public = X25519Public::from(&keypair.private.x25519);
+            envelope
+                .slots
+                .iter()
+                .enumerate()
+                .filter(|(_, slot)| {
+                    let SlotLock::PublicKey {
+                        hint,
+                        ephemeral_public,
+                    } = &slot.lock
+                    else {
+                        panic!("untyped envelopes hold public-key slots");
+                    };
+                    key_exchange(
+                        &keypair.private,
+                        &recipient_public,
+                        *hint,
+                        ephemeral_public,
+                        layout.kek,
+                    )
+                    .is_some()
+                })
+                .map(|(index, _)| index)
+                .collect()
+        };
+        for (index, keypair) in keypairs.iter().enumerate() {
+            assert_eq!(tried(&encrypted, keypair), [index]);
+        }
+        assert!(tried(&encrypted, &Keypair::generate()).is_empty());
+
+        // Damage the third recipient's hint: its intact slot is now skipped
+        let hint_at = {
+            let envelope = Envelope::parse(&encrypted, layout).unwrap();
+            let SlotLock::PublicKey {
+                hint: Some(hint), ..
+            } = envelope.slots[2].lock
+            else {
+                panic!("slot has a hint");
+            };
+            hint.as_ptr() as usize - encrypted.as_ptr() as usize
+        };
+        encrypted[hint_at] ^= 1;
+        assert!(tried(&encrypted, &keypairs[2]).is_empty());
+        let err = decrypt_asymmetric(&encrypted, &keypairs[2].private, layout)
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Could not decrypt: you may not be a recipient"
+        );
+        for keypair in [&keypairs[0], &keypairs[1], &keypairs[3]] {
+            let decrypted = decrypt_asymmetric(&encrypted, &keypair.private, layout).unwrap();
+            assert_eq!(&*decrypted, b"hinted");
+        }
+    }
+
+    #[test]
+    fn test_threshold_envelope() {
+        let keypairs: Vec<Keypair> = (0..4).map(|_| Keypair::generate()).collect();
+        let recipients: Vec<PublicKey> = keypairs.iter().map(|k| k.public.clone()).collect();
+        let layout = AsymmetricLayout::CURRENT;
+
+        for hide_recipients in [false, true] {
+            let options = EnvelopeOptions {
+                hide_recipients,
+                threshold: Some(3),
+                ..Default::default()
+            };
+            let encrypted = encrypt_asymmetric(b"three of four", &recipients, &options).unwrap();
+            assert_eq!(
+                envelope_info(&encrypted, layout).unwrap().threshold,
+                Some(3)
+            );
+
+            // One key alone cannot decrypt
+            let error = decrypt_asymmetric(&encrypted, &keypairs[0].private, layout).unwrap_err();
+            assert!(error.to_string().contains("needs 3"), "{error}");
+
+            let partials: Vec<PartialDecryption> = keypairs
+                .iter()
+                .map(|k| partial_decrypt(&encrypted, &k.private, layout).unwrap())
+                .collect();
+            let reload = |i: usize| PartialDecryption::from_bytes(&partials[i].to_bytes()).unwrap();
+            let decrypted =
+                decrypt_combined(&encrypted, &[reload(3), reload(0), reload(2)], layout).unwrap();
+            assert_eq!(&*decrypted, b"three of four");
+
+            let error = decrypt_combined(&encrypted, &[reload(0), reload(1)], layout).unwrap_err();
+            assert!(error.to_string().contains("Need partial"), "{error}");
+            assert!(
+                decrypt_combined(&encrypted, &[reload(0), reload(0), reload(1)], layout).is_err()
+            );
+
+            // Shares of another message are refused
+            let other = encrypt_asymmetric(b"other", &recipients, &options).unwrap();
+            let foreign = partial_decrypt(&other, &keypairs[1].private, layout).unwrap();
+            let error =
+                decrypt_combined(&encrypted, &[reload(0), foreign, reload(2)], layout).unwrap_err();
+            assert!(error.to_string().contains("different message"), "{error}");
+
+            // A partial that disagrees with the envelope is named, not left to the AEAD
+            let mut mismatched = reload(1);
+            mismatched.threshold = 2;
+            let expected = format!("slot {} expects 2", mismatched.index);
+            let error = decrypt_combined(&encrypted, &[reload(0), mismatched, reload(2)], layout)
+                .unwrap_err();
+            assert!(error.to_string().contains(&expected), "{error}");
+            let mut mismatched = reload(1);
+            mismatched.index = 255;
+            let error = decrypt_combined(&encrypted, &[reload(0), mismatched, reload(2)], layout)
+                .unwrap_err();
+            assert!(
+                error.to_string().contains("slot 255 does not match"),
+                "{error}"
+            );
+            let mut zero = reload(1);
+            zero.index = 0;
+            let error = PartialDecryption::from_bytes(&zero.to_bytes())
+                .map(|_| ())
+                .unwrap_err();
+            assert!(error.to_string().contains("invalid threshold or index"));
+        }
+
+        let stranger = Keypair::generate();
+        let options = EnvelopeOptions {
+            threshold: Some(2),
+            ..Default::default()
+        };
+        let encrypted = encrypt_asymmetric(b"pair", &recipients[..2], &options).unwrap();
+        assert!(partial_decrypt(&encrypted, &stranger.private, layout).is_err());
+
+        // The threshold cannot exceed the recipients
+        let options = EnvelopeOptions {
+            threshold: Some(5),
+            ..Default::default()
+        };
+        assert!(encrypt_asymmetric(b"too many", &recipients, &options).is_err());
+    }
+
+    #[test]
+    fn test_mixed_key_slots() {
+        let keypair = Keypair::generate();
+        let keyfile = Keyfile::generate();
+        // Cheap parameters keep the test fast
+        let kdf = KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let recipients = [
+            Recipient::PublicKey(&keypair.public),
+            Recipient::Passphrase("correct horse", kdf),
+            Recipient::Keyfile(&keyfile),
+        ];
+        let layout = AsymmetricLayout::CURRENT;
+
+        for hide_recipients in [false, true] {
+            let options = EnvelopeOptions {
+                hide_recipients,
+                recipient_hints: true,
+                ..Default::default()
+            };
+            let encrypted = encrypt_to_slots(b"any of three", &recipients, &options).unwrap();
+
+            let info = envelope_info(&encrypted, layout).unwrap();
+            let slot_types = info.slot_types.unwrap();
+            assert_eq!(slot_types.len(), info.slots);
+            assert!(slot_types.contains(&SlotType::Passphrase));
+            assert!(slot_types.contains(&SlotType::Keyfile));
+
+            for credential in [
+                Credential::PrivateKey(&keypair.private),
+                Credential::Passphrase("correct horse"),
+                Credential::Keyfile(&keyfile),
+            ] {
+                let decrypted = decrypt_envelope(&encrypted, credential, layout).unwrap();
+                assert_eq!(&*decrypted, b"any of three");
+            }
+
+            let error =
+                decrypt_envelope(&encrypted, Credential::Passphrase("wrong"), layout).unwrap_err();
+            assert!(error.to_string().contains("wrong passphrase"), "{error}");
+            let other = Keyfile::generate();
+            let error =
+                decrypt_envelope(&encrypted, Credential::Keyfile(&other), layout).unwrap_err();
+            assert!(error.to_string().contains("wrong keyfile"), "{error}");
+        }
+
+        // The error names the slot types the message does have
+        let encrypted = encrypt_to_slots(
+            b"passphrase only",
+            &[Recipient::Passphrase("pw", kdf)],
+            &EnvelopeOptions::default(),
+        )
+        .unwrap();
+        let error = decrypt_asymmetric(&encrypted, &keypair.private, layout).unwrap_err();
+        assert!(
+            error.to_string().contains("opens with: passphrase"),
+            "{error}"
+        );
+
+        // Public keys alone keep the untyped layout
+        let encrypted =
+            encrypt_to_slots(b"plain", &recipients[..1], &EnvelopeOptions::default()).unwrap();
+        assert_eq!(envelope_info(&encrypted, layout).unwrap().slot_types, None);
+
+        // Threshold envelopes take public keys only
+        let options = EnvelopeOptions {
+            threshold: Some(2),
+            ..Default::default()
+        };
+        assert!(encrypt_to_slots(b"no", &recipients, &options).is_err());
+    }
+
+    #[test]
+    fn test_one_argon2_derivation_per_envelope() {
+        let keypairs: Vec<Keypair> = (0..2).map(|_| Keypair::generate()).collect();
+        let kdf = KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let options = EnvelopeOptions::default();
+        let layout = AsymmetricLayout::CURRENT;
+
+        // Writers refuse to ask for a second derivation
+        let two_passphrases = [
+            Recipient::Passphrase("one", kdf),
+            Recipient::Passphrase("two", kdf),
+        ];
+        assert!(encrypt_to_slots(b"no", &two_passphrases, &options).is_err());
+        let two_factors: Vec<Recipient> = keypairs
+            .iter()
+            .zip(["one", "two"])
+            .map(|(k, passphrase)| Recipient::KeyAndPassphrase(&k.public, passphrase, kdf))
+            .collect();
+        assert!(encrypt_to_slots(b"no", &two_factors, &options).is_err());
+
+        // Flags, one-byte slot count, then type + one-byte length + body per slot
+        let split = |envelope: &[u8]| -> (Vec<Vec<u8>>, Vec<u8>) {
+            let mut offset = 2;
+            let mut slots = Vec::new();
+            for _ in 0..envelope[1] {
+                let (length, used) = varint::decode(&envelope[offset + 1..]).unwrap();
+                let end = offset + 1 + used + length as usize;
+                slots.push(envelope[offset..end].to_vec());
+                offset = end;
+            }
+            (slots, envelope[offset..].to_vec())
+        };
+        let join = |slots: &[Vec<u8>], rest: &[u8]| {
+            let mut envelope = vec![FLAG_SLOT_TYPES, slots.len() as u8];
+            slots
+                .iter()
+                .for_each(|slot| envelope.extend_from_slice(slot));
+            envelope.extend_from_slice(rest);
+            envelope
+        };
+
+        // A crafted envelope repeating the passphrase slot
+        let encrypted =
+            encrypt_to_slots(b"once", &[Recipient::Passphrase("one", kdf)], &options).unwrap();
+        let (slots, rest) = split(&encrypted);
+        let crafted = join(&[slots[0].clone(), slots[0].clone()], &rest);
+        let error = decrypt_envelope(&crafted, Credential::Passphrase("one"), layout).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid envelope: 2 passphrase key slots, but at most one is allowed"
+        );
+
+        // Two-factor slots with a salt of their own each
+        let shared: Vec<Recipient> = keypairs
+            .iter()
+            .map(|k| Recipient::KeyAndPassphrase(&k.public, "shared", kdf))
+            .collect();
+        let encrypted = encrypt_to_slots(b"once", &shared, &options).unwrap();
+        let (mut slots, rest) = split(&encrypted);
+        let salt_start = slots[1].len() - SECOND_FACTOR_SIZE - SLOT_SIZE + PARAMS_SIZE;
+        slots[1][salt_start] ^= 1;
+        let crafted = join(&slots, &rest);
+        let credential = Credential::KeyAndPassphrase(&keypairs[0].private, "shared");
+        let error = decrypt_envelope(&crafted, credential, layout).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid envelope: two-factor key slots must share one salt and KDF parameters"
+        );
+    }
+
+    #[test]
+    fn test_two_factor_slots() {
+        let keypairs: Vec<Keypair> = (0..2).map(|_| Keypair::generate()).collect();
+        let kdf = KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let recipients: Vec<Recipient> = keypairs
+            .iter()
+            .map(|k| Recipient::KeyAndPassphrase(&k.public, "archive pass", kdf))
+            .collect();
+        let layout = AsymmetricLayout::CURRENT;
+
+        for (hide_recipients, recipient_hints) in [(false, false), (true, true)] {
+            let options = EnvelopeOptions {
+                hide_recipients,
+                recipient_hints,
+                ..Default::default()
+            };
+            let encrypted = encrypt_to_slots(b"both factors", &recipients, &options).unwrap();
+            let slot_types = envelope_info(&encrypted, layout)
+                .unwrap()
+                .slot_types
+                .unwrap();
+            assert!(
+                slot_types
+                    .iter()
+                    .all(|&slot_type| slot_type == SlotType::KeyAndPassphrase)
+            );
+
+            for keypair in &keypairs {
+                let credential = Credential::KeyAndPassphrase(&keypair.private, "archive pass");
+                let decrypted = decrypt_envelope(&encrypted, credential, layout).unwrap();
+                assert_eq!(&*decrypted, b"both factors");
+            }
+
+            // Either factor alone is refused, naming the missing one
+            let error = decrypt_asymmetric(&encrypted, &keypairs[0].private, layout).unwrap_err();
+            assert!(error.to_string().contains("needs a passphrase"), "{error}");
+            let error =
+                decrypt_envelope(&encrypted, Credential::Passphrase("archive pass"), layout)
+                    .unwrap_err();
+            assert!(error.to_string().contains("needs a private key"), "{error}");
+
+            let credential = Credential::KeyAndPassphrase(&keypairs[0].private, "wrong");
+            let error = decrypt_envelope(&encrypted, credential, layout).unwrap_err();
+            assert!(error.to_string().contains("wrong passphrase"), "{error}");
+            let stranger = Keypair::generate();
+            let credential = Credential::KeyAndPassphrase(&stranger.private, "archive pass");
+            assert!(decrypt_envelope(&encrypted, credential, layout).is_err());
+        }
+    }
+
+    #[test]
+    fn test_unknown_envelope_flags_rejected() {
+        let keypair = Keypair::generate();
+        let mut encrypted = encrypt_asymmetric(
+            b"flagged",
+            std::slice::from_ref(&keypair.public),
+            &EnvelopeOptions::default(),
+        )
+        .unwrap();
+        encrypted[0] |= 0x80;
+        let result = decrypt_asymmetric(&encrypted, &keypair.private, AsymmetricLayout::CURRENT);
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("Unknown envelope flags")
+        );
+    }
+
+    #[test]
+    fn test_hkdf_binds_public_keys() {
+        let shared = [7u8; 32];
+        let a = X25519Public::from([1u8; 32]);
+        let b = X25519Public::from([2u8; 32]);
+
+        assert_ne!(
+            derive_key_encryption_key(&shared, &a, &b),
+            derive_key_encryption_key(&shared, &b, &a)
+        );
+        assert_eq!(
+            KekDerivation::for_format_version(HKDF_FORMAT_VERSION),
+            KekDerivation::HkdfSha256
+        );
+        assert!(KekDerivation::for_format_version(4).is_legacy());
+    }
 }
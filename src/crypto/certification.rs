@@ -0,0 +1,226 @@
+//! Key certifications: one key's signed statement that a public key belongs
+//! to a named person.
+
+use super::armor::{armor, dearmor};
+use super::keys::{KEY_ID_SIZE, PrivateKey, PublicKey, unix_now};
+use super::signing::SIGNATURE_SIZE;
+use anyhow::{Context, Result, anyhow};
+use ed25519_dalek::{Signature, Signer, Verifier, VerifyingKey};
+use std::fs;
+use std::path::Path;
+use x25519_dalek::PublicKey as X25519Public;
+
+const CERTIFICATION_LABEL: &str = "CERTIFICATION";
+
+const CERTIFICATION_DOMAIN: &[u8] = b"zimhide certification v1";
+const CERTIFICATION_VERSION: u8 = 1;
+/// Longest name a certification can carry, in bytes.
+pub const MAX_CERTIFIED_NAME: usize = 255;
+
+/// A signed statement by the key `certifier` that `subject` is `name`'s key.
+#[derive(Clone)]
+pub struct Certification {
+    pub subject: PublicKey,
+    pub name: String,
+    /// Seconds since the Unix epoch
+    pub created: u64,
+    /// Key id of the certifying key
+    pub certifier: [u8; KEY_ID_SIZE],
+    signature: [u8; SIGNATURE_SIZE],
+}
+
+impl Certification {
+    /// Certify `subject` as `name` with `certifier`, which must be able to sign.
+    pub fn create(subject: &PublicKey, name: &str, certifier: &PrivateKey) -> Result<Self> {
+        if name.is_empty() || name.len() > MAX_CERTIFIED_NAME {
+            return Err(anyhow!(
+                "Certified names must be 1 to {} bytes long",
+                MAX_CERTIFIED_NAME
+            ));
+        }
+        let signing_key = certifier.signing_key()?;
+        let mut certification = Self {
+            subject: PublicKey {
+                validity: None,
+                ..subject.clone()
+            },
+            name: name.to_string(),
+            created: unix_now(),
+            certifier: certifier.public_key().key_id(),
+            signature: [0u8; SIGNATURE_SIZE],
+        };
+        certification.signature = signing_key.sign(&certification.signed_message()).to_bytes();
+        Ok(certification)
+    }
+
+    /// Check that `certifier` made this certification.
+    pub fn verify(&self, certifier: &PublicKey) -> Result<()> {
+        if certifier.key_id() != self.certifier {
+            return Err(anyhow!(
+                "Certification of {} was not made by key {}",
+                self.name,
+                certifier.fingerprint()
+            ));
+        }
+        certifier
+            .verifying_key()?
+            .verify(
+                &self.signed_message(),
+                &Signature::from_bytes(&self.signature),
+            )
+            .map_err(|_| {
+                anyhow!(
+                    "Certification of {} has an invalid signature from key {}",
+                    self.name,
+                    certifier.fingerprint()
+                )
+            })
+    }
+
+    /// Everything but the signature.
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![CERTIFICATION_VERSION];
+        match self.subject.ed25519 {
+            Some(ref ed25519) => {
+                bytes.push(1);
+                bytes.extend_from_slice(ed25519.as_bytes());
+            }
+            None => bytes.push(0),
+        }
+        bytes.extend_from_slice(self.subject.x25519.as_bytes());
+        bytes.extend_from_slice(&self.created.to_le_bytes());
+        bytes.extend_from_slice(&self.certifier);
+        bytes.push(self.name.len() as u8);
+        bytes.extend_from_slice(self.name.as_bytes());
+        bytes
+    }
+
+    fn signed_message(&self) -> Vec<u8> {
+        let mut message = CERTIFICATION_DOMAIN.to_vec();
+        message.extend_from_slice(&self.signed_bytes());
+        message
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.signed_bytes();
+        bytes.extend_from_slice(&self.signature);
+        bytes
+    }
+
+    /// Parse a certification. Its signature can only be checked with the
+    /// certifier's key; see `verify`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let truncated = || anyhow!("Invalid certification: truncated");
+        let mut rest = bytes;
+        let mut take = |n: usize| -> Result<&[u8]> {
+            if rest.len() < n {
+                return Err(truncated());
+            }
+            let (taken, remaining) = rest.split_at(n);
+            rest = remaining;
+            Ok(taken)
+        };
+
+        let version = take(1)?[0];
+        if version != CERTIFICATION_VERSION {
+            return Err(anyhow!("Unsupported certification version {}", version));
+        }
+        let ed25519 = match take(1)?[0] {
+            0 => None,
+            1 => {
+                let ed25519_bytes: [u8; 32] = take(32)?.try_into().expect("length taken");
+                Some(
+                    VerifyingKey::from_bytes(&ed25519_bytes)
+                        .map_err(|e| anyhow!("Invalid key in certification: {}", e))?,
+                )
+            }
+            other => return Err(anyhow!("Invalid certification key type {}", other)),
+        };
+        let x25519_bytes: [u8; 32] = take(32)?.try_into().expect("length taken");
+        let created = u64::from_le_bytes(take(8)?.try_into().expect("length taken"));
+        let certifier = take(KEY_ID_SIZE)?.try_into().expect("length taken");
+        let name_len = take(1)?[0] as usize;
+        let name = String::from_utf8(take(name_len)?.to_vec())
+            .map_err(|_| anyhow!("Invalid certification: name is not UTF-8"))?;
+        let signature = take(SIGNATURE_SIZE)?.try_into().expect("length taken");
+        if !rest.is_empty() {
+            return Err(anyhow!(
+                "Invalid certification: {} trailing bytes",
+                rest.len()
+            ));
+        }
+
+        Ok(Self {
+            subject: PublicKey {
+                ed25519,
+                x25519: X25519Public::from(x25519_bytes),
+                validity: None,
+            },
+            name,
+            created,
+            certifier,
+            signature,
+        })
+    }
+
+    pub fn to_pem(&self) -> String {
+        armor(CERTIFICATION_LABEL, &self.to_bytes()).to_string()
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        Self::from_bytes(&dearmor(content, CERTIFICATION_LABEL)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read certification: {}", path.display()))?;
+        Self::parse(&content).with_context(|| path.display().to_string())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_pem())
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::keys::Keypair;
+
+    #[test]
+    fn test_certification_roundtrip() {
+        let alice = Keypair::generate();
+        let bob = Keypair::generate();
+
+        let certification = Certification::create(&bob.public, "bob", &alice.private).unwrap();
+        let parsed = Certification::parse(&certification.to_pem()).unwrap();
+        assert_eq!(parsed.name, "bob");
+        assert_eq!(parsed.subject.key_id(), bob.public.key_id());
+        parsed.verify(&alice.public).unwrap();
+        assert!(parsed.verify(&bob.public).is_err());
+    }
+
+    #[test]
+    fn test_altered_certification_rejected() {
+        let alice = Keypair::generate();
+        let bob = Keypair::generate();
+        let certification = Certification::create(&bob.public, "bob", &alice.private).unwrap();
+
+        // Renaming the subject breaks the signature
+        let mut bytes = certification.to_bytes();
+        let name_at = bytes.len() - SIGNATURE_SIZE - 3;
+        bytes[name_at] = b'r';
+        let renamed = Certification::from_bytes(&bytes).unwrap();
+        assert_eq!(renamed.name, "rob");
+        assert!(renamed.verify(&alice.public).is_err());
+
+        // So does swapping in another key
+        let mut bytes = certification.to_bytes();
+        bytes[2..34].copy_from_slice(Keypair::generate().public.ed25519.unwrap().as_bytes());
+        let swapped = Certification::from_bytes(&bytes).unwrap();
+        assert!(swapped.verify(&alice.public).is_err());
+
+        assert!(Certification::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
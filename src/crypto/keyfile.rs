@@ -0,0 +1,88 @@
+//! Symmetric keyfiles: 256 random bits that open a message directly, with no
+//! passphrase stretching.
+
+use super::armor::{armor, dearmor, is_armored};
+use super::keys::write_private_file;
+use super::secret::{SecretKey, Zeroizing};
+use anyhow::{Context, Result, anyhow};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use std::fs;
+use std::path::Path;
+
+const KEYFILE_LABEL: &str = "SYMMETRIC KEY";
+
+pub const KEYFILE_SIZE: usize = 32;
+
+/// A 256-bit symmetric key.
+pub struct Keyfile {
+    key: SecretKey,
+}
+
+impl Keyfile {
+    pub fn generate() -> Self {
+        let mut key = Zeroizing::new([0u8; KEYFILE_SIZE]);
+        OsRng.fill_bytes(key.as_mut_slice());
+        Self { key }
+    }
+
+    pub(crate) fn key(&self) -> &SecretKey {
+        &self.key
+    }
+
+    pub fn to_pem(&self) -> Zeroizing<String> {
+        armor(KEYFILE_LABEL, self.key.as_slice())
+    }
+
+    /// Read a keyfile: zimhide's armored form, or exactly 32 raw bytes.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = Zeroizing::new(
+            fs::read(path)
+                .with_context(|| format!("Failed to read keyfile: {}", path.display()))?,
+        );
+        let bytes = match std::str::from_utf8(&content) {
+            Ok(text) if is_armored(text, KEYFILE_LABEL) => {
+                dearmor(text, KEYFILE_LABEL).with_context(|| path.display().to_string())?
+            }
+            _ => Zeroizing::new(content.to_vec()),
+        };
+        if bytes.len() != KEYFILE_SIZE {
+            return Err(anyhow!(
+                "Not a keyfile: {} must hold {} bytes, or a zimhide symmetric key",
+                path.display(),
+                KEYFILE_SIZE
+            ));
+        }
+
+        let mut key = Zeroizing::new([0u8; KEYFILE_SIZE]);
+        key.copy_from_slice(&bytes);
+        Ok(Self { key })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_private_file(path, &self.to_pem())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_keyfile_roundtrip_and_raw_bytes() {
+        let dir = tempdir().unwrap();
+        let keyfile = Keyfile::generate();
+
+        let armored = dir.path().join("armored.key");
+        keyfile.save(&armored).unwrap();
+        assert_eq!(*Keyfile::load(&armored).unwrap().key(), *keyfile.key());
+
+        let raw = dir.path().join("raw.key");
+        fs::write(&raw, keyfile.key().as_slice()).unwrap();
+        assert_eq!(*Keyfile::load(&raw).unwrap().key(), *keyfile.key());
+
+        fs::write(&raw, [0u8; 31]).unwrap();
+        assert!(Keyfile::load(&raw).is_err());
+    }
+}
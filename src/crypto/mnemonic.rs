@@ -0,0 +1,169 @@
+//! Word-list encoding of key material for paper backups.
+//!
+//! This is the BIP39 mnemonic encoding, generalised to any multiple of four
+//! bytes: the data is followed by the first `len / 4` bits of its SHA-256,
+//! and each 11 bits pick one of 2048 English words. 32 bytes make the usual
+//! 24 words.
+
+use super::secret::{SecretString, Zeroizing};
+use anyhow::{Result, anyhow};
+use sha2::{Digest, Sha256};
+use std::sync::LazyLock;
+
+/// The BIP39 English wordlist, one word per line.
+const WORDLIST: &str = include_str!("wordlist.txt");
+
+static WORDS: LazyLock<Vec<&'static str>> = LazyLock::new(|| WORDLIST.lines().collect());
+
+const BITS_PER_WORD: usize = 11;
+/// Words are unique in their first four letters, so that is enough to type.
+const PREFIX_LEN: usize = 4;
+
+/// Encode `data`, which must be a non-empty multiple of four bytes.
+pub fn encode(data: &[u8]) -> Result<SecretString> {
+    if data.is_empty() || !data.len().is_multiple_of(4) {
+        return Err(anyhow!(
+            "Mnemonics encode a multiple of 4 bytes, not {}",
+            data.len()
+        ));
+    }
+
+    let checksum = Sha256::digest(data);
+    let checksum_bits = data.len() / 4;
+    let bit = |i: usize| -> usize {
+        let (bytes, i) = if i < data.len() * 8 {
+            (data, i)
+        } else {
+            (checksum.as_slice(), i - data.len() * 8)
+        };
+        usize::from(bytes[i / 8] >> (7 - i % 8) & 1)
+    };
+
+    let word_count = (data.len() * 8 + checksum_bits) / BITS_PER_WORD;
+    let mut phrase = Zeroizing::new(String::new());
+    for word in 0..word_count {
+        let index = (0..BITS_PER_WORD).fold(0, |acc, i| acc << 1 | bit(word * BITS_PER_WORD + i));
+        if word > 0 {
+            phrase.push(' ');
+        }
+        phrase.push_str(WORDS[index]);
+    }
+    Ok(phrase)
+}
+
+/// Decode a phrase written by `encode`, checking its checksum. Words may be
+/// abbreviated to their first four letters and are matched case-insensitively.
+pub fn decode(phrase: &str) -> Result<Zeroizing<Vec<u8>>> {
+    let indices = Zeroizing::new(
+        phrase
+            .split_whitespace()
+            .enumerate()
+            .map(|(position, word)| {
+                word_index(word).ok_or_else(|| {
+                    anyhow!("Word {} ({:?}) is not in the word list", position + 1, word)
+                })
+            })
+            .collect::<Result<Vec<usize>>>()?,
+    );
+    if indices.is_empty() || !indices.len().is_multiple_of(3) {
+        return Err(anyhow!(
+            "Expected a multiple of 3 words, got {}",
+            indices.len()
+        ));
+    }
+
+    // 33 bits for every 32 bits of data
+    let total_bits = indices.len() * BITS_PER_WORD;
+    let data_len = total_bits * 32 / 33 / 8;
+    let bit = |i: usize| indices[i / BITS_PER_WORD] >> (BITS_PER_WORD - 1 - i % BITS_PER_WORD) & 1;
+
+    let mut data = Zeroizing::new(vec![0u8; data_len]);
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte = (0..8).fold(0, |acc, b| acc << 1 | bit(i * 8 + b) as u8);
+    }
+
+    let checksum = Sha256::digest(data.as_slice());
+    let intact = (0..data_len / 4)
+        .all(|i| bit(data_len * 8 + i) == usize::from(checksum[i / 8] >> (7 - i % 8) & 1));
+    if !intact {
+        return Err(anyhow!(
+            "The words do not form a valid backup: checksum mismatch. Check for a mistyped or swapped word."
+        ));
+    }
+    Ok(data)
+}
+
+fn word_index(word: &str) -> Option<usize> {
+    let word = word.to_ascii_lowercase();
+    if let Ok(index) = WORDS.binary_search(&word.as_str()) {
+        return Some(index);
+    }
+    if word.len() < PREFIX_LEN {
+        return None;
+    }
+    let mut matches = WORDS
+        .iter()
+        .enumerate()
+        .filter(|(_, candidate)| candidate.starts_with(word.as_str()));
+    match (matches.next(), matches.next()) {
+        (Some((index, _)), None) => Some(index),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bip39_vectors() {
+        assert_eq!(WORDS.len(), 2048);
+
+        // From the BIP39 reference test vectors
+        let phrase = encode(&[0u8; 32]).unwrap();
+        assert_eq!(phrase.as_str(), format!("{}art", "abandon ".repeat(23)));
+        let phrase = encode(&[0x7f; 16]).unwrap();
+        assert_eq!(
+            phrase.as_str(),
+            "legal winner thank year wave sausage worth useful legal winner thank yellow"
+        );
+    }
+
+    #[test]
+    fn test_roundtrip_and_abbreviations() {
+        for len in [16, 32, 48, 64, 80] {
+            let data: Vec<u8> = (0..len as u8).map(|b| b.wrapping_mul(37)).collect();
+            let phrase = encode(&data).unwrap();
+            assert_eq!(phrase.split(' ').count(), len * 8 * 33 / 32 / 11);
+            assert_eq!(*decode(&phrase).unwrap(), data);
+
+            let abbreviated: Vec<String> = phrase
+                .split(' ')
+                .map(|word| {
+                    word.chars()
+                        .take(PREFIX_LEN)
+                        .collect::<String>()
+                        .to_uppercase()
+                })
+                .collect();
+            assert_eq!(*decode(&abbreviated.join("\n")).unwrap(), data);
+        }
+        assert!(encode(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_mistakes_detected() {
+        let phrase = encode(&[42u8; 32]).unwrap();
+        let mut words: Vec<&str> = phrase.split(' ').collect();
+
+        words.swap(3, 4);
+        assert!(decode(&words.join(" ")).is_err());
+        words.swap(3, 4);
+
+        words[0] = "notaword";
+        let error = decode(&words.join(" ")).unwrap_err().to_string();
+        assert!(error.contains("Word 1"), "{error}");
+
+        assert!(decode(&words[1..].join(" ")).is_err());
+    }
+}
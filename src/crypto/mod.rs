@@ -1,11 +1,30 @@
+mod armor;
 pub mod asymmetric;
+pub mod certification;
+mod interop;
+pub mod keyfile;
 pub mod keys;
+pub mod mnemonic;
+pub mod revocation;
+pub mod secret;
+pub mod shamir;
 pub mod signing;
 pub mod symmetric;
+pub mod threshold;
 
-pub use asymmetric::{decrypt_asymmetric, encrypt_asymmetric};
+pub use asymmetric::{
+    AsymmetricLayout, Credential, EnvelopeOptions, KekDerivation, Recipient, SlotType,
+    decrypt_asymmetric, decrypt_combined, decrypt_envelope, encrypt_asymmetric, encrypt_to_slots,
+    partial_decrypt,
+};
+pub use certification::Certification;
+pub use keyfile::Keyfile;
 #[allow(unused_imports)]
 pub use keys::Keypair;
-pub use keys::{PrivateKey, PublicKey};
+pub use keys::{KeyFormat, PrivateKey, PublicKey};
+pub use revocation::{Revocation, RevocationReason};
+pub use secret::{SecretBytes, SecretString};
+pub use shamir::KeyShare;
 pub use signing::{sign_message, verify_signature};
-pub use symmetric::{decrypt_symmetric, encrypt_symmetric};
+pub use symmetric::{KdfParams, KdfProfile, SymmetricLayout, decrypt_symmetric, encrypt_symmetric};
+pub use threshold::PartialDecryption;
@@ -0,0 +1,225 @@
+//! Revocation certificates: a key's own signed statement that it must no
+//! longer be used.
+
+use super::armor::{armor, dearmor};
+use super::keys::{KEY_ID_SIZE, PrivateKey, PublicKey, unix_now};
+use super::signing::SIGNATURE_SIZE;
+use anyhow::{Context, Result, anyhow};
+use ed25519_dalek::{Signature, Signer, Verifier, VerifyingKey};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use x25519_dalek::PublicKey as X25519Public;
+
+const REVOCATION_LABEL: &str = "REVOCATION";
+
+const REVOCATION_DOMAIN: &[u8] = b"zimhide revocation v1";
+const REVOCATION_VERSION: u8 = 1;
+/// Version, both public halves, revocation time and reason.
+const SIGNED_SIZE: usize = 1 + 32 + 32 + 8 + 1;
+/// Size of an encoded certificate.
+pub const REVOCATION_SIZE: usize = SIGNED_SIZE + SIGNATURE_SIZE;
+
+/// Why a key was revoked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum RevocationReason {
+    /// No reason given
+    #[default]
+    Unspecified,
+    /// The private key was lost or exposed
+    Compromised,
+    /// Replaced by a new key
+    Superseded,
+    /// No longer used
+    Retired,
+}
+
+impl RevocationReason {
+    fn id(self) -> u8 {
+        match self {
+            Self::Unspecified => 0,
+            Self::Compromised => 1,
+            Self::Superseded => 2,
+            Self::Retired => 3,
+        }
+    }
+
+    /// Unknown reasons read as unspecified, so new ones can be added.
+    fn from_id(id: u8) -> Self {
+        match id {
+            1 => Self::Compromised,
+            2 => Self::Superseded,
+            3 => Self::Retired,
+            _ => Self::Unspecified,
+        }
+    }
+}
+
+impl fmt::Display for RevocationReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Unspecified => "no reason given",
+            Self::Compromised => "compromised",
+            Self::Superseded => "superseded",
+            Self::Retired => "retired",
+        })
+    }
+}
+
+/// A signed statement that `key` was revoked at `revoked`.
+#[derive(Clone)]
+pub struct Revocation {
+    pub key: PublicKey,
+    /// Seconds since the Unix epoch
+    pub revoked: u64,
+    pub reason: RevocationReason,
+    signature: [u8; SIGNATURE_SIZE],
+}
+
+impl Revocation {
+    /// Revoke `private_key` as of now. Only keys that can sign can be revoked.
+    pub fn create(private_key: &PrivateKey, reason: RevocationReason) -> Result<Self> {
+        let signing_key = private_key.signing_key()?;
+        let mut revocation = Self {
+            key: PublicKey {
+                validity: None,
+                ..private_key.public_key()
+            },
+            revoked: unix_now(),
+            reason,
+            signature: [0u8; SIGNATURE_SIZE],
+        };
+        revocation.signature = signing_key.sign(&revocation.signed_message()).to_bytes();
+        Ok(revocation)
+    }
+
+    pub fn key_id(&self) -> [u8; KEY_ID_SIZE] {
+        self.key.key_id()
+    }
+
+    fn signed_bytes(&self) -> [u8; SIGNED_SIZE] {
+        let ed25519 = self.key.ed25519.expect("revoked keys have an Ed25519 half");
+        let mut bytes = [0u8; SIGNED_SIZE];
+        bytes[0] = REVOCATION_VERSION;
+        bytes[1..33].copy_from_slice(ed25519.as_bytes());
+        bytes[33..65].copy_from_slice(self.key.x25519.as_bytes());
+        bytes[65..73].copy_from_slice(&self.revoked.to_le_bytes());
+        bytes[73] = self.reason.id();
+        bytes
+    }
+
+    fn signed_message(&self) -> Vec<u8> {
+        let mut message = REVOCATION_DOMAIN.to_vec();
+        message.extend_from_slice(&self.signed_bytes());
+        message
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(REVOCATION_SIZE);
+        bytes.extend_from_slice(&self.signed_bytes());
+        bytes.extend_from_slice(&self.signature);
+        bytes
+    }
+
+    /// Parse a certificate, checking that the revoked key signed it.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != REVOCATION_SIZE {
+            return Err(anyhow!(
+                "Invalid revocation certificate: expected {} bytes, got {}",
+                REVOCATION_SIZE,
+                bytes.len()
+            ));
+        }
+        if bytes[0] != REVOCATION_VERSION {
+            return Err(anyhow!(
+                "Unsupported revocation certificate version {}",
+                bytes[0]
+            ));
+        }
+
+        let ed25519_bytes: [u8; 32] = bytes[1..33].try_into().expect("length validated");
+        let ed25519 = VerifyingKey::from_bytes(&ed25519_bytes)
+            .map_err(|e| anyhow!("Invalid key in revocation certificate: {}", e))?;
+        let x25519_bytes: [u8; 32] = bytes[33..65].try_into().expect("length validated");
+        let revocation = Self {
+            key: PublicKey {
+                ed25519: Some(ed25519),
+                x25519: X25519Public::from(x25519_bytes),
+                validity: None,
+            },
+            revoked: u64::from_le_bytes(bytes[65..73].try_into().expect("length validated")),
+            reason: RevocationReason::from_id(bytes[73]),
+            signature: bytes[SIGNED_SIZE..].try_into().expect("length validated"),
+        };
+
+        ed25519
+            .verify(
+                &revocation.signed_message(),
+                &Signature::from_bytes(&revocation.signature),
+            )
+            .map_err(|_| {
+                anyhow!(
+                    "Invalid revocation certificate: not signed by key {}",
+                    revocation.key.fingerprint()
+                )
+            })?;
+        Ok(revocation)
+    }
+
+    pub fn to_pem(&self) -> String {
+        armor(REVOCATION_LABEL, &self.to_bytes()).to_string()
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        Self::from_bytes(&dearmor(content, REVOCATION_LABEL)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).with_context(|| {
+            format!("Failed to read revocation certificate: {}", path.display())
+        })?;
+        Self::parse(&content).with_context(|| path.display().to_string())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_pem())
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::keys::Keypair;
+
+    #[test]
+    fn test_revocation_roundtrip() {
+        let keypair = Keypair::generate();
+        let revocation =
+            Revocation::create(&keypair.private, RevocationReason::Compromised).unwrap();
+
+        let parsed = Revocation::parse(&revocation.to_pem()).unwrap();
+        assert_eq!(parsed.key_id(), keypair.public.key_id());
+        assert_eq!(parsed.reason, RevocationReason::Compromised);
+        assert_eq!(parsed.revoked, revocation.revoked);
+    }
+
+    #[test]
+    fn test_forged_revocation_rejected() {
+        let keypair = Keypair::generate();
+        let mut bytes = Revocation::create(&keypair.private, RevocationReason::Retired)
+            .unwrap()
+            .to_bytes();
+
+        // Backdating breaks the signature
+        bytes[65] ^= 1;
+        assert!(Revocation::from_bytes(&bytes).is_err());
+
+        // So does claiming another key was revoked
+        let mut bytes = Revocation::create(&keypair.private, RevocationReason::Retired)
+            .unwrap()
+            .to_bytes();
+        bytes[33..65].copy_from_slice(Keypair::generate().public.x25519.as_bytes());
+        assert!(Revocation::from_bytes(&bytes).is_err());
+    }
+}
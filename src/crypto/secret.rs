@@ -0,0 +1,177 @@
+//! Containers that wipe keys, passphrases and plaintext when dropped.
+
+use chacha20poly1305::aead::{self, AeadInPlace, Nonce, Tag, generic_array::typenum::Unsigned};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+pub use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// A passphrase, wiped on drop.
+pub type SecretString = Zeroizing<String>;
+
+/// A 256-bit cipher key, wiped on drop.
+pub type SecretKey = Zeroizing<[u8; 32]>;
+
+/// A fixed-size heap buffer for decrypted data. Its pages are locked into RAM
+/// where the OS allows it, so they are not written to swap, and the contents
+/// are wiped before the memory is released.
+///
+/// Locking is best effort: it fails quietly when the buffer exceeds
+/// `RLIMIT_MEMLOCK` or the platform has no `mlock`.
+pub struct SecretBytes {
+    bytes: Box<[u8]>,
+    lock: Option<region::LockGuard>,
+}
+
+impl SecretBytes {
+    /// A zero-filled buffer of `len` bytes.
+    pub fn zeroed(len: usize) -> Self {
+        let bytes = vec![0u8; len].into_boxed_slice();
+        let lock = if len > 0 {
+            region::lock(bytes.as_ptr(), len).ok()
+        } else {
+            None
+        };
+        Self { bytes, lock }
+    }
+
+    /// Whether the buffer's pages are locked into RAM.
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_some()
+    }
+}
+
+impl From<&[u8]> for SecretBytes {
+    fn from(data: &[u8]) -> Self {
+        let mut secret = Self::zeroed(data.len());
+        secret.copy_from_slice(data);
+        secret
+    }
+}
+
+impl From<Vec<u8>> for SecretBytes {
+    /// Copy `data` into a locked buffer and wipe the original.
+    fn from(mut data: Vec<u8>) -> Self {
+        let secret = Self::from(data.as_slice());
+        data.zeroize();
+        secret
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl DerefMut for SecretBytes {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+}
+
+impl Zeroize for SecretBytes {
+    fn zeroize(&mut self) {
+        self.bytes.zeroize();
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.zeroize();
+        // Unlock before the allocation is freed
+        self.lock.take();
+    }
+}
+
+impl ZeroizeOnDrop for SecretBytes {}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBytes([REDACTED; {}])", self.bytes.len())
+    }
+}
+
+/// Decrypt `ciphertext ‖ tag` directly into a locked buffer, so the
+/// plaintext never passes through ordinary heap memory.
+pub(crate) fn open<A: AeadInPlace>(
+    cipher: &A,
+    nonce: &Nonce<A>,
+    sealed: &[u8],
+) -> Result<SecretBytes, aead::Error> {
+    let body_len = sealed
+        .len()
+        .checked_sub(A::TagSize::USIZE)
+        .ok_or(aead::Error)?;
+    let (body, tag) = sealed.split_at(body_len);
+
+    let mut plaintext = SecretBytes::from(body);
+    cipher.decrypt_in_place_detached(nonce, b"", &mut plaintext, Tag::<A>::from_slice(tag))?;
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{FileRecord, Payload};
+    use chacha20poly1305::{ChaCha20Poly1305, KeyInit, aead::Aead};
+
+    fn assert_zeroize<T: Zeroize>() {}
+    fn assert_zeroize_on_drop<T: ZeroizeOnDrop>() {}
+
+    #[test]
+    fn test_secret_types_are_wiped() {
+        assert_zeroize::<SecretBytes>();
+        assert_zeroize_on_drop::<SecretBytes>();
+        assert_zeroize_on_drop::<SecretString>();
+        assert_zeroize_on_drop::<SecretKey>();
+        assert_zeroize_on_drop::<ed25519_dalek::SigningKey>();
+        assert_zeroize_on_drop::<Zeroizing<Payload>>();
+        assert_zeroize_on_drop::<ChaCha20Poly1305>();
+    }
+
+    #[test]
+    fn test_payload_zeroize() {
+        let mut payload = Payload {
+            text: Some("plaintext".to_string()),
+            audio: Some(vec![7; 16]),
+            files: vec![FileRecord {
+                name: "notes.txt".to_string(),
+                data: b"attached".to_vec(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        payload.zeroize();
+        assert!(payload.text.is_none());
+        assert!(payload.audio.is_none());
+        assert!(payload.files.is_empty());
+    }
+
+    #[test]
+    fn test_secret_bytes_zeroize() {
+        let mut secret = SecretBytes::from(b"plaintext".to_vec());
+        assert_eq!(&*secret, b"plaintext");
+        secret.zeroize();
+        assert!(secret.iter().all(|&b| b == 0));
+        assert_eq!(secret.len(), 9);
+
+        // Empty buffers are valid and never locked
+        assert!(!SecretBytes::zeroed(0).is_locked());
+    }
+
+    #[test]
+    fn test_open_in_place() {
+        let cipher = ChaCha20Poly1305::new_from_slice(&[9u8; 32]).unwrap();
+        let nonce = Nonce::<ChaCha20Poly1305>::default();
+        let sealed = cipher.encrypt(&nonce, b"in place".as_slice()).unwrap();
+
+        assert_eq!(&*open(&cipher, &nonce, &sealed).unwrap(), b"in place");
+
+        let mut tampered = sealed.clone();
+        tampered[0] ^= 1;
+        assert!(open(&cipher, &nonce, &tampered).is_err());
+        assert!(open(&cipher, &nonce, &sealed[..4]).is_err());
+    }
+}
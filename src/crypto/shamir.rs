@@ -0,0 +1,345 @@
+//! Shamir secret sharing over GF(256), so that any `threshold` of `shares`
+//! holders can rebuild a secret and fewer learn nothing. Used for private key
+//! custody and for threshold-encrypted content keys.
+
+use super::armor::{armor, dearmor};
+use super::keys::{KEY_ID_SIZE, PrivateKey, format_key_id};
+use super::secret::Zeroizing;
+use anyhow::{Context, Result, anyhow};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+const SHARE_LABEL: &str = "KEY SHARE";
+
+const SHARE_VERSION: u8 = 1;
+const SPLIT_ID_SIZE: usize = 16;
+/// Version, split id, threshold, share count, index and key id.
+const SHARE_HEADER_SIZE: usize = 1 + SPLIT_ID_SIZE + 3 + KEY_ID_SIZE;
+/// Truncated SHA-256 over the rest of the share, to catch damaged files.
+const CHECKSUM_SIZE: usize = 4;
+
+/// One holder's part of a split private key.
+pub struct KeyShare {
+    /// Random, shared by every share of one split
+    pub split_id: [u8; SPLIT_ID_SIZE],
+    pub threshold: u8,
+    pub shares: u8,
+    /// Evaluation point, 1 to `shares`
+    pub index: u8,
+    /// Key id of the split key, to confirm the rebuilt key
+    pub key_id: [u8; KEY_ID_SIZE],
+    data: Zeroizing<Vec<u8>>,
+}
+
+impl KeyShare {
+    /// Split `key` into `shares` shares, any `threshold` of which rebuild it.
+    pub fn split(key: &PrivateKey, threshold: u8, shares: u8) -> Result<Vec<Self>> {
+        if threshold < 2 || threshold > shares {
+            return Err(anyhow!(
+                "The threshold must be at least 2 and at most the number of shares ({})",
+                shares
+            ));
+        }
+
+        let secret = key.to_bytes()?;
+        let mut split_id = [0u8; SPLIT_ID_SIZE];
+        OsRng.fill_bytes(&mut split_id);
+        let key_id = key.public_key().key_id();
+
+        let xs: Vec<u8> = (1..=shares).collect();
+        let data = split_secret(&secret, threshold, &xs);
+
+        Ok((1..=shares)
+            .zip(data)
+            .map(|(index, data)| Self {
+                split_id,
+                threshold,
+                shares,
+                index,
+                key_id,
+                data,
+            })
+            .collect())
+    }
+
+    /// Rebuild the key from at least `threshold` shares of the same split.
+    pub fn combine(shares: &[Self]) -> Result<PrivateKey> {
+        let first = shares.first().ok_or_else(|| anyhow!("No shares given"))?;
+        for share in shares {
+            if share.split_id != first.split_id
+                || share.key_id != first.key_id
+                || share.data.len() != first.data.len()
+            {
+                return Err(anyhow!(
+                    "Shares {} and {} come from different splits",
+                    first.index,
+                    share.index
+                ));
+            }
+            if share.threshold != first.threshold || share.shares != first.shares {
+                return Err(anyhow!(
+                    "Shares {} and {} disagree on the split ({} of {} against {} of {}); one of them is damaged",
+                    first.index,
+                    share.index,
+                    first.threshold,
+                    first.shares,
+                    share.threshold,
+                    share.shares
+                ));
+            }
+        }
+
+        let mut chosen: Vec<&Self> = Vec::new();
+        for share in shares {
+            if chosen.iter().any(|known| known.index == share.index) {
+                return Err(anyhow!("Share {} was given more than once", share.index));
+            }
+            chosen.push(share);
+        }
+        if chosen.len() < usize::from(first.threshold) {
+            return Err(anyhow!(
+                "Need {} of the {} shares to rebuild key {}, got {}",
+                first.threshold,
+                first.shares,
+                format_key_id(&first.key_id),
+                chosen.len()
+            ));
+        }
+        chosen.truncate(usize::from(first.threshold));
+
+        let points: Vec<(u8, &[u8])> = chosen
+            .iter()
+            .map(|share| (share.index, share.data.as_slice()))
+            .collect();
+        let secret = combine_secret(&points);
+
+        let key = PrivateKey::from_bytes(&secret, Path::new("combined shares"))?;
+        if key.public_key().key_id() != first.key_id {
+            return Err(anyhow!(
+                "The shares rebuilt a different key than {}; one of them is damaged",
+                format_key_id(&first.key_id)
+            ));
+        }
+        Ok(key)
+    }
+
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut bytes = Zeroizing::new(Vec::with_capacity(
+            SHARE_HEADER_SIZE + self.data.len() + CHECKSUM_SIZE,
+        ));
+        bytes.push(SHARE_VERSION);
+        bytes.extend_from_slice(&self.split_id);
+        bytes.extend_from_slice(&[self.threshold, self.shares, self.index]);
+        bytes.extend_from_slice(&self.key_id);
+        bytes.extend_from_slice(&self.data);
+        let checksum = Sha256::digest(bytes.as_slice());
+        bytes.extend_from_slice(&checksum[..CHECKSUM_SIZE]);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() <= SHARE_HEADER_SIZE + CHECKSUM_SIZE {
+            return Err(anyhow!("Key share is truncated"));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+        if Sha256::digest(body)[..CHECKSUM_SIZE] != *checksum {
+            return Err(anyhow!("Key share is damaged: checksum mismatch"));
+        }
+        if body[0] != SHARE_VERSION {
+            return Err(anyhow!("Unsupported key share version {}", body[0]));
+        }
+
+        let share = Self {
+            split_id: body[1..17].try_into().expect("length validated"),
+            threshold: body[17],
+            shares: body[18],
+            index: body[19],
+            key_id: body[20..SHARE_HEADER_SIZE]
+                .try_into()
+                .expect("length validated"),
+            data: Zeroizing::new(body[SHARE_HEADER_SIZE..].to_vec()),
+        };
+        if share.threshold < 2
+            || share.threshold > share.shares
+            || share.index == 0
+            || share.index > share.shares
+        {
+            return Err(anyhow!(
+                "Key share has an invalid threshold or index ({} of {}, share {})",
+                share.threshold,
+                share.shares,
+                share.index
+            ));
+        }
+        Ok(share)
+    }
+
+    pub fn to_pem(&self) -> Zeroizing<String> {
+        armor(SHARE_LABEL, &self.to_bytes())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = Zeroizing::new(
+            fs::read_to_string(path)
+                .with_context(|| format!("Failed to read key share: {}", path.display()))?,
+        );
+        dearmor(&content, SHARE_LABEL)
+            .and_then(|bytes| Self::from_bytes(&bytes))
+            .with_context(|| path.display().to_string())
+    }
+}
+
+/// Split `secret` byte by byte into one share per nonzero point in `xs`, any
+/// `threshold` of which rebuild it.
+pub(crate) fn split_secret(secret: &[u8], threshold: u8, xs: &[u8]) -> Vec<Zeroizing<Vec<u8>>> {
+    let mut shares: Vec<Zeroizing<Vec<u8>>> = xs
+        .iter()
+        .map(|_| Zeroizing::new(Vec::with_capacity(secret.len())))
+        .collect();
+    // A random polynomial per byte whose constant term is the secret byte
+    let mut coefficients = Zeroizing::new(vec![0u8; usize::from(threshold)]);
+    for &byte in secret {
+        coefficients[0] = byte;
+        OsRng.fill_bytes(&mut coefficients[1..]);
+        for (&x, share) in xs.iter().zip(shares.iter_mut()) {
+            // Horner's rule
+            let y = coefficients
+                .iter()
+                .rev()
+                .fold(0, |acc, &coefficient| gf_mul(acc, x) ^ coefficient);
+            share.push(y);
+        }
+    }
+    shares
+}
+
+/// Rebuild a secret from `(x, share)` points with distinct x by Lagrange
+/// interpolation at x = 0. With fewer points than the threshold the result
+/// is unrelated to the secret.
+pub(crate) fn combine_secret(points: &[(u8, &[u8])]) -> Zeroizing<Vec<u8>> {
+    let len = points.first().map_or(0, |(_, share)| share.len());
+    let mut secret = Zeroizing::new(vec![0u8; len]);
+    for (i, &(x, share)) in points.iter().enumerate() {
+        // Subtraction is XOR in GF(256)
+        let basis = points
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != i)
+            .fold(1, |acc, (_, &(other, _))| {
+                gf_mul(acc, gf_div(other, other ^ x))
+            });
+        for (byte, &y) in secret.iter_mut().zip(share) {
+            *byte ^= gf_mul(y, basis);
+        }
+    }
+    secret
+}
+
+/// Multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x + 1, without
+/// branching on the operands.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & (b & 1).wrapping_neg();
+        let carry = (a >> 7).wrapping_neg();
+        a = (a << 1) ^ (carry & 0x1b);
+        b >>= 1;
+    }
+    product
+}
+
+/// Division in GF(2^8): `a` times the inverse of `b`, which is `b^254`.
+fn gf_div(a: u8, b: u8) -> u8 {
+    let mut inverse = 1;
+    let mut power = b;
+    for bit in 0..8 {
+        if 254 >> bit & 1 == 1 {
+            inverse = gf_mul(inverse, power);
+        }
+        power = gf_mul(power, power);
+    }
+    gf_mul(a, inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::keys::Keypair;
+
+    #[test]
+    fn test_gf256_arithmetic() {
+        // The AES field: 0x53 and 0xca are inverses
+        assert_eq!(gf_mul(0x53, 0xca), 1);
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(gf_div(1, a), a), 1);
+            assert_eq!(gf_div(gf_mul(a, 0x57), 0x57), a);
+        }
+    }
+
+    #[test]
+    fn test_any_threshold_subset_rebuilds_key() {
+        let keypair = Keypair::generate();
+        let shares = KeyShare::split(&keypair.private, 3, 5).unwrap();
+        let fingerprint = keypair.public.fingerprint();
+
+        for subset in [[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+            let chosen: Vec<KeyShare> = subset
+                .iter()
+                .map(|&i| KeyShare::from_bytes(&shares[i].to_bytes()).unwrap())
+                .collect();
+            let key = KeyShare::combine(&chosen).unwrap();
+            assert_eq!(key.public_key().fingerprint(), fingerprint);
+        }
+    }
+
+    #[test]
+    fn test_bad_share_sets_rejected() {
+        let keypair = Keypair::generate();
+        let reload = |share: &KeyShare| KeyShare::from_bytes(&share.to_bytes()).unwrap();
+        let shares = KeyShare::split(&keypair.private, 3, 5).unwrap();
+        let other = KeyShare::split(&keypair.private, 3, 5).unwrap();
+
+        let error = KeyShare::combine(&[reload(&shares[0]), reload(&shares[1])])
+            .map(|_| ())
+            .unwrap_err();
+        assert!(error.to_string().contains("Need 3"), "{error}");
+
+        let error = KeyShare::combine(&[reload(&shares[0]), reload(&shares[1]), reload(&other[2])])
+            .map(|_| ())
+            .unwrap_err();
+        assert!(error.to_string().contains("different splits"), "{error}");
+
+        let duplicate = [reload(&shares[0]), reload(&shares[0]), reload(&shares[1])];
+        assert!(KeyShare::combine(&duplicate).is_err());
+
+        let mut damaged = shares[0].to_bytes();
+        damaged[SHARE_HEADER_SIZE] ^= 1;
+        assert!(KeyShare::from_bytes(&damaged).is_err());
+
+        // Evaluation points run from 1 to the share count
+        for index in [0, 6] {
+            let mut share = reload(&shares[0]);
+            share.index = index;
+            let error = KeyShare::from_bytes(&share.to_bytes())
+                .map(|_| ())
+                .unwrap_err();
+            assert!(error.to_string().contains("invalid threshold or index"));
+        }
+
+        let mut mismatched = reload(&shares[2]);
+        mismatched.shares = 6;
+        let error = KeyShare::combine(&[reload(&shares[0]), reload(&shares[1]), mismatched])
+            .map(|_| ())
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("disagree on the split"),
+            "{error}"
+        );
+
+        assert!(KeyShare::split(&keypair.private, 1, 5).is_err());
+        assert!(KeyShare::split(&keypair.private, 4, 3).is_err());
+    }
+}
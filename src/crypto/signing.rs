@@ -4,9 +4,9 @@ use ed25519_dalek::{Signature, Signer, Verifier};
 
 pub const SIGNATURE_SIZE: usize = 64;
 
-pub fn sign_message(message: &[u8], private_key: &PrivateKey) -> [u8; SIGNATURE_SIZE] {
-    let signature = private_key.ed25519.sign(message);
-    signature.to_bytes()
+pub fn sign_message(message: &[u8], private_key: &PrivateKey) -> Result<[u8; SIGNATURE_SIZE]> {
+    let signature = private_key.signing_key()?.sign(message);
+    Ok(signature.to_bytes())
 }
 
 pub fn verify_signature(
@@ -16,12 +16,15 @@ pub fn verify_signature(
 ) -> Result<()> {
     let signature = Signature::from_bytes(signature);
 
-    public_key.ed25519.verify(message, &signature).map_err(|_| {
-        anyhow!(
-            "Signature verification failed: message was not signed by key {}",
-            public_key.fingerprint()
-        )
-    })
+    public_key
+        .verifying_key()?
+        .verify(message, &signature)
+        .map_err(|_| {
+            anyhow!(
+                "Signature verification failed: message was not signed by key {}",
+                public_key.fingerprint()
+            )
+        })
 }
 
 #[cfg(test)]
@@ -34,7 +37,7 @@ mod tests {
         let keypair = Keypair::generate();
         let message = b"Important message to sign";
 
-        let signature = sign_message(message, &keypair.private);
+        let signature = sign_message(message, &keypair.private).unwrap();
         verify_signature(message, &signature, &keypair.public).unwrap();
     }
 
@@ -44,7 +47,7 @@ mod tests {
         let keypair2 = Keypair::generate();
         let message = b"Important message";
 
-        let signature = sign_message(message, &keypair1.private);
+        let signature = sign_message(message, &keypair1.private).unwrap();
         let result = verify_signature(message, &signature, &keypair2.public);
 
         assert!(result.is_err());
@@ -56,7 +59,7 @@ mod tests {
         let message = b"Original message";
         let modified = b"Modified message";
 
-        let signature = sign_message(message, &keypair.private);
+        let signature = sign_message(message, &keypair.private).unwrap();
         let result = verify_signature(modified, &signature, &keypair.public);
 
         assert!(result.is_err());
@@ -1,32 +1,202 @@
+use super::secret::{SecretBytes, SecretKey, Zeroizing, open};
 use anyhow::{Result, anyhow};
-use argon2::{
-    Argon2, PasswordHasher,
-    password_hash::{SaltString, rand_core::OsRng},
-};
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version, password_hash::SaltString};
 use chacha20poly1305::{
     ChaCha20Poly1305, Nonce,
     aead::{Aead, KeyInit},
 };
 use rand::RngCore;
+use std::fmt;
+use std::time::{Duration, Instant};
 
 const NONCE_SIZE: usize = 12;
 const KEY_SIZE: usize = 32;
+const SALT_SIZE: usize = 16;
+pub(crate) const PARAMS_SIZE: usize = 9; // memory (u32) + iterations (u32) + parallelism (u8)
 
-pub fn encrypt_symmetric(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>> {
-    // Generate salt
-    let salt = SaltString::generate(&mut OsRng);
-    let salt_bytes = salt.as_str().as_bytes();
+/// First container format version whose symmetric envelope records its
+/// Argon2id parameters.
+pub const KDF_PARAMS_FORMAT_VERSION: u8 = 6;
 
-    // Derive key using Argon2id
-    let argon2 = Argon2::default();
-    let hash = argon2
-        .hash_password(passphrase.as_bytes(), &salt)
-        .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+/// Argon2id cost parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u8,
+}
 
-    let key_bytes = hash.hash.ok_or_else(|| anyhow!("No hash output"))?;
-    let key: [u8; KEY_SIZE] = key_bytes.as_bytes()[..KEY_SIZE]
-        .try_into()
-        .map_err(|_| anyhow!("Invalid key length"))?;
+impl KdfParams {
+    /// The `argon2` crate defaults, used by every format version before 6.
+    pub const INTERACTIVE: Self = Self {
+        memory_kib: 19 * 1024,
+        iterations: 2,
+        parallelism: 1,
+    };
+    pub const MODERATE: Self = Self {
+        memory_kib: 256 * 1024,
+        iterations: 3,
+        parallelism: 1,
+    };
+    pub const SENSITIVE: Self = Self {
+        memory_kib: 1024 * 1024,
+        iterations: 4,
+        parallelism: 1,
+    };
+
+    /// Limits on parameters read from untrusted files. No more than the
+    /// sensitive profile costs, so a crafted file cannot make a reader
+    /// allocate or compute much beyond what a real encoder would ask for.
+    pub const MAX_MEMORY_KIB: u32 = Self::SENSITIVE.memory_kib;
+    pub const MAX_ITERATIONS: u32 = 16;
+    pub const MAX_PARALLELISM: u8 = 16;
+    /// Memory times iterations, in KiB passes.
+    pub const MAX_COST: u64 = Self::SENSITIVE.memory_kib as u64 * Self::SENSITIVE.iterations as u64;
+
+    /// The most iterations allowed with `memory_kib` of memory.
+    pub fn max_iterations(memory_kib: u32) -> u32 {
+        let by_cost = Self::MAX_COST / u64::from(memory_kib.max(1));
+        Self::MAX_ITERATIONS.min(u32::try_from(by_cost).unwrap_or(u32::MAX))
+    }
+
+    /// Reject parameters that could exhaust memory or CPU, or that Argon2 refuses.
+    pub fn check_limits(&self) -> Result<()> {
+        if self.memory_kib > Self::MAX_MEMORY_KIB {
+            return Err(anyhow!(
+                "Key derivation memory of {} MiB exceeds the limit of {} MiB",
+                self.memory_kib / 1024,
+                Self::MAX_MEMORY_KIB / 1024
+            ));
+        }
+        if self.iterations > Self::MAX_ITERATIONS {
+            return Err(anyhow!(
+                "Key derivation iterations ({}) exceed the limit of {}",
+                self.iterations,
+                Self::MAX_ITERATIONS
+            ));
+        }
+        if self.iterations > Self::max_iterations(self.memory_kib) {
+            return Err(anyhow!(
+                "Key derivation cost of {} iterations over {} MiB exceeds the limit of {} iterations at that memory",
+                self.iterations,
+                self.memory_kib / 1024,
+                Self::max_iterations(self.memory_kib)
+            ));
+        }
+        if self.parallelism > Self::MAX_PARALLELISM {
+            return Err(anyhow!(
+                "Key derivation parallelism ({}) exceeds the limit of {}",
+                self.parallelism,
+                Self::MAX_PARALLELISM
+            ));
+        }
+        self.argon2().map(|_| ())
+    }
+
+    fn argon2(&self) -> Result<Argon2<'static>> {
+        let params = Params::new(
+            self.memory_kib,
+            self.iterations,
+            u32::from(self.parallelism),
+            Some(KEY_SIZE),
+        )
+        .map_err(|e| anyhow!("Invalid key derivation parameters: {}", e))?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    pub(crate) fn derive_key(&self, passphrase: &str, salt: &[u8]) -> Result<SecretKey> {
+        let mut key = Zeroizing::new([0u8; KEY_SIZE]);
+        self.argon2()?
+            .hash_password_into(passphrase.as_bytes(), salt, key.as_mut_slice())
+            .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+        Ok(key)
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; PARAMS_SIZE] {
+        let mut bytes = [0u8; PARAMS_SIZE];
+        bytes[0..4].copy_from_slice(&self.memory_kib.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.iterations.to_le_bytes());
+        bytes[8] = self.parallelism;
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            memory_kib: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            iterations: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            parallelism: bytes[8],
+        }
+    }
+}
+
+impl fmt::Display for KdfParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Argon2id, {} MiB, {} iteration{}, {} lane{}",
+            self.memory_kib / 1024,
+            self.iterations,
+            if self.iterations == 1 { "" } else { "s" },
+            self.parallelism,
+            if self.parallelism == 1 { "" } else { "s" }
+        )
+    }
+}
+
+/// Named Argon2id cost presets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum KdfProfile {
+    /// 19 MiB, 2 iterations: fast enough for everyday use
+    #[default]
+    Interactive,
+    /// 256 MiB, 3 iterations
+    Moderate,
+    /// 1 GiB, 4 iterations: for high-value archives
+    Sensitive,
+}
+
+impl KdfProfile {
+    pub fn params(self) -> KdfParams {
+        match self {
+            Self::Interactive => KdfParams::INTERACTIVE,
+            Self::Moderate => KdfParams::MODERATE,
+            Self::Sensitive => KdfParams::SENSITIVE,
+        }
+    }
+}
+
+/// Layout of a symmetric envelope, selected by the container format version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymmetricLayout {
+    /// Base64 salt string, crate-default Argon2 parameters (versions 1-5)
+    Legacy,
+    /// Argon2id parameters and raw salt stored in the envelope
+    WithKdfParams,
+}
+
+impl SymmetricLayout {
+    /// The layout new envelopes are written with.
+    pub const CURRENT: Self = Self::WithKdfParams;
+
+    pub fn for_format_version(version: u8) -> Self {
+        if version >= KDF_PARAMS_FORMAT_VERSION {
+            Self::WithKdfParams
+        } else {
+            Self::Legacy
+        }
+    }
+}
+
+pub fn encrypt_symmetric(
+    plaintext: &[u8],
+    passphrase: &str,
+    params: &KdfParams,
+) -> Result<Vec<u8>> {
+    params.check_limits()?;
+
+    let mut salt = [0u8; SALT_SIZE];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let key = params.derive_key(passphrase, &salt)?;
 
     // Generate nonce
     let mut nonce_bytes = [0u8; NONCE_SIZE];
@@ -34,28 +204,101 @@ pub fn encrypt_symmetric(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>>
     let nonce = Nonce::from_slice(&nonce_bytes);
 
     // Encrypt
-    let cipher = ChaCha20Poly1305::new_from_slice(&key)
+    let cipher = ChaCha20Poly1305::new_from_slice(key.as_slice())
         .map_err(|e| anyhow!("Cipher creation failed: {}", e))?;
 
     let ciphertext = cipher
         .encrypt(nonce, plaintext)
         .map_err(|e| anyhow!("Encryption failed: {}", e))?;
 
-    // Format: salt_length (1 byte) + salt + nonce + ciphertext
-    let mut output = Vec::with_capacity(1 + salt_bytes.len() + NONCE_SIZE + ciphertext.len());
-    output.push(salt_bytes.len() as u8);
-    output.extend_from_slice(salt_bytes);
+    // Format: params + salt + nonce + ciphertext
+    let mut output = Vec::with_capacity(PARAMS_SIZE + SALT_SIZE + NONCE_SIZE + ciphertext.len());
+    output.extend_from_slice(&params.to_bytes());
+    output.extend_from_slice(&salt);
     output.extend_from_slice(&nonce_bytes);
     output.extend_from_slice(&ciphertext);
 
     Ok(output)
 }
 
-pub fn decrypt_symmetric(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+pub fn decrypt_symmetric(
+    data: &[u8],
+    passphrase: &str,
+    layout: SymmetricLayout,
+) -> Result<SecretBytes> {
     if data.is_empty() {
         return Err(anyhow!("Cannot decrypt: ciphertext is empty"));
     }
 
+    let (key, rest) = match layout {
+        SymmetricLayout::Legacy => derive_legacy_key(data, passphrase)?,
+        SymmetricLayout::WithKdfParams => {
+            let min_size = PARAMS_SIZE + SALT_SIZE + NONCE_SIZE + 16; // 16 is auth tag
+            if data.len() < min_size {
+                return Err(anyhow!(
+                    "Ciphertext too short: expected at least {} bytes, got {}",
+                    min_size,
+                    data.len()
+                ));
+            }
+            let params = KdfParams::from_bytes(&data[..PARAMS_SIZE]);
+            params.check_limits()?;
+            let salt = &data[PARAMS_SIZE..PARAMS_SIZE + SALT_SIZE];
+            (
+                params.derive_key(passphrase, salt)?,
+                &data[PARAMS_SIZE + SALT_SIZE..],
+            )
+        }
+    };
+
+    let nonce = Nonce::from_slice(&rest[..NONCE_SIZE]);
+    let ciphertext = &rest[NONCE_SIZE..];
+
+    // Decrypt
+    let cipher = ChaCha20Poly1305::new_from_slice(key.as_slice())
+        .map_err(|e| anyhow!("Cipher creation failed: {}", e))?;
+
+    open(&cipher, nonce, ciphertext)
+        .map_err(|_| anyhow!("Decryption failed: wrong passphrase or corrupted data"))
+}
+
+/// Argon2id parameters recorded in a symmetric envelope.
+pub fn kdf_params(data: &[u8], layout: SymmetricLayout) -> Option<KdfParams> {
+    match layout {
+        SymmetricLayout::Legacy => Some(KdfParams::INTERACTIVE),
+        SymmetricLayout::WithKdfParams => {
+            (data.len() >= PARAMS_SIZE).then(|| KdfParams::from_bytes(&data[..PARAMS_SIZE]))
+        }
+    }
+}
+
+/// Find the iteration count that makes Argon2id take about `target` with
+/// `memory_kib` of memory on this machine.
+pub fn calibrate(memory_kib: u32, target: Duration) -> Result<(KdfParams, Duration)> {
+    let mut params = KdfParams {
+        memory_kib,
+        iterations: 1,
+        parallelism: 1,
+    };
+    params.check_limits()?;
+
+    let salt = [0u8; SALT_SIZE];
+    let time = |params: &KdfParams| -> Result<Duration> {
+        let start = Instant::now();
+        params.derive_key("calibration", &salt)?;
+        Ok(start.elapsed())
+    };
+
+    let single = time(&params)?;
+    let estimate = target.as_secs_f64() / single.as_secs_f64().max(f64::EPSILON);
+    params.iterations = (estimate.round() as u32).clamp(1, KdfParams::max_iterations(memory_kib));
+
+    Ok((params, time(&params)?))
+}
+
+/// Key derivation for the pre-version-6 layout: salt_length + base64 salt,
+/// hashed with `Argon2::default()`. Returns the key and the remaining bytes.
+fn derive_legacy_key<'a>(data: &'a [u8], passphrase: &str) -> Result<(SecretKey, &'a [u8])> {
     let salt_len = data[0] as usize;
     let min_size = 1 + salt_len + NONCE_SIZE + 16; // 16 is auth tag
     if data.len() < min_size {
@@ -70,54 +313,110 @@ pub fn decrypt_symmetric(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
     let salt_str = std::str::from_utf8(salt_bytes)?;
     let salt = SaltString::from_b64(salt_str).map_err(|e| anyhow!("Invalid salt: {}", e))?;
 
-    let nonce_start = 1 + salt_len;
-    let nonce_bytes = &data[nonce_start..nonce_start + NONCE_SIZE];
-    let nonce = Nonce::from_slice(nonce_bytes);
-
-    let ciphertext = &data[nonce_start + NONCE_SIZE..];
-
-    // Derive key
     let argon2 = Argon2::default();
     let hash = argon2
         .hash_password(passphrase.as_bytes(), &salt)
         .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
 
     let key_bytes = hash.hash.ok_or_else(|| anyhow!("No hash output"))?;
-    let key: [u8; KEY_SIZE] = key_bytes.as_bytes()[..KEY_SIZE]
-        .try_into()
-        .map_err(|_| anyhow!("Invalid key length"))?;
-
-    // Decrypt
-    let cipher = ChaCha20Poly1305::new_from_slice(&key)
-        .map_err(|e| anyhow!("Cipher creation failed: {}", e))?;
+    let key = Zeroizing::new(
+        key_bytes.as_bytes()[..KEY_SIZE]
+            .try_into()
+            .map_err(|_| anyhow!("Invalid key length"))?,
+    );
 
-    let plaintext = cipher
-        .decrypt(nonce, ciphertext)
-        .map_err(|_| anyhow!("Decryption failed: wrong passphrase or corrupted data"))?;
-
-    Ok(plaintext)
+    Ok((key, &data[1 + salt_len..]))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use argon2::password_hash::rand_core::OsRng;
+
+    /// Cheap parameters so tests stay fast.
+    const TEST_PARAMS: KdfParams = KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
 
     #[test]
     fn test_symmetric_roundtrip() {
         let plaintext = b"Hello, world! This is a secret message.";
         let passphrase = "my_secure_passphrase";
 
-        let encrypted = encrypt_symmetric(plaintext, passphrase).unwrap();
-        let decrypted = decrypt_symmetric(&encrypted, passphrase).unwrap();
+        let encrypted = encrypt_symmetric(plaintext, passphrase, &TEST_PARAMS).unwrap();
+        let decrypted =
+            decrypt_symmetric(&encrypted, passphrase, SymmetricLayout::CURRENT).unwrap();
 
-        assert_eq!(plaintext.as_slice(), decrypted.as_slice());
+        assert_eq!(plaintext.as_slice(), &*decrypted);
+        assert_eq!(
+            kdf_params(&encrypted, SymmetricLayout::CURRENT),
+            Some(TEST_PARAMS)
+        );
     }
 
     #[test]
     fn test_wrong_passphrase() {
         let plaintext = b"Secret data";
-        let encrypted = encrypt_symmetric(plaintext, "correct").unwrap();
-        let result = decrypt_symmetric(&encrypted, "wrong");
+        let encrypted = encrypt_symmetric(plaintext, "correct", &TEST_PARAMS).unwrap();
+        let result = decrypt_symmetric(&encrypted, "wrong", SymmetricLayout::CURRENT);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_legacy_layout_decrypts() {
+        // Build a pre-version-6 envelope the way older releases did
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(b"legacy", &salt)
+            .unwrap()
+            .hash
+            .unwrap();
+        let cipher = ChaCha20Poly1305::new_from_slice(&hash.as_bytes()[..KEY_SIZE]).unwrap();
+        let nonce = [3u8; NONCE_SIZE];
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), b"old message".as_slice())
+            .unwrap();
+
+        let mut data = vec![salt.as_str().len() as u8];
+        data.extend_from_slice(salt.as_str().as_bytes());
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+
+        let decrypted = decrypt_symmetric(&data, "legacy", SymmetricLayout::Legacy).unwrap();
+        assert_eq!(&*decrypted, b"old message");
+    }
+
+    #[test]
+    fn test_untrusted_params_capped() {
+        let mut encrypted = encrypt_symmetric(b"data", "pass", &TEST_PARAMS).unwrap();
+        // Claim 4 GiB of memory, after the kind byte
+        encrypted[1..5].copy_from_slice(&(4 * 1024 * 1024u32).to_le_bytes());
+        let err = decrypt_symmetric(&encrypted, "pass", SymmetricLayout::CURRENT)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("exceeds the limit"), "{err}");
+
+        let too_many = KdfParams {
+            iterations: KdfParams::MAX_ITERATIONS + 1,
+            ..TEST_PARAMS
+        };
+        assert!(encrypt_symmetric(b"data", "pass", &too_many).is_err());
+
+        // Every profile fits, but not more work than the sensitive one
+        for profile in [
+            KdfProfile::Interactive,
+            KdfProfile::Moderate,
+            KdfProfile::Sensitive,
+        ] {
+            profile.params().check_limits().unwrap();
+        }
+        let too_costly = KdfParams {
+            iterations: KdfParams::SENSITIVE.iterations + 1,
+            ..KdfParams::SENSITIVE
+        };
+        let err = too_costly.check_limits().unwrap_err().to_string();
+        assert!(err.contains("exceeds the limit of 4 iterations"), "{err}");
+    }
 }
@@ -0,0 +1,110 @@
+//! Partial decryptions: one recipient's share of the content key of a
+//! threshold-encrypted message, handed to whoever combines them.
+
+use super::armor::{armor, dearmor};
+use super::secret::{SecretKey, Zeroizing};
+use anyhow::{Context, Result, anyhow};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+const PARTIAL_LABEL: &str = "PARTIAL DECRYPTION";
+
+const PARTIAL_VERSION: u8 = 1;
+const MESSAGE_DOMAIN: &[u8] = b"zimhide threshold message v1";
+pub const MESSAGE_ID_SIZE: usize = 16;
+const SHARE_SIZE: usize = 32;
+const CHECKSUM_SIZE: usize = 4;
+/// Version, message id, threshold, index, share and checksum.
+const PARTIAL_SIZE: usize = 1 + MESSAGE_ID_SIZE + 2 + SHARE_SIZE + CHECKSUM_SIZE;
+
+/// A recipient's share of a message's content key.
+pub struct PartialDecryption {
+    /// Identifies the message the share belongs to
+    pub message_id: [u8; MESSAGE_ID_SIZE],
+    /// Shares needed to rebuild the content key
+    pub threshold: u8,
+    /// Evaluation point: the key slot's position, from 1
+    pub index: u8,
+    pub(super) share: SecretKey,
+}
+
+impl PartialDecryption {
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut bytes = Zeroizing::new(Vec::with_capacity(PARTIAL_SIZE));
+        bytes.push(PARTIAL_VERSION);
+        bytes.extend_from_slice(&self.message_id);
+        bytes.extend_from_slice(&[self.threshold, self.index]);
+        bytes.extend_from_slice(self.share.as_slice());
+        let checksum = Sha256::digest(bytes.as_slice());
+        bytes.extend_from_slice(&checksum[..CHECKSUM_SIZE]);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != PARTIAL_SIZE {
+            return Err(anyhow!(
+                "Invalid partial decryption: expected {} bytes, got {}",
+                PARTIAL_SIZE,
+                bytes.len()
+            ));
+        }
+        let (body, checksum) = bytes.split_at(PARTIAL_SIZE - CHECKSUM_SIZE);
+        if Sha256::digest(body)[..CHECKSUM_SIZE] != *checksum {
+            return Err(anyhow!("Partial decryption is damaged: checksum mismatch"));
+        }
+        if body[0] != PARTIAL_VERSION {
+            return Err(anyhow!(
+                "Unsupported partial decryption version {}",
+                body[0]
+            ));
+        }
+
+        let (threshold, index) = (body[MESSAGE_ID_SIZE + 1], body[MESSAGE_ID_SIZE + 2]);
+        // Threshold envelopes need two slots; evaluation points start at 1
+        if threshold < 2 || index == 0 {
+            return Err(anyhow!(
+                "Partial decryption has an invalid threshold or index ({} needed, slot {})",
+                threshold,
+                index
+            ));
+        }
+
+        let mut share = Zeroizing::new([0u8; SHARE_SIZE]);
+        share.copy_from_slice(&body[MESSAGE_ID_SIZE + 3..]);
+        Ok(Self {
+            message_id: body[1..=MESSAGE_ID_SIZE]
+                .try_into()
+                .expect("length validated"),
+            threshold,
+            index,
+            share,
+        })
+    }
+
+    pub fn to_pem(&self) -> Zeroizing<String> {
+        armor(PARTIAL_LABEL, &self.to_bytes())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content =
+            Zeroizing::new(fs::read_to_string(path).with_context(|| {
+                format!("Failed to read partial decryption: {}", path.display())
+            })?);
+        dearmor(&content, PARTIAL_LABEL)
+            .and_then(|bytes| Self::from_bytes(&bytes))
+            .with_context(|| path.display().to_string())
+    }
+}
+
+/// Identifies a threshold message by its payload nonce, which is random per
+/// message, so shares of different messages are not combined.
+pub(super) fn message_id(payload_nonce: &[u8]) -> [u8; MESSAGE_ID_SIZE] {
+    let digest = Sha256::new()
+        .chain_update(MESSAGE_DOMAIN)
+        .chain_update(payload_nonce)
+        .finalize();
+    let mut id = [0u8; MESSAGE_ID_SIZE];
+    id.copy_from_slice(&digest[..MESSAGE_ID_SIZE]);
+    id
+}
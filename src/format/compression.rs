@@ -0,0 +1,79 @@
+//! Payload compression, applied to the serialized payload before encryption.
+
+use anyhow::{Result, anyhow};
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use std::io::{Read, Write};
+
+/// Algorithm identifier for raw DEFLATE (RFC 1951).
+pub const ALGORITHM_DEFLATE: u8 = 1;
+
+/// Upper bound on decompressed size, to stop decompression bombs.
+pub const MAX_DECOMPRESSED_SIZE: u64 = 1 << 30;
+
+/// Compress a serialized payload.
+///
+/// Returns `None` when compression would not make the data smaller, in which
+/// case the payload should be embedded as-is.
+pub fn compress_payload(bytes: &[u8]) -> Result<Option<Vec<u8>>> {
+    let mut encoder = DeflateEncoder::new(vec![ALGORITHM_DEFLATE], Compression::best());
+    encoder.write_all(bytes)?;
+    let compressed = encoder.finish()?;
+
+    if compressed.len() < bytes.len() {
+        Ok(Some(compressed))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Reverse `compress_payload`.
+pub fn decompress_payload(bytes: &[u8]) -> Result<Vec<u8>> {
+    let (&algorithm, stream) = bytes
+        .split_first()
+        .ok_or_else(|| anyhow!("Compressed payload is empty"))?;
+
+    if algorithm != ALGORITHM_DEFLATE {
+        return Err(anyhow!("Unknown compression algorithm: {}", algorithm));
+    }
+
+    let mut decompressed = Vec::new();
+    DeflateDecoder::new(stream)
+        .take(MAX_DECOMPRESSED_SIZE + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| anyhow!("Decompression failed: {}", e))?;
+
+    if decompressed.len() as u64 > MAX_DECOMPRESSED_SIZE {
+        return Err(anyhow!(
+            "Decompressed payload exceeds {} bytes",
+            MAX_DECOMPRESSED_SIZE
+        ));
+    }
+
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression_roundtrip() {
+        let data = b"repetitive text ".repeat(64);
+        let compressed = compress_payload(&data).unwrap().expect("should shrink");
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress_payload(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn test_incompressible_data_is_skipped() {
+        let data: Vec<u8> = (0..64u8).map(|i| i.wrapping_mul(167) ^ 0x5a).collect();
+        assert!(compress_payload(&data).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_unknown_algorithm_rejected() {
+        assert!(decompress_payload(&[99, 0, 0]).is_err());
+    }
+}
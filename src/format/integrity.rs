@@ -0,0 +1,221 @@
+//! Checksums that let readers tell missing, corrupted and intact data apart.
+//!
+//! From format version 3 the header carries a CRC32C of its own fields, and
+//! the payload and signature are followed by one CRC32C per 256-byte block.
+//! Per-block checksums let decode report roughly how much data was damaged.
+
+use super::payload::{CHECKSUM_VERSION, Header, MAGIC, VERSION};
+use std::fmt;
+
+pub const CHECKSUM_SIZE: usize = 4;
+pub const BLOCK_SIZE: usize = 256;
+
+/// Magic bytes within this many flipped bits still count as "data present".
+const MAX_MAGIC_BIT_ERRORS: u32 = 3;
+
+pub fn checksum(bytes: &[u8]) -> u32 {
+    crc32c::crc32c(bytes)
+}
+
+/// CRC32C of each `BLOCK_SIZE` block of `data`, concatenated (u32 LE each).
+pub fn block_checksums(data: &[u8]) -> Vec<u8> {
+    data.chunks(BLOCK_SIZE)
+        .flat_map(|block| checksum(block).to_le_bytes())
+        .collect()
+}
+
+/// Size of the checksum trailer for `data_len` bytes of payload and signature.
+pub fn trailer_size(data_len: usize) -> usize {
+    data_len.div_ceil(BLOCK_SIZE) * CHECKSUM_SIZE
+}
+
+/// Whether `bytes` starts with the magic, allowing a few bit errors.
+pub fn looks_like_magic(bytes: &[u8]) -> bool {
+    bytes.len() >= MAGIC.len()
+        && bytes
+            .iter()
+            .zip(MAGIC)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum::<u32>()
+            <= MAX_MAGIC_BIT_ERRORS
+}
+
+/// Outcome of checking extracted bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Integrity {
+    /// No zimhide data in the carrier
+    Missing,
+    /// Data is present but the header is damaged
+    HeaderCorrupted,
+    /// The header is intact but the data ends early
+    Truncated { expected: usize, available: usize },
+    /// Some payload/signature blocks fail their checksum
+    PayloadCorrupted {
+        damaged_blocks: usize,
+        total_blocks: usize,
+        damaged_bytes: usize,
+    },
+    /// All checksums match
+    Intact,
+    /// The format version has no checksums to verify
+    Unchecked { version: u8 },
+}
+
+impl Integrity {
+    pub fn is_corrupted(&self) -> bool {
+        matches!(
+            self,
+            Self::HeaderCorrupted | Self::Truncated { .. } | Self::PayloadCorrupted { .. }
+        )
+    }
+}
+
+impl fmt::Display for Integrity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "no embedded data"),
+            Self::HeaderCorrupted => write!(f, "corrupted (header damaged)"),
+            Self::Truncated {
+                expected,
+                available,
+            } => write!(
+                f,
+                "corrupted (truncated: {available} of {expected} bytes present)"
+            ),
+            Self::PayloadCorrupted {
+                damaged_blocks,
+                total_blocks,
+                damaged_bytes,
+            } => write!(
+                f,
+                "corrupted ({damaged_blocks} of {total_blocks} blocks damaged, up to {damaged_bytes} bytes)"
+            ),
+            Self::Intact => write!(f, "intact (checksums verified)"),
+            Self::Unchecked { version } => {
+                write!(f, "not checked (format version {version} has no checksums)")
+            }
+        }
+    }
+}
+
+/// Classify raw extracted bytes as missing, corrupted or intact.
+pub fn check_integrity(bytes: &[u8]) -> Integrity {
+    if !looks_like_magic(bytes) {
+        return Integrity::Missing;
+    }
+
+    if bytes.len() > 4 && &bytes[0..4] == MAGIC && bytes[4] > VERSION {
+        // Written by a newer release; let the parser report it
+        return Integrity::Unchecked { version: bytes[4] };
+    }
+
+    let Ok(header) = Header::from_bytes(bytes) else {
+        return Integrity::HeaderCorrupted;
+    };
+
+    if header.version < CHECKSUM_VERSION {
+        return Integrity::Unchecked {
+            version: header.version,
+        };
+    }
+
+    let body_start = header.encoded_size();
+    let expected = header.embedded_size();
+    let body_len = header.payload_length as usize + header.signature_block_size();
+    if bytes.len() < expected {
+        return Integrity::Truncated {
+            expected,
+            available: bytes.len(),
+        };
+    }
+
+    let body = &bytes[body_start..body_start + body_len];
+    let stored = &bytes[body_start + body_len..expected];
+
+    let total_blocks = body_len.div_ceil(BLOCK_SIZE);
+    let mut damaged_blocks = 0;
+    let mut damaged_bytes = 0;
+    for (block, crc) in body.chunks(BLOCK_SIZE).zip(stored.chunks(CHECKSUM_SIZE)) {
+        if checksum(block).to_le_bytes() != crc {
+            damaged_blocks += 1;
+            damaged_bytes += block.len();
+        }
+    }
+
+    if damaged_blocks == 0 {
+        Integrity::Intact
+    } else {
+        Integrity::PayloadCorrupted {
+            damaged_blocks,
+            total_blocks,
+            damaged_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::payload::{EmbeddedData, Flags, StegoMethodId};
+
+    fn sample_bytes() -> Vec<u8> {
+        let payload = vec![0x42u8; 1000];
+        EmbeddedData {
+            header: Header {
+                version: VERSION,
+                flags: Flags {
+                    has_text: true,
+                    ..Default::default()
+                },
+                method: StegoMethodId::Lsb,
+                payload_length: payload.len() as u32,
+            },
+            payload,
+            signature: None,
+        }
+        .to_bytes()
+    }
+
+    #[test]
+    fn test_intact_data() {
+        assert_eq!(check_integrity(&sample_bytes()), Integrity::Intact);
+    }
+
+    #[test]
+    fn test_missing_data() {
+        assert_eq!(check_integrity(&[0u8; 64]), Integrity::Missing);
+        assert_eq!(check_integrity(b"ZI"), Integrity::Missing);
+    }
+
+    #[test]
+    fn test_corrupted_header() {
+        let mut bytes = sample_bytes();
+        bytes[0] ^= 0x01; // magic still recognisable
+        assert_eq!(check_integrity(&bytes), Integrity::HeaderCorrupted);
+    }
+
+    #[test]
+    fn test_corrupted_payload_blocks_counted() {
+        let mut bytes = sample_bytes();
+        let body_start = Header::CHECKED_SIZE;
+        bytes[body_start + 10] ^= 0xff;
+        bytes[body_start + 900] ^= 0x01;
+        assert_eq!(
+            check_integrity(&bytes),
+            Integrity::PayloadCorrupted {
+                damaged_blocks: 2,
+                total_blocks: 4,
+                damaged_bytes: 256 + 232,
+            }
+        );
+    }
+
+    #[test]
+    fn test_truncated_data() {
+        let bytes = sample_bytes();
+        assert!(matches!(
+            check_integrity(&bytes[..bytes.len() - 1]),
+            Integrity::Truncated { .. }
+        ));
+    }
+}
@@ -1,3 +1,10 @@
+pub mod compression;
+pub mod integrity;
 pub mod payload;
+pub mod varint;
 
-pub use payload::{EmbeddedData, Flags, Header, Payload};
+pub use compression::compress_payload;
+pub use integrity::{Integrity, check_integrity};
+pub use payload::{
+    EmbeddedData, EmbeddedSignature, FileRecord, Flags, Header, Payload, RecordType, SignatureScope,
+};
@@ -1,8 +1,26 @@
+use super::compression::decompress_payload;
+use super::integrity::{CHECKSUM_SIZE, block_checksums, checksum, trailer_size};
+use super::varint;
 use anyhow::{Result, anyhow};
+use zeroize::{Zeroize, Zeroizing};
 
 pub const MAGIC: &[u8; 4] = b"ZIMH";
-pub const VERSION: u8 = 1;
+pub const VERSION: u8 = 8;
+/// First format version with typed payload records, file attachments and compression.
+pub const RECORDS_VERSION: u8 = 2;
+/// First format version with header and block checksums.
+pub const CHECKSUM_VERSION: u8 = 3;
 pub const SIGNATURE_SIZE: usize = 64;
+/// First format version whose signatures cover a transcript, not just the payload.
+pub const TRANSCRIPT_VERSION: u8 = 4;
+/// First format version whose signatures may name the signing key, marked by
+/// the `names_signer` flag.
+pub const SIGNER_ID_VERSION: u8 = 8;
+/// Size of the signer's key id: the leading bytes of its fingerprint digest.
+pub const SIGNER_ID_SIZE: usize = 16;
+
+/// Domain separator prefixed to every signed transcript.
+const SIGNATURE_DOMAIN: &[u8] = b"zimhide signed transcript\0";
 
 #[derive(Debug, Clone, Copy, Default)]
 #[allow(clippy::struct_excessive_bools)]
@@ -10,11 +28,34 @@ pub struct Flags {
     pub has_text: bool,
     pub has_audio: bool,
     pub is_signed: bool,
+    /// A passphrase envelope
     pub symmetric_encryption: bool,
-    pub asymmetric_encryption: bool,
+    /// A key-slot envelope: public-key, passphrase or keyfile slots, any of
+    /// which (or a threshold of which) opens it. A keyfile alone is one slot
+    pub key_slot_encryption: bool,
+    pub has_files: bool,
+    pub compressed: bool,
+    /// The signature block carries the signer's key id
+    pub names_signer: bool,
 }
 
 impl Flags {
+    /// Bits a version 1 writer could set: `has_files` and `compressed` came later.
+    const V1_BITS: u8 = 0b0001_1111;
+    /// Bits a writer of versions 2 to 7 could set: all but `names_signer`.
+    const V2_BITS: u8 = 0b0111_1111;
+
+    /// Bits with an assigned meaning in the given format version.
+    pub fn known_bits(version: u8) -> u8 {
+        if version < RECORDS_VERSION {
+            Self::V1_BITS
+        } else if version < SIGNER_ID_VERSION {
+            Self::V2_BITS
+        } else {
+            0xff
+        }
+    }
+
     pub fn as_byte(self) -> u8 {
         let mut byte = 0u8;
         if self.has_text {
@@ -29,9 +70,18 @@ impl Flags {
         if self.symmetric_encryption {
             byte |= 1 << 3;
         }
-        if self.asymmetric_encryption {
+        if self.key_slot_encryption {
             byte |= 1 << 4;
         }
+        if self.has_files {
+            byte |= 1 << 5;
+        }
+        if self.compressed {
+            byte |= 1 << 6;
+        }
+        if self.names_signer {
+            byte |= 1 << 7;
+        }
         byte
     }
 
@@ -41,7 +91,10 @@ impl Flags {
             has_audio: (byte & (1 << 1)) != 0,
             is_signed: (byte & (1 << 2)) != 0,
             symmetric_encryption: (byte & (1 << 3)) != 0,
-            asymmetric_encryption: (byte & (1 << 4)) != 0,
+            key_slot_encryption: (byte & (1 << 4)) != 0,
+            has_files: (byte & (1 << 5)) != 0,
+            compressed: (byte & (1 << 6)) != 0,
+            names_signer: (byte & (1 << 7)) != 0,
         }
     }
 }
@@ -77,14 +130,69 @@ pub struct Header {
 
 impl Header {
     pub const SIZE: usize = 4 + 1 + 1 + 1 + 4; // magic + version + flags + method + length
+    pub const CHECKED_SIZE: usize = Self::SIZE + CHECKSUM_SIZE; // v3+: + header CRC32C
+    /// Largest payload the fixed-width length field can describe. Records inside
+    /// the payload use varint lengths, but the header keeps its fixed layout.
+    pub const MAX_PAYLOAD_LENGTH: usize = u32::MAX as usize;
+
+    /// The header length field for a payload of `len` bytes.
+    pub fn payload_length_for(len: usize) -> Result<u32> {
+        u32::try_from(len).map_err(|_| {
+            anyhow!(
+                "Payload too large: {} bytes exceeds the format limit of {} bytes",
+                len,
+                Self::MAX_PAYLOAD_LENGTH
+            )
+        })
+    }
+
+    pub fn size_for_version(version: u8) -> usize {
+        if version >= CHECKSUM_VERSION {
+            Self::CHECKED_SIZE
+        } else {
+            Self::SIZE
+        }
+    }
+
+    pub fn encoded_size(&self) -> usize {
+        Self::size_for_version(self.version)
+    }
+
+    /// Size of the signature block that follows the payload, if any.
+    pub fn signature_block_size(&self) -> usize {
+        if !self.flags.is_signed {
+            0
+        } else if self.flags.names_signer {
+            1 + SIGNER_ID_SIZE + SIGNATURE_SIZE // scope + signer + signature
+        } else if self.version >= TRANSCRIPT_VERSION {
+            1 + SIGNATURE_SIZE // scope + signature
+        } else {
+            SIGNATURE_SIZE
+        }
+    }
+
+    /// Size of the complete embedded data this header describes.
+    pub fn embedded_size(&self) -> usize {
+        let body_size = self.payload_length as usize + self.signature_block_size();
+        let trailer = if self.version >= CHECKSUM_VERSION {
+            trailer_size(body_size)
+        } else {
+            0
+        };
+        self.encoded_size() + body_size + trailer
+    }
 
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::with_capacity(Self::SIZE);
+        let mut bytes = Vec::with_capacity(self.encoded_size());
         bytes.extend_from_slice(MAGIC);
         bytes.push(self.version);
         bytes.push(self.flags.as_byte());
         bytes.push(self.method as u8);
         bytes.extend_from_slice(&self.payload_length.to_le_bytes());
+        if self.version >= CHECKSUM_VERSION {
+            let crc = checksum(&bytes);
+            bytes.extend_from_slice(&crc.to_le_bytes());
+        }
         bytes
     }
 
@@ -110,6 +218,35 @@ impl Header {
             ));
         }
 
+        if version >= CHECKSUM_VERSION {
+            if bytes.len() < Self::CHECKED_SIZE {
+                return Err(anyhow!(
+                    "Header too short: expected {} bytes, got {}",
+                    Self::CHECKED_SIZE,
+                    bytes.len()
+                ));
+            }
+            if !header_checksum_matches(&bytes[..Self::CHECKED_SIZE]) {
+                return Err(anyhow!(
+                    "Header checksum mismatch: the embedded data is corrupted"
+                ));
+            }
+        } else if let Some(original) = damaged_version(bytes) {
+            return Err(anyhow!(
+                "Header corrupted: version byte reads {} but the checksum matches version {}",
+                version,
+                original
+            ));
+        }
+
+        if bytes[5] & !Flags::known_bits(version) != 0 {
+            return Err(anyhow!(
+                "Invalid flags: 0x{:02x} uses features newer than format version {}",
+                bytes[5],
+                version
+            ));
+        }
+
         let flags = Flags::from_byte(bytes[5]);
         let method = StegoMethodId::try_from(bytes[6])?;
         let payload_length = u32::from_le_bytes([bytes[7], bytes[8], bytes[9], bytes[10]]);
@@ -123,20 +260,171 @@ impl Header {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+fn header_checksum_matches(header: &[u8]) -> bool {
+    let stored = u32::from_le_bytes([
+        header[Header::SIZE],
+        header[Header::SIZE + 1],
+        header[Header::SIZE + 2],
+        header[Header::SIZE + 3],
+    ]);
+    checksum(&header[..Header::SIZE]) == stored
+}
+
+/// Detect a checksummed header whose version byte was corrupted into a
+/// pre-checksum version, which would otherwise be silently misparsed.
+fn damaged_version(bytes: &[u8]) -> Option<u8> {
+    if bytes.len() < Header::CHECKED_SIZE {
+        return None;
+    }
+    let mut patched = bytes[..Header::CHECKED_SIZE].to_vec();
+    (CHECKSUM_VERSION..=VERSION).find(|&candidate| {
+        patched[4] = candidate;
+        header_checksum_matches(&patched)
+    })
+}
+
+/// Record type identifiers used by the v2 payload format.
+///
+/// Readers skip record types they do not recognise, so new types can be
+/// added without another format break.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RecordType {
+    Text = 1,
+    Audio = 2,
+    File = 3,
+    Metadata = 4,
+    Padding = 5,
+    Revocation = 6,
+}
+
+impl RecordType {
+    pub fn from_id(id: u64) -> Option<Self> {
+        match id {
+            1 => Some(Self::Text),
+            2 => Some(Self::Audio),
+            3 => Some(Self::File),
+            4 => Some(Self::Metadata),
+            5 => Some(Self::Padding),
+            6 => Some(Self::Revocation),
+            _ => None,
+        }
+    }
+}
+
+/// An embedded file attachment (v2 `File` record).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Zeroize)]
+pub struct FileRecord {
+    pub name: String,
+    pub mime_type: String,
+    /// Modification time in seconds since the Unix epoch (0 if unknown)
+    pub modified: u64,
+    /// Unix permission bits (0 if unknown)
+    pub mode: u32,
+    pub data: Vec<u8>,
+}
+
+impl FileRecord {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.name.len() + self.mime_type.len() + 24);
+        push_field(&mut bytes, self.name.as_bytes());
+        push_field(&mut bytes, self.mime_type.as_bytes());
+        varint::encode(self.modified, &mut bytes);
+        varint::encode(u64::from(self.mode), &mut bytes);
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = FieldReader::new(bytes);
+        let name = String::from_utf8(reader.field("file name")?.to_vec())?;
+        let mime_type = String::from_utf8(reader.field("file MIME type")?.to_vec())?;
+        let modified = reader.varint("file modification time")?;
+        let mode = u32::try_from(reader.varint("file mode")?)
+            .map_err(|_| anyhow!("File record has an invalid mode"))?;
+        let data = reader.rest().to_vec();
+
+        Ok(Self {
+            name,
+            mime_type,
+            modified,
+            mode,
+            data,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Zeroize)]
 pub struct Payload {
     pub text: Option<String>,
     pub audio: Option<Vec<u8>>,
+    pub files: Vec<FileRecord>,
+    /// Free-form key/value metadata
+    pub metadata: Vec<(String, String)>,
+    /// Key revocation certificates, passed on for recipients to import
+    pub revocations: Vec<Vec<u8>>,
+    /// Number of padding bytes written (or skipped when reading)
+    pub padding: usize,
+    /// Number of records skipped because their type is unknown
+    pub unknown_records: usize,
 }
 
 impl Payload {
+    /// Serialize as a sequence of v2 records.
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
 
+        if let Some(ref text) = self.text {
+            push_record(&mut bytes, RecordType::Text, text.as_bytes());
+        }
+
+        if let Some(ref audio) = self.audio {
+            push_record(&mut bytes, RecordType::Audio, audio);
+        }
+
+        for file in &self.files {
+            push_record(&mut bytes, RecordType::File, &file.to_bytes());
+        }
+
+        for (key, value) in &self.metadata {
+            let mut record = Vec::with_capacity(key.len() + value.len() + 1);
+            push_field(&mut record, key.as_bytes());
+            record.extend_from_slice(value.as_bytes());
+            push_record(&mut bytes, RecordType::Metadata, &record);
+        }
+
+        for revocation in &self.revocations {
+            push_record(&mut bytes, RecordType::Revocation, revocation);
+        }
+
+        if self.padding > 0 {
+            push_record(&mut bytes, RecordType::Padding, &vec![0u8; self.padding]);
+        }
+
+        bytes
+    }
+
+    /// Serialize using the v1 layout (text and audio only).
+    ///
+    /// Kept for producing files readable by older releases.
+    pub fn to_bytes_v1(&self) -> Result<Vec<u8>> {
+        if !self.files.is_empty() || !self.metadata.is_empty() || !self.revocations.is_empty() {
+            return Err(anyhow!(
+                "Format version 1 can only hold text and audio content"
+            ));
+        }
+
+        // Lengths are u32 fields; larger content cannot be written in this layout
+        let length = |what: &str, len: usize| {
+            u32::try_from(len)
+                .map_err(|_| anyhow!("{} too large for format version 1: {} bytes", what, len))
+        };
+        let mut bytes = Vec::new();
+
         // Text length and content
         if let Some(ref text) = self.text {
             let text_bytes = text.as_bytes();
-            bytes.extend_from_slice(&(text_bytes.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&length("Text", text_bytes.len())?.to_le_bytes());
             bytes.extend_from_slice(text_bytes);
         } else {
             bytes.extend_from_slice(&0u32.to_le_bytes());
@@ -144,16 +432,94 @@ impl Payload {
 
         // Audio length and content
         if let Some(ref audio) = self.audio {
-            bytes.extend_from_slice(&(audio.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&length("Audio", audio.len())?.to_le_bytes());
             bytes.extend_from_slice(audio);
         } else {
             bytes.extend_from_slice(&0u32.to_le_bytes());
         }
 
-        bytes
+        Ok(bytes)
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+    /// Parse a decrypted payload, decompressing it first if the header says
+    /// so. The content is plaintext, so it is wiped on drop.
+    pub fn decode(plaintext: &[u8], header: &Header) -> Result<Zeroizing<Self>> {
+        let payload = if header.flags.compressed {
+            let decompressed = Zeroizing::new(decompress_payload(plaintext)?);
+            Self::from_bytes(&decompressed, header.version)
+        } else {
+            Self::from_bytes(plaintext, header.version)
+        };
+        payload.map(Zeroizing::new)
+    }
+
+    /// Parse a decrypted payload written with the given format version.
+    pub fn from_bytes(bytes: &[u8], version: u8) -> Result<Self> {
+        if version < RECORDS_VERSION {
+            Self::from_bytes_v1(bytes)
+        } else {
+            Self::from_records(bytes)
+        }
+    }
+
+    fn from_records(bytes: &[u8]) -> Result<Self> {
+        let mut payload = Self::default();
+        let mut offset = 0;
+
+        while offset < bytes.len() {
+            let (type_id, used) = varint::decode(&bytes[offset..])
+                .map_err(|e| anyhow!("Invalid record type at offset {}: {}", offset, e))?;
+            offset += used;
+
+            let (length, used) = varint::decode(&bytes[offset..])
+                .map_err(|e| anyhow!("Invalid record length at offset {}: {}", offset, e))?;
+            offset += used;
+
+            let available = bytes.len() - offset;
+            let length = usize::try_from(length)
+                .ok()
+                .filter(|&len| len <= available)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Payload truncated: record requires {} bytes at offset {}, but only {} bytes available",
+                        length,
+                        offset,
+                        available
+                    )
+                })?;
+            let value = &bytes[offset..offset + length];
+            offset += length;
+
+            match RecordType::from_id(type_id) {
+                Some(RecordType::Text) => {
+                    if payload.text.is_some() {
+                        return Err(anyhow!("Payload contains more than one text record"));
+                    }
+                    payload.text = Some(String::from_utf8(value.to_vec())?);
+                }
+                Some(RecordType::Audio) => {
+                    if payload.audio.is_some() {
+                        return Err(anyhow!("Payload contains more than one audio record"));
+                    }
+                    payload.audio = Some(value.to_vec());
+                }
+                Some(RecordType::File) => payload.files.push(FileRecord::from_bytes(value)?),
+                Some(RecordType::Metadata) => {
+                    let mut reader = FieldReader::new(value);
+                    let key = String::from_utf8(reader.field("metadata key")?.to_vec())?;
+                    let value = String::from_utf8(reader.rest().to_vec())?;
+                    payload.metadata.push((key, value));
+                }
+                Some(RecordType::Padding) => payload.padding += length,
+                Some(RecordType::Revocation) => payload.revocations.push(value.to_vec()),
+                None => payload.unknown_records += 1,
+            }
+        }
+
+        Ok(payload)
+    }
+
+    fn from_bytes_v1(bytes: &[u8]) -> Result<Self> {
         if bytes.len() < 8 {
             return Err(anyhow!(
                 "Payload too short: expected at least 8 bytes, got {}",
@@ -218,30 +584,142 @@ impl Payload {
             None
         };
 
-        Ok(Self { text, audio })
+        Ok(Self {
+            text,
+            audio,
+            ..Default::default()
+        })
     }
 }
 
+fn push_record(out: &mut Vec<u8>, record_type: RecordType, value: &[u8]) {
+    varint::encode(record_type as u64, out);
+    varint::encode(value.len() as u64, out);
+    out.extend_from_slice(value);
+}
+
+fn push_field(out: &mut Vec<u8>, value: &[u8]) {
+    varint::encode(value.len() as u64, out);
+    out.extend_from_slice(value);
+}
+
+/// Cursor over the length-prefixed fields inside a record value.
+struct FieldReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> FieldReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    fn varint(&mut self, what: &str) -> Result<u64> {
+        let (value, used) = varint::decode(&self.bytes[self.offset..])
+            .map_err(|e| anyhow!("Invalid {}: {}", what, e))?;
+        self.offset += used;
+        Ok(value)
+    }
+
+    fn field(&mut self, what: &str) -> Result<&'a [u8]> {
+        let length = self.varint(what)?;
+        let available = self.bytes.len() - self.offset;
+        let length = usize::try_from(length)
+            .ok()
+            .filter(|&len| len <= available)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Record truncated: {} requires {} bytes, but only {} bytes available",
+                    what,
+                    length,
+                    available
+                )
+            })?;
+        let value = &self.bytes[self.offset..self.offset + length];
+        self.offset += length;
+        Ok(value)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.bytes[self.offset..];
+        self.offset = self.bytes.len();
+        rest
+    }
+}
+
+/// What a signature authenticates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SignatureScope {
+    /// The payload bytes alone (format versions 1-3)
+    Payload = 0,
+    /// Domain separator, header and payload
+    Header = 1,
+    /// Domain separator, header, carrier audio hash and payload
+    HeaderAndCarrier = 2,
+}
+
+impl SignatureScope {
+    pub fn from_byte(byte: u8) -> Result<Self> {
+        match byte {
+            1 => Ok(Self::Header),
+            2 => Ok(Self::HeaderAndCarrier),
+            _ => Err(anyhow!("Unknown signature scope: {}", byte)),
+        }
+    }
+
+    pub fn covers_carrier(self) -> bool {
+        self == Self::HeaderAndCarrier
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::Payload => "payload only; header is not authenticated",
+            Self::Header => "header and payload",
+            Self::HeaderAndCarrier => "header, payload and carrier audio",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmbeddedSignature {
+    pub scope: SignatureScope,
+    /// Key id of the signer, when the header flags name it
+    pub signer: Option<[u8; SIGNER_ID_SIZE]>,
+    pub bytes: [u8; SIGNATURE_SIZE],
+}
+
 #[derive(Debug, Clone)]
 pub struct EmbeddedData {
     pub header: Header,
     pub payload: Vec<u8>, // Raw payload bytes (may be encrypted)
-    pub signature: Option<[u8; SIGNATURE_SIZE]>,
+    pub signature: Option<EmbeddedSignature>,
 }
 
 impl EmbeddedData {
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = self.header.to_bytes();
+        let body_start = bytes.len();
         bytes.extend_from_slice(&self.payload);
         if let Some(sig) = &self.signature {
-            bytes.extend_from_slice(sig);
+            if self.header.version >= TRANSCRIPT_VERSION {
+                bytes.push(sig.scope as u8);
+            }
+            if self.header.flags.names_signer {
+                bytes.extend_from_slice(&sig.signer.unwrap_or_default());
+            }
+            bytes.extend_from_slice(&sig.bytes);
+        }
+        if self.header.version >= CHECKSUM_VERSION {
+            let checksums = block_checksums(&bytes[body_start..]);
+            bytes.extend_from_slice(&checksums);
         }
         bytes
     }
 
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
         let header = Header::from_bytes(bytes)?;
-        let payload_start = Header::SIZE;
+        let payload_start = header.encoded_size();
         let payload_end = payload_start + header.payload_length as usize;
 
         if bytes.len() < payload_end {
@@ -255,8 +733,8 @@ impl EmbeddedData {
         let payload = bytes[payload_start..payload_end].to_vec();
 
         let signature = if header.flags.is_signed {
-            let sig_start = payload_end;
-            let sig_end = sig_start + SIGNATURE_SIZE;
+            let mut sig_start = payload_end;
+            let sig_end = sig_start + header.signature_block_size();
             if bytes.len() < sig_end {
                 return Err(anyhow!(
                     "Data truncated: signature requires {} bytes, but only {} bytes available",
@@ -264,9 +742,27 @@ impl EmbeddedData {
                     bytes.len()
                 ));
             }
+            let scope = if header.version >= TRANSCRIPT_VERSION {
+                sig_start += 1;
+                SignatureScope::from_byte(bytes[payload_end])?
+            } else {
+                SignatureScope::Payload
+            };
+            let signer = if header.flags.names_signer {
+                let mut signer = [0u8; SIGNER_ID_SIZE];
+                signer.copy_from_slice(&bytes[sig_start..sig_start + SIGNER_ID_SIZE]);
+                sig_start += SIGNER_ID_SIZE;
+                Some(signer)
+            } else {
+                None
+            };
             let mut sig = [0u8; SIGNATURE_SIZE];
             sig.copy_from_slice(&bytes[sig_start..sig_end]);
-            Some(sig)
+            Some(EmbeddedSignature {
+                scope,
+                signer,
+                bytes: sig,
+            })
         } else {
             None
         };
@@ -278,14 +774,53 @@ impl EmbeddedData {
         })
     }
 
+    /// The exact bytes a signature with the given scope is computed over.
+    ///
+    /// `signer` is required when the header flags name the signer, and `carrier_hash` for
+    /// `SignatureScope::HeaderAndCarrier`.
+    pub fn signed_message(
+        &self,
+        scope: SignatureScope,
+        signer: Option<&[u8; SIGNER_ID_SIZE]>,
+        carrier_hash: Option<&[u8; 32]>,
+    ) -> Result<Vec<u8>> {
+        if scope == SignatureScope::Payload {
+            return Ok(self.payload.clone());
+        }
+
+        let header = self.header.to_bytes();
+        let mut message = Vec::with_capacity(
+            SIGNATURE_DOMAIN.len() + 1 + Header::SIZE + SIGNER_ID_SIZE + 32 + self.payload.len(),
+        );
+        message.extend_from_slice(SIGNATURE_DOMAIN);
+        message.push(scope as u8);
+        message.extend_from_slice(&header[..Header::SIZE]);
+        if self.header.flags.names_signer {
+            let signer = signer.ok_or_else(|| anyhow!("Signer key id required"))?;
+            message.extend_from_slice(signer);
+        }
+        if scope.covers_carrier() {
+            let hash = carrier_hash
+                .ok_or_else(|| anyhow!("Carrier hash required for a carrier-bound signature"))?;
+            message.extend_from_slice(hash);
+        }
+        message.extend_from_slice(&self.payload);
+        Ok(message)
+    }
+
     pub fn total_size(&self) -> usize {
-        Header::SIZE
-            + self.payload.len()
+        let body_size = self.payload.len()
             + if self.signature.is_some() {
-                SIGNATURE_SIZE
+                self.header.signature_block_size()
             } else {
                 0
-            }
+            };
+        let trailer = if self.header.version >= CHECKSUM_VERSION {
+            trailer_size(body_size)
+        } else {
+            0
+        };
+        self.header.encoded_size() + body_size + trailer
     }
 }
 
@@ -300,7 +835,10 @@ mod tests {
             has_audio: false,
             is_signed: true,
             symmetric_encryption: false,
-            asymmetric_encryption: true,
+            key_slot_encryption: true,
+            has_files: true,
+            compressed: false,
+            names_signer: true,
         };
         let byte = flags.as_byte();
         let decoded = Flags::from_byte(byte);
@@ -308,7 +846,10 @@ mod tests {
         assert_eq!(flags.has_audio, decoded.has_audio);
         assert_eq!(flags.is_signed, decoded.is_signed);
         assert_eq!(flags.symmetric_encryption, decoded.symmetric_encryption);
-        assert_eq!(flags.asymmetric_encryption, decoded.asymmetric_encryption);
+        assert_eq!(flags.key_slot_encryption, decoded.key_slot_encryption);
+        assert_eq!(flags.has_files, decoded.has_files);
+        assert_eq!(flags.compressed, decoded.compressed);
+        assert_eq!(flags.names_signer, decoded.names_signer);
     }
 
     #[test]
@@ -316,13 +857,270 @@ mod tests {
         let payload = Payload {
             text: Some("Hello, world!".to_string()),
             audio: Some(vec![1, 2, 3, 4, 5]),
+            ..Default::default()
         };
         let bytes = payload.to_bytes();
-        let decoded = Payload::from_bytes(&bytes).unwrap();
+        let decoded = Payload::from_bytes(&bytes, VERSION).unwrap();
         assert_eq!(payload.text, decoded.text);
         assert_eq!(payload.audio, decoded.audio);
     }
 
+    #[test]
+    fn test_payload_v1_roundtrip() {
+        let payload = Payload {
+            text: Some("Hello, world!".to_string()),
+            audio: Some(vec![1, 2, 3, 4, 5]),
+            ..Default::default()
+        };
+        let bytes = payload.to_bytes_v1().unwrap();
+        let decoded = Payload::from_bytes(&bytes, 1).unwrap();
+        assert_eq!(payload.text, decoded.text);
+        assert_eq!(payload.audio, decoded.audio);
+    }
+
+    #[test]
+    fn test_payload_all_record_types_roundtrip() {
+        let payload = Payload {
+            text: Some("note".to_string()),
+            audio: None,
+            files: vec![FileRecord {
+                name: "report.pdf".to_string(),
+                mime_type: "application/pdf".to_string(),
+                modified: 1_700_000_000,
+                mode: 0o644,
+                data: vec![0x25, 0x50, 0x44, 0x46],
+            }],
+            metadata: vec![("author".to_string(), "zim".to_string())],
+            revocations: vec![vec![1; 138]],
+            padding: 16,
+            unknown_records: 0,
+        };
+        let decoded = Payload::from_bytes(&payload.to_bytes(), VERSION).unwrap();
+        assert_eq!(payload.text, decoded.text);
+        assert_eq!(payload.files, decoded.files);
+        assert_eq!(payload.metadata, decoded.metadata);
+        assert_eq!(payload.revocations, decoded.revocations);
+        assert_eq!(decoded.padding, 16);
+    }
+
+    #[test]
+    fn test_unknown_records_are_skipped() {
+        let mut bytes = Vec::new();
+        push_record(&mut bytes, RecordType::Text, b"kept");
+        varint::encode(200, &mut bytes);
+        varint::encode(3, &mut bytes);
+        bytes.extend_from_slice(&[9, 9, 9]);
+
+        let decoded = Payload::from_bytes(&bytes, VERSION).unwrap();
+        assert_eq!(decoded.text.as_deref(), Some("kept"));
+        assert_eq!(decoded.unknown_records, 1);
+    }
+
+    #[test]
+    fn test_truncated_record_rejected() {
+        let mut bytes = Vec::new();
+        push_record(&mut bytes, RecordType::Text, b"truncated");
+        bytes.truncate(bytes.len() - 2);
+        assert!(Payload::from_bytes(&bytes, VERSION).is_err());
+    }
+
+    #[test]
+    fn test_header_checksum_detects_corruption() {
+        let header = Header {
+            version: VERSION,
+            flags: Flags {
+                has_text: true,
+                ..Default::default()
+            },
+            method: StegoMethodId::Lsb,
+            payload_length: 42,
+        };
+        let mut bytes = header.to_bytes();
+        assert_eq!(bytes.len(), Header::CHECKED_SIZE);
+        bytes[6] ^= 0x01;
+        let err = Header::from_bytes(&bytes).unwrap_err();
+        assert!(err.to_string().contains("checksum"));
+    }
+
+    #[test]
+    fn test_damaged_version_byte_detected() {
+        let header = Header {
+            version: VERSION,
+            flags: Flags::default(),
+            method: StegoMethodId::Lsb,
+            payload_length: 7,
+        };
+        let mut bytes = header.to_bytes();
+        bytes[4] = 1;
+        let err = Header::from_bytes(&bytes).unwrap_err();
+        assert!(err.to_string().contains("version byte"));
+    }
+
+    #[test]
+    fn test_embedded_data_v3_roundtrip() {
+        let embedded = EmbeddedData {
+            header: Header {
+                version: VERSION,
+                flags: Flags {
+                    is_signed: true,
+                    names_signer: true,
+                    ..Default::default()
+                },
+                method: StegoMethodId::Lsb,
+                payload_length: 300,
+            },
+            payload: vec![7u8; 300],
+            signature: Some(EmbeddedSignature {
+                scope: SignatureScope::Header,
+                signer: Some([5u8; SIGNER_ID_SIZE]),
+                bytes: [9u8; SIGNATURE_SIZE],
+            }),
+        };
+        let bytes = embedded.to_bytes();
+        assert_eq!(bytes.len(), embedded.total_size());
+        assert_eq!(bytes.len(), Header::CHECKED_SIZE + 381 + 2 * CHECKSUM_SIZE);
+
+        let decoded = EmbeddedData::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.payload, embedded.payload);
+        assert_eq!(decoded.signature, embedded.signature);
+    }
+
+    #[test]
+    fn test_signed_message_covers_header() {
+        let mut embedded = EmbeddedData {
+            header: Header {
+                version: VERSION,
+                flags: Flags {
+                    is_signed: true,
+                    has_text: true,
+                    names_signer: true,
+                    ..Default::default()
+                },
+                method: StegoMethodId::Lsb,
+                payload_length: 3,
+            },
+            payload: vec![1, 2, 3],
+            signature: None,
+        };
+        let signer = [5u8; SIGNER_ID_SIZE];
+        let original = embedded
+            .signed_message(SignatureScope::Header, Some(&signer), None)
+            .unwrap();
+
+        embedded.header.flags.has_audio = true;
+        let tampered = embedded
+            .signed_message(SignatureScope::Header, Some(&signer), None)
+            .unwrap();
+        assert_ne!(original, tampered);
+
+        // The signer id is covered too, and required
+        let other_signer = embedded
+            .signed_message(SignatureScope::Header, Some(&[6u8; SIGNER_ID_SIZE]), None)
+            .unwrap();
+        assert_ne!(tampered, other_signer);
+        assert!(
+            embedded
+                .signed_message(SignatureScope::Header, None, None)
+                .is_err()
+        );
+
+        // Legacy scope ignores the header entirely
+        assert_eq!(
+            embedded
+                .signed_message(SignatureScope::Payload, None, None)
+                .unwrap(),
+            vec![1, 2, 3]
+        );
+        assert!(
+            embedded
+                .signed_message(SignatureScope::HeaderAndCarrier, Some(&signer), None)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_signer_flag_rejected_before_v8() {
+        let header = Header {
+            version: SIGNER_ID_VERSION - 1,
+            flags: Flags {
+                names_signer: true,
+                ..Flags::default()
+            },
+            method: StegoMethodId::Lsb,
+            payload_length: 0,
+        };
+        let err = Header::from_bytes(&header.to_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid flags: 0x80 uses features newer than format version 7"
+        );
+
+        let header = Header {
+            version: SIGNER_ID_VERSION,
+            ..header
+        };
+        assert!(
+            Header::from_bytes(&header.to_bytes())
+                .unwrap()
+                .flags
+                .names_signer
+        );
+    }
+
+    #[test]
+    fn test_newer_flags_rejected_on_v1() {
+        for flags in [
+            Flags {
+                compressed: true,
+                ..Flags::default()
+            },
+            Flags {
+                has_files: true,
+                ..Flags::default()
+            },
+        ] {
+            let header = Header {
+                version: 1,
+                flags,
+                method: StegoMethodId::Lsb,
+                payload_length: 0,
+            };
+            let err = Header::from_bytes(&header.to_bytes()).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "Invalid flags: 0x{:02x} uses features newer than format version 1",
+                    flags.as_byte()
+                )
+            );
+
+            let header = Header {
+                version: RECORDS_VERSION,
+                ..header
+            };
+            assert_eq!(
+                Header::from_bytes(&header.to_bytes())
+                    .unwrap()
+                    .flags
+                    .as_byte(),
+                flags.as_byte()
+            );
+        }
+    }
+
+    #[test]
+    fn test_payload_length_cap() {
+        assert_eq!(
+            Header::payload_length_for(Header::MAX_PAYLOAD_LENGTH).unwrap(),
+            u32::MAX
+        );
+        let err = Header::payload_length_for(Header::MAX_PAYLOAD_LENGTH + 1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Payload too large: 4294967296 bytes exceeds the format limit of 4294967295 bytes"
+        );
+    }
+
     #[test]
     fn test_header_roundtrip() {
         let header = Header {
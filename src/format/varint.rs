@@ -0,0 +1,73 @@
+//! Unsigned LEB128 variable-length integers used by the v2 record format.
+
+use anyhow::{Result, anyhow};
+
+/// Maximum encoded size of a u64 (ceil(64 / 7)).
+pub const MAX_VARINT_SIZE: usize = 10;
+
+/// Append `value` to `out` as an unsigned LEB128 varint.
+pub fn encode(mut value: u64, out: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Decode a varint from the start of `bytes`.
+///
+/// Returns the value and the number of bytes consumed.
+pub fn decode(bytes: &[u8]) -> Result<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, &byte) in bytes.iter().enumerate().take(MAX_VARINT_SIZE) {
+        let chunk = u64::from(byte & 0x7f);
+        let shift = 7 * i as u32;
+        if shift == 63 && chunk > 1 {
+            return Err(anyhow!("Varint overflows 64 bits"));
+        }
+        value |= chunk << shift;
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+
+    if bytes.len() >= MAX_VARINT_SIZE {
+        Err(anyhow!("Varint overflows 64 bits"))
+    } else {
+        Err(anyhow!("Varint truncated after {} bytes", bytes.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint_roundtrip() {
+        for value in [0, 1, 127, 128, 300, 16_384, u64::from(u32::MAX), u64::MAX] {
+            let mut bytes = Vec::new();
+            encode(value, &mut bytes);
+            let (decoded, used) = decode(&bytes).unwrap();
+            assert_eq!(value, decoded);
+            assert_eq!(bytes.len(), used);
+        }
+    }
+
+    #[test]
+    fn test_varint_known_encodings() {
+        let mut bytes = Vec::new();
+        encode(300, &mut bytes);
+        assert_eq!(bytes, [0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_varint_truncated_and_overflow() {
+        assert!(decode(&[0x80, 0x80]).is_err());
+        assert!(decode(&[0xff; 11]).is_err());
+        assert!(decode(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02]).is_err());
+    }
+}
@@ -0,0 +1,952 @@
+//! Named keys, so commands can take `alice` instead of `alice.pub`.
+//!
+//! The keyring lives in `$XDG_DATA_HOME/zimhide/keyring` (by default
+//! `~/.local/share/zimhide/keyring`):
+//!
+//! - `contacts/<name>.pub`: other people's public keys
+//! - `identities/<name>.priv` and `<name>.pub`: your own keys; private keys
+//!   are copied as given, so passphrase protection is kept
+//! - `groups/<name>`: contact or identity names, one per line
+//! - `trust/<name>`: a contact's trust level, if it is not `unverified`
+//! - `revocations/<key id>.rev`: imported revocation certificates, by the
+//!   revoked key's id in hex
+//! - `certifications/<key id>/<certifier id>.cert`: certifications of a key,
+//!   one per certifying key
+//! - `default`: the name of the identity used when `--key` is omitted
+
+use crate::crypto::keys::{KEY_ID_SIZE, unix_now, write_private_file};
+use crate::crypto::{Certification, KeyFormat, PrivateKey, PublicKey, Revocation};
+use crate::time::format_timestamp;
+use anyhow::{Context, Result, anyhow};
+use clap::ValueEnum;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+/// Longest accepted entry name.
+pub const MAX_NAME_LEN: usize = 64;
+
+/// What a keyring name refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// Someone else's public key
+    Contact,
+    /// One of your own private keys
+    Identity,
+    /// A set of contacts and identities, expanded to each as a recipient
+    Group,
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Contact => "contact",
+            Self::Identity => "identity",
+            Self::Group => "group",
+        })
+    }
+}
+
+/// How far a contact is trusted as a signer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Trust {
+    /// Signatures from this key are rejected
+    Untrusted,
+    /// Added, but not yet confirmed out of band
+    #[default]
+    Unverified,
+    /// Confirmed out of band, e.g. with `keys compare`
+    Verified,
+    /// Verified, and keys they certify are trusted too
+    Introducer,
+}
+
+impl fmt::Display for Trust {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Untrusted => "untrusted",
+            Self::Unverified => "unverified",
+            Self::Verified => "verified",
+            Self::Introducer => "introducer",
+        })
+    }
+}
+
+/// Why a key is trusted, or is not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Validation {
+    /// Your own identity, or a contact you verified
+    Direct,
+    /// Certified by one of your identities or by an introducer
+    Certified { introducer: String },
+    /// A contact marked untrusted
+    Untrusted,
+    /// Neither verified nor certified
+    Unknown,
+}
+
+impl fmt::Display for Validation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Direct => f.write_str("directly trusted"),
+            Self::Certified { introducer } => write!(f, "certified by {introducer}"),
+            Self::Untrusted => f.write_str("untrusted"),
+            Self::Unknown => f.write_str("unknown"),
+        }
+    }
+}
+
+/// The keyring entry whose key made a signature.
+pub struct Signer {
+    /// The entry's name, or the name a certification gives the key
+    pub name: String,
+    pub key: PublicKey,
+    /// `None` for your own identities and keys known only by certification
+    pub trust: Option<Trust>,
+    pub validation: Validation,
+}
+
+impl Signer {
+    /// Whether signatures by this key are accepted. Unknown signers are: they
+    /// are in the keyring because you added them, and only `untrusted` rejects.
+    pub fn is_trusted(&self) -> bool {
+        self.validation != Validation::Untrusted
+    }
+
+    /// Trust as shown beside the signer's name.
+    pub fn trust_label(&self) -> String {
+        match (&self.validation, self.trust) {
+            (Validation::Untrusted, _) => Validation::Untrusted.to_string(),
+            (Validation::Direct, None) => "your identity".to_string(),
+            (validation, Some(trust)) => format!("{trust}, {validation}"),
+            (validation, None) => validation.to_string(),
+        }
+    }
+}
+
+pub struct Keyring {
+    dir: PathBuf,
+}
+
+impl Keyring {
+    /// The keyring in the XDG data directory. Nothing is created until the
+    /// first entry is added.
+    pub fn open() -> Result<Self> {
+        // The XDG spec says relative values are invalid and must be ignored
+        let data_home = match std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+        {
+            Some(dir) => dir,
+            None => std::env::var_os("HOME")
+                .filter(|home| !home.is_empty())
+                .map(|home| PathBuf::from(home).join(".local").join("share"))
+                .ok_or_else(|| {
+                    anyhow!("Cannot locate the keyring: neither XDG_DATA_HOME nor HOME is set")
+                })?,
+        };
+        Ok(Self::at(data_home.join("zimhide").join("keyring")))
+    }
+
+    /// A keyring rooted at `dir`.
+    pub fn at(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn contact_path(&self, name: &str) -> PathBuf {
+        self.dir.join("contacts").join(format!("{name}.pub"))
+    }
+
+    fn identity_path(&self, name: &str) -> PathBuf {
+        self.dir.join("identities").join(format!("{name}.priv"))
+    }
+
+    fn identity_public_path(&self, name: &str) -> PathBuf {
+        self.identity_path(name).with_extension("pub")
+    }
+
+    fn group_path(&self, name: &str) -> PathBuf {
+        self.dir.join("groups").join(name)
+    }
+
+    fn trust_path(&self, name: &str) -> PathBuf {
+        self.dir.join("trust").join(name)
+    }
+
+    fn revocation_path(&self, id: &[u8; KEY_ID_SIZE]) -> PathBuf {
+        self.dir
+            .join("revocations")
+            .join(format!("{}.rev", hex_id(id)))
+    }
+
+    fn certifications_dir(&self, id: &[u8; KEY_ID_SIZE]) -> PathBuf {
+        self.dir.join("certifications").join(hex_id(id))
+    }
+
+    fn default_path(&self) -> PathBuf {
+        self.dir.join("default")
+    }
+
+    /// What `name` refers to, if anything.
+    pub fn kind(&self, name: &str) -> Option<EntryKind> {
+        if validate_name(name).is_err() {
+            None
+        } else if self.contact_path(name).is_file() {
+            Some(EntryKind::Contact)
+        } else if self.identity_path(name).is_file() {
+            Some(EntryKind::Identity)
+        } else if self.group_path(name).is_file() {
+            Some(EntryKind::Group)
+        } else {
+            None
+        }
+    }
+
+    /// Sorted names of every entry of `kind`.
+    pub fn names(&self, kind: EntryKind) -> Result<Vec<String>> {
+        let (dir, extension) = match kind {
+            EntryKind::Contact => (self.dir.join("contacts"), Some("pub")),
+            EntryKind::Identity => (self.dir.join("identities"), Some("priv")),
+            EntryKind::Group => (self.dir.join("groups"), None),
+        };
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&dir)
+            .with_context(|| format!("Failed to read keyring: {}", dir.display()))?
+        {
+            let path = entry?.path();
+            let name = match extension {
+                Some(extension) if path.extension().and_then(|e| e.to_str()) == Some(extension) => {
+                    path.file_stem()
+                }
+                Some(_) => None,
+                None => path.file_name(),
+            };
+            if let Some(name) = name.and_then(|n| n.to_str())
+                && validate_name(name).is_ok()
+            {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn add_contact(&self, name: &str, key: &PublicKey) -> Result<()> {
+        self.check_free(name)?;
+        let path = self.contact_path(name);
+        create_parent(&path)?;
+        key.save_as(&path, storage_format(key))
+    }
+
+    /// Store a private key file's text unchanged, with its public key beside it.
+    pub fn add_identity(&self, name: &str, private_text: &str, public: &PublicKey) -> Result<()> {
+        self.check_free(name)?;
+        let path = self.identity_path(name);
+        create_parent(&path)?;
+        write_private_file(&path, private_text)?;
+        public.save_as(&self.identity_public_path(name), storage_format(public))
+    }
+
+    pub fn add_group(&self, name: &str, members: &[String]) -> Result<()> {
+        self.check_free(name)?;
+        if members.is_empty() {
+            return Err(anyhow!("A group needs at least one member"));
+        }
+        for member in members {
+            match self.kind(member) {
+                Some(EntryKind::Contact | EntryKind::Identity) => {}
+                Some(EntryKind::Group) => {
+                    return Err(anyhow!("Groups cannot contain groups: {}", member));
+                }
+                None => return Err(anyhow!("No contact or identity named {}", member)),
+            }
+        }
+        let path = self.group_path(name);
+        create_parent(&path)?;
+        self.write_members(name, members)
+    }
+
+    fn write_members(&self, group: &str, members: &[String]) -> Result<()> {
+        let mut content = members.join("\n");
+        content.push('\n');
+        let path = self.group_path(group);
+        fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Member names of a group.
+    pub fn members(&self, group: &str) -> Result<Vec<String>> {
+        let path = self.group_path(group);
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read group: {}", path.display()))?;
+        Ok(content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Delete an entry, dropping it from groups and clearing it as the
+    /// default identity.
+    pub fn remove(&self, name: &str) -> Result<EntryKind> {
+        let kind = self.require(name)?;
+        match kind {
+            EntryKind::Contact => {
+                fs::remove_file(self.contact_path(name))?;
+                let trust = self.trust_path(name);
+                if trust.exists() {
+                    fs::remove_file(trust)?;
+                }
+            }
+            EntryKind::Identity => {
+                fs::remove_file(self.identity_path(name))?;
+                let public = self.identity_public_path(name);
+                if public.exists() {
+                    fs::remove_file(public)?;
+                }
+                if self.default_identity()?.as_deref() == Some(name) {
+                    fs::remove_file(self.default_path())?;
+                }
+            }
+            EntryKind::Group => fs::remove_file(self.group_path(name))?,
+        }
+        if kind != EntryKind::Group {
+            self.replace_member(name, None)?;
+        }
+        Ok(kind)
+    }
+
+    /// Rename an entry, updating groups and the default identity.
+    pub fn rename(&self, old: &str, new: &str) -> Result<EntryKind> {
+        let kind = self.require(old)?;
+        self.check_free(new)?;
+        match kind {
+            EntryKind::Contact => {
+                fs::rename(self.contact_path(old), self.contact_path(new))?;
+                let trust = self.trust_path(old);
+                if trust.exists() {
+                    fs::rename(trust, self.trust_path(new))?;
+                }
+            }
+            EntryKind::Identity => {
+                fs::rename(self.identity_path(old), self.identity_path(new))?;
+                let public = self.identity_public_path(old);
+                if public.exists() {
+                    fs::rename(public, self.identity_public_path(new))?;
+                }
+                if self.default_identity()?.as_deref() == Some(old) {
+                    self.set_default(new)?;
+                }
+            }
+            EntryKind::Group => fs::rename(self.group_path(old), self.group_path(new))?,
+        }
+        if kind != EntryKind::Group {
+            self.replace_member(old, Some(new))?;
+        }
+        Ok(kind)
+    }
+
+    /// Rename or (with `None`) drop a member in every group.
+    fn replace_member(&self, old: &str, new: Option<&str>) -> Result<()> {
+        for group in self.names(EntryKind::Group)? {
+            let members = self.members(&group)?;
+            if !members.iter().any(|member| member == old) {
+                continue;
+            }
+            let updated: Vec<String> = members
+                .into_iter()
+                .filter_map(|member| match new {
+                    _ if member != old => Some(member),
+                    Some(new) => Some(new.to_string()),
+                    None => None,
+                })
+                .collect();
+            self.write_members(&group, &updated)?;
+        }
+        Ok(())
+    }
+
+    /// The public key of a contact or identity.
+    pub fn public_key(&self, name: &str) -> Result<PublicKey> {
+        match self.require(name)? {
+            EntryKind::Contact => PublicKey::load(&self.contact_path(name)),
+            EntryKind::Identity => PublicKey::load(&self.identity_public_path(name)),
+            EntryKind::Group => Err(anyhow!("{} is a group, not a single key", name)),
+        }
+    }
+
+    /// The public keys `name` stands for: one, or each member of a group.
+    pub fn recipients(&self, name: &str) -> Result<Vec<PublicKey>> {
+        if self.require(name)? != EntryKind::Group {
+            return Ok(vec![self.public_key(name)?]);
+        }
+        let members = self.members(name)?;
+        if members.is_empty() {
+            return Err(anyhow!("Group {} has no members", name));
+        }
+        members
+            .iter()
+            .map(|member| {
+                self.public_key(member)
+                    .with_context(|| format!("In group {}", name))
+            })
+            .collect()
+    }
+
+    /// The private key file of an identity.
+    pub fn identity(&self, name: &str) -> Result<PathBuf> {
+        match self.require(name)? {
+            EntryKind::Identity => Ok(self.identity_path(name)),
+            kind => Err(anyhow!(
+                "{} is a {}; only identities hold private keys",
+                name,
+                kind
+            )),
+        }
+    }
+
+    /// A contact's trust level.
+    pub fn trust(&self, name: &str) -> Result<Trust> {
+        if self.require(name)? != EntryKind::Contact {
+            return Err(anyhow!("Only contacts have a trust level: {}", name));
+        }
+        let path = self.trust_path(name);
+        if !path.exists() {
+            return Ok(Trust::default());
+        }
+        let level = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Trust::from_str(level.trim(), true)
+            .map_err(|_| anyhow!("Invalid trust level in {}", path.display()))
+    }
+
+    pub fn set_trust(&self, name: &str, trust: Trust) -> Result<()> {
+        match self.require(name)? {
+            EntryKind::Contact => {}
+            EntryKind::Identity => {
+                return Err(anyhow!("{} is your own identity and always trusted", name));
+            }
+            EntryKind::Group => return Err(anyhow!("Trust is set per contact, not on groups")),
+        }
+        let path = self.trust_path(name);
+        if trust == Trust::default() {
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+            return Ok(());
+        }
+        create_parent(&path)?;
+        fs::write(&path, format!("{trust}\n"))
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The identity or contact whose key has this id, or else a key that an
+    /// introducer certified.
+    pub fn find_key_id(&self, id: &[u8; KEY_ID_SIZE]) -> Result<Option<Signer>> {
+        if let Some((name, key, trust)) = self.lookup(id)? {
+            let validation = match trust {
+                None | Some(Trust::Verified | Trust::Introducer) => Validation::Direct,
+                Some(Trust::Untrusted) => Validation::Untrusted,
+                Some(Trust::Unverified) => match self.introduced(id)? {
+                    Some((_, introducer)) => Validation::Certified { introducer },
+                    None => Validation::Unknown,
+                },
+            };
+            return Ok(Some(Signer {
+                name,
+                key,
+                trust,
+                validation,
+            }));
+        }
+        Ok(self
+            .introduced(id)?
+            .map(|(certification, introducer)| Signer {
+                name: certification.name,
+                key: certification.subject,
+                trust: None,
+                validation: Validation::Certified { introducer },
+            }))
+    }
+
+    /// The identity or contact whose key has this id, with a contact's trust.
+    fn lookup(&self, id: &[u8; KEY_ID_SIZE]) -> Result<Option<(String, PublicKey, Option<Trust>)>> {
+        for name in self.names(EntryKind::Identity)? {
+            let key = self.public_key(&name)?;
+            if key.key_id() == *id {
+                return Ok(Some((name, key, None)));
+            }
+        }
+        for name in self.names(EntryKind::Contact)? {
+            let key = self.public_key(&name)?;
+            if key.key_id() == *id {
+                let trust = Some(self.trust(&name)?);
+                return Ok(Some((name, key, trust)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// A certification of the key with this id by one of your identities or
+    /// an unrevoked introducer, with the certifier's name.
+    fn introduced(&self, id: &[u8; KEY_ID_SIZE]) -> Result<Option<(Certification, String)>> {
+        for certification in self.certifications(id)? {
+            let Some((name, certifier, trust)) = self.lookup(&certification.certifier)? else {
+                continue;
+            };
+            let may_introduce = matches!(trust, None | Some(Trust::Introducer));
+            if may_introduce
+                && certification.subject.key_id() == *id
+                && self.revocation(&certification.certifier)?.is_none()
+                && certification.verify(&certifier).is_ok()
+            {
+                return Ok(Some((certification, name)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Store a certification, replacing any earlier one by the same key.
+    pub fn add_certification(&self, certification: &Certification) -> Result<()> {
+        let path = self
+            .certifications_dir(&certification.subject.key_id())
+            .join(format!("{}.cert", hex_id(&certification.certifier)));
+        create_parent(&path)?;
+        certification.save(&path)
+    }
+
+    /// Every stored certification of the key with this id.
+    pub fn certifications(&self, id: &[u8; KEY_ID_SIZE]) -> Result<Vec<Certification>> {
+        let dir = self.certifications_dir(id);
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut certifications = Vec::new();
+        for entry in fs::read_dir(&dir)
+            .with_context(|| format!("Failed to read keyring: {}", dir.display()))?
+        {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) == Some("cert") {
+                certifications.push(Certification::load(&path)?);
+            }
+        }
+        Ok(certifications)
+    }
+
+    /// Store a revocation certificate. Keys need not be in the keyring, so
+    /// a key added later is already known to be revoked.
+    pub fn add_revocation(&self, revocation: &Revocation) -> Result<()> {
+        let path = self.revocation_path(&revocation.key_id());
+        create_parent(&path)?;
+        revocation.save(&path)
+    }
+
+    /// The imported revocation of the key with this id, if any.
+    pub fn revocation(&self, id: &[u8; KEY_ID_SIZE]) -> Result<Option<Revocation>> {
+        let path = self.revocation_path(id);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Revocation::load(&path).map(Some)
+    }
+
+    pub fn default_identity(&self) -> Result<Option<String>> {
+        let path = self.default_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let name = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(Some(name.trim().to_string()).filter(|name| !name.is_empty()))
+    }
+
+    pub fn set_default(&self, name: &str) -> Result<()> {
+        self.identity(name)?;
+        fs::write(self.default_path(), format!("{name}\n"))
+            .with_context(|| format!("Failed to write {}", self.default_path().display()))
+    }
+
+    fn require(&self, name: &str) -> Result<EntryKind> {
+        validate_name(name)?;
+        self.kind(name)
+            .ok_or_else(|| anyhow!("No keyring entry named {}", name))
+    }
+
+    fn check_free(&self, name: &str) -> Result<()> {
+        validate_name(name)?;
+        match self.kind(name) {
+            Some(kind) => Err(anyhow!("The keyring already has a {} named {}", kind, name)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Names start with a letter or digit and hold letters, digits, `.`, `_`,
+/// `-` and `@`, so they never read as paths.
+pub fn validate_name(name: &str) -> Result<()> {
+    let valid = name.len() <= MAX_NAME_LEN
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '@'));
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Invalid keyring name {:?}: use up to {} letters, digits, '.', '_', '-' or '@', starting with a letter or digit",
+            name,
+            MAX_NAME_LEN
+        ))
+    }
+}
+
+/// The keyring name an argument refers to. Existing files and literal keys
+/// win over names, so paths behave as before.
+pub fn as_name(arg: &Path) -> Option<&str> {
+    if arg.exists() {
+        return None;
+    }
+    arg.to_str()
+        .filter(|s| !s.starts_with("age1") && !s.starts_with("ssh-"))
+        .filter(|s| validate_name(s).is_ok())
+}
+
+/// Keys for `--encrypt-to` values: files, literal keys, contacts, identities
+/// or groups. A recipient named more than once is only included once.
+pub fn resolve_recipients(args: &[PathBuf]) -> Result<Vec<PublicKey>> {
+    let mut keyring = None;
+    let mut recipients: Vec<PublicKey> = Vec::new();
+    for arg in args {
+        let keys = match as_name(arg) {
+            Some(name) => {
+                let keyring = match keyring {
+                    Some(ref keyring) => keyring,
+                    None => keyring.insert(Keyring::open()?),
+                };
+                keyring.recipients(name)?
+            }
+            None => vec![PublicKey::load(arg)?],
+        };
+        for key in keys {
+            check_usable(&key, &format!("Recipient {}", arg.display()))?;
+            if !recipients
+                .iter()
+                .any(|known| known.fingerprint() == key.fingerprint())
+            {
+                recipients.push(key);
+            }
+        }
+    }
+    Ok(recipients)
+}
+
+/// The key for a `--verify` value: a file, literal key, contact or identity.
+pub fn resolve_public_key(arg: &Path) -> Result<PublicKey> {
+    match as_name(arg) {
+        Some(name) => Keyring::open()?.public_key(name),
+        None => PublicKey::load(arg),
+    }
+}
+
+/// The private key file for a `--key` value: a file or an identity name.
+pub fn resolve_identity(arg: &Path) -> Result<PathBuf> {
+    match as_name(arg) {
+        Some(name) => Keyring::open()?.identity(name),
+        None => Ok(arg.to_path_buf()),
+    }
+}
+
+/// The private key file for an optional `--key`, falling back to the
+/// default identity.
+pub fn identity_or_default(arg: Option<&Path>) -> Result<Option<PathBuf>> {
+    if let Some(arg) = arg {
+        return resolve_identity(arg).map(Some);
+    }
+    // Without a home directory there is simply no default
+    let Ok(keyring) = Keyring::open() else {
+        return Ok(None);
+    };
+    match keyring.default_identity()? {
+        Some(name) => keyring
+            .identity(&name)
+            .with_context(|| format!("Default identity {} is missing", name))
+            .map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Look up the signer of a message in the keyring, if there is one.
+pub fn find_signer(id: &[u8; KEY_ID_SIZE]) -> Result<Option<Signer>> {
+    match Keyring::open() {
+        Ok(keyring) => keyring.find_key_id(id),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Look up a revocation of the key with this id in the keyring.
+pub fn find_revocation(id: &[u8; KEY_ID_SIZE]) -> Result<Option<Revocation>> {
+    match Keyring::open() {
+        Ok(keyring) => keyring.revocation(id),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Refuse a key that has expired or been revoked; `what` names it in the error.
+pub fn check_usable(key: &PublicKey, what: &str) -> Result<()> {
+    if let Some(revocation) = find_revocation(&key.key_id())? {
+        return Err(anyhow!(
+            "{} ({}) was revoked on {} ({})",
+            what,
+            key.fingerprint(),
+            format_timestamp(revocation.revoked),
+            revocation.reason
+        ));
+    }
+    // The validity block can be stripped from a public key, which then reads
+    // as never expiring, so a copy in the keyring still counts
+    let expires = match key.expires() {
+        Some(expires) => Some(expires),
+        None => find_signer(&key.key_id())?.and_then(|known| known.key.expires()),
+    };
+    if let Some(expires) = expires.filter(|&expires| expires <= unix_now()) {
+        return Err(anyhow!(
+            "{} ({}) expired on {}",
+            what,
+            key.fingerprint(),
+            format_timestamp(expires)
+        ));
+    }
+    Ok(())
+}
+
+/// Read a key given to `keys add`: a private key file becomes an identity,
+/// anything else is loaded as a public key.
+pub fn read_private_text(path: &Path) -> Result<Option<Zeroizing<String>>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = Zeroizing::new(
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?,
+    );
+    Ok(PrivateKey::is_private_key_text(&content).then_some(content))
+}
+
+/// age recipients have no zimhide encoding; everything else is stored as zimhide.
+fn storage_format(key: &PublicKey) -> KeyFormat {
+    if key.ed25519.is_some() {
+        KeyFormat::Zimhide
+    } else {
+        KeyFormat::Age
+    }
+}
+
+/// A key id as 32 hex digits, for file names.
+fn hex_id(id: &[u8; KEY_ID_SIZE]) -> String {
+    id.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn create_parent(path: &Path) -> Result<()> {
+    let parent = path.parent().expect("keyring paths have a parent");
+    fs::create_dir_all(parent)
+        .with_context(|| format!("Failed to create keyring directory: {}", parent.display()))?;
+    // Identities live here too; keep the keyring private
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        for dir in [parent, parent.parent().unwrap_or(parent)] {
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::keys::Keypair;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_names_and_groups() {
+        let dir = tempdir().unwrap();
+        let keyring = Keyring::at(dir.path().join("keyring"));
+        let alice = Keypair::generate();
+        let bob = Keypair::generate();
+
+        keyring.add_contact("alice", &alice.public).unwrap();
+        keyring.add_contact("bob", &bob.public).unwrap();
+        assert!(keyring.add_contact("alice", &bob.public).is_err());
+        keyring
+            .add_group("team", &["alice".to_string(), "bob".to_string()])
+            .unwrap();
+        assert!(keyring.add_group("nested", &["team".to_string()]).is_err());
+        assert!(keyring.add_group("ghosts", &["carol".to_string()]).is_err());
+
+        assert_eq!(keyring.kind("team"), Some(EntryKind::Group));
+        assert_eq!(keyring.names(EntryKind::Contact).unwrap(), ["alice", "bob"]);
+        assert_eq!(keyring.recipients("team").unwrap().len(), 2);
+        assert_eq!(
+            keyring.public_key("alice").unwrap().fingerprint(),
+            alice.public.fingerprint()
+        );
+
+        // Renames and removals follow into groups
+        keyring.rename("alice", "alice2").unwrap();
+        assert_eq!(keyring.members("team").unwrap(), ["alice2", "bob"]);
+        keyring.remove("bob").unwrap();
+        assert_eq!(keyring.members("team").unwrap(), ["alice2"]);
+        assert_eq!(keyring.kind("bob"), None);
+    }
+
+    #[test]
+    fn test_trust_and_signer_lookup() {
+        let dir = tempdir().unwrap();
+        let keyring = Keyring::at(dir.path().join("keyring"));
+        let me = Keypair::generate();
+        let alice = Keypair::generate();
+        let text = me.private.to_pem().unwrap();
+        keyring.add_identity("me", &text, &me.public).unwrap();
+        keyring.add_contact("alice", &alice.public).unwrap();
+
+        assert_eq!(keyring.trust("alice").unwrap(), Trust::Unverified);
+        keyring.set_trust("alice", Trust::Verified).unwrap();
+        keyring.rename("alice", "al").unwrap();
+        assert_eq!(keyring.trust("al").unwrap(), Trust::Verified);
+        assert!(keyring.set_trust("me", Trust::Untrusted).is_err());
+
+        let signer = keyring
+            .find_key_id(&alice.public.key_id())
+            .unwrap()
+            .unwrap();
+        assert_eq!(signer.name, "al");
+        assert_eq!(signer.trust, Some(Trust::Verified));
+        let own = keyring.find_key_id(&me.public.key_id()).unwrap().unwrap();
+        assert!(own.is_trusted());
+        assert_eq!(own.trust_label(), "your identity");
+
+        keyring.set_trust("al", Trust::Untrusted).unwrap();
+        let signer = keyring
+            .find_key_id(&alice.public.key_id())
+            .unwrap()
+            .unwrap();
+        assert!(!signer.is_trusted());
+        assert!(
+            keyring
+                .find_key_id(&Keypair::generate().public.key_id())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_revocations() {
+        let dir = tempdir().unwrap();
+        let keyring = Keyring::at(dir.path().join("keyring"));
+        let alice = Keypair::generate();
+        let revocation =
+            Revocation::create(&alice.private, crate::crypto::RevocationReason::Superseded)
+                .unwrap();
+
+        assert!(
+            keyring
+                .revocation(&alice.public.key_id())
+                .unwrap()
+                .is_none()
+        );
+        keyring.add_revocation(&revocation).unwrap();
+        let stored = keyring.revocation(&alice.public.key_id()).unwrap().unwrap();
+        assert_eq!(stored.revoked, revocation.revoked);
+        assert!(
+            keyring
+                .revocation(&Keypair::generate().public.key_id())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_certified_keys() {
+        let dir = tempdir().unwrap();
+        let keyring = Keyring::at(dir.path().join("keyring"));
+        let me = Keypair::generate();
+        let bob = Keypair::generate();
+        let carol = Keypair::generate();
+        let dave = Keypair::generate();
+        let text = me.private.to_pem().unwrap();
+        keyring.add_identity("me", &text, &me.public).unwrap();
+        keyring.add_contact("bob", &bob.public).unwrap();
+        keyring.add_contact("dave", &dave.public).unwrap();
+
+        // Your own certifications count directly
+        let certification = Certification::create(&dave.public, "dave", &me.private).unwrap();
+        keyring.add_certification(&certification).unwrap();
+        let signer = keyring.find_key_id(&dave.public.key_id()).unwrap().unwrap();
+        assert_eq!(
+            signer.validation,
+            Validation::Certified {
+                introducer: "me".to_string()
+            }
+        );
+        assert_eq!(signer.trust_label(), "unverified, certified by me");
+
+        // Someone else's only once they are an introducer
+        let certification = Certification::create(&carol.public, "carol", &bob.private).unwrap();
+        keyring.add_certification(&certification).unwrap();
+        let carol_id = carol.public.key_id();
+        assert!(keyring.find_key_id(&carol_id).unwrap().is_none());
+        keyring.set_trust("bob", Trust::Introducer).unwrap();
+        let signer = keyring.find_key_id(&carol_id).unwrap().unwrap();
+        assert_eq!(signer.name, "carol");
+        assert_eq!(signer.trust_label(), "certified by bob");
+        assert!(signer.is_trusted());
+
+        // A revoked introducer vouches for nobody
+        let revocation =
+            Revocation::create(&bob.private, crate::crypto::RevocationReason::Compromised).unwrap();
+        keyring.add_revocation(&revocation).unwrap();
+        assert!(keyring.find_key_id(&carol_id).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_default_identity() {
+        let dir = tempdir().unwrap();
+        let keyring = Keyring::at(dir.path().join("keyring"));
+        let me = Keypair::generate();
+        let text = me.private.to_pem().unwrap();
+
+        keyring.add_identity("me", &text, &me.public).unwrap();
+        assert!(keyring.set_default("nobody").is_err());
+        keyring.set_default("me").unwrap();
+        assert_eq!(keyring.default_identity().unwrap().as_deref(), Some("me"));
+
+        keyring.rename("me", "myself").unwrap();
+        assert_eq!(
+            keyring.default_identity().unwrap().as_deref(),
+            Some("myself")
+        );
+        let loaded = PrivateKey::load(&keyring.identity("myself").unwrap()).unwrap();
+        assert_eq!(loaded.public_key().fingerprint(), me.public.fingerprint());
+
+        keyring.remove("myself").unwrap();
+        assert_eq!(keyring.default_identity().unwrap(), None);
+    }
+
+    #[test]
+    fn test_validate_name() {
+        for name in ["alice", "bob.smith", "team-2", "carol@example.org"] {
+            assert!(validate_name(name).is_ok(), "{name}");
+        }
+        for name in ["", ".hidden", "../etc", "a/b", "-flag", "with space"] {
+            assert!(validate_name(name).is_err(), "{name}");
+        }
+    }
+}
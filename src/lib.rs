@@ -4,12 +4,16 @@
 
 use clap::{CommandFactory, Parser, Subcommand};
 
+pub mod attachment;
 pub mod audio;
 pub mod commands;
 pub mod crypto;
 pub mod format;
+pub mod keyring;
+pub mod passphrase;
 pub mod progress;
 pub mod stego;
+pub mod time;
 pub mod verbosity;
 pub mod wav;
 
@@ -42,10 +46,10 @@ impl Cli {
 
 #[derive(Subcommand)]
 pub enum Commands {
-    /// Embed text or audio into a WAV file
+    /// Embed text, audio or files into a WAV file
     Encode(commands::encode::EncodeArgs),
 
-    /// Extract text content from a WAV file
+    /// Extract text and attached files from a WAV file
     Decode(commands::decode::DecodeArgs),
 
     /// Extract and play embedded audio from a WAV file
@@ -54,9 +58,18 @@ pub enum Commands {
     /// Generate a keypair for encryption and signing
     Keygen(commands::keygen::KeygenArgs),
 
+    /// Manage the keyring of named keys and compare keys
+    Keys(commands::keys::KeysArgs),
+
+    /// Add, change or remove passphrase protection on a private key file
+    Passwd(commands::passwd::PasswdArgs),
+
     /// Inspect embedded content metadata without decrypting
     Inspect(commands::inspect::InspectArgs),
 
+    /// Measure Argon2id cost and suggest --kdf-* settings for this machine
+    Calibrate(commands::calibrate::CalibrateArgs),
+
     /// Generate shell completions
     Completions(commands::completions::CompletionsArgs),
 }
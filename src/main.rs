@@ -11,7 +11,10 @@ fn main() -> Result<()> {
         Commands::Decode(args) => zimhide::commands::decode::run(args, verbosity),
         Commands::Play(args) => zimhide::commands::play::run(args, verbosity),
         Commands::Keygen(args) => zimhide::commands::keygen::run(args, verbosity),
+        Commands::Keys(args) => zimhide::commands::keys::run(args, verbosity),
+        Commands::Passwd(args) => zimhide::commands::passwd::run(args, verbosity),
         Commands::Inspect(args) => zimhide::commands::inspect::run(args, verbosity),
+        Commands::Calibrate(args) => zimhide::commands::calibrate::run(args, verbosity),
         Commands::Completions(args) => {
             zimhide::commands::completions::run(args);
             Ok(())
@@ -0,0 +1,332 @@
+//! Passphrase input that keeps secrets out of shell history and `ps`.
+
+use crate::crypto::{PrivateKey, SecretString};
+use anyhow::{Context, Result, anyhow};
+use clap::Args;
+use std::fs;
+use std::io::{BufRead, BufReader, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use zeroize::{Zeroize, Zeroizing};
+
+/// Environment variable consulted when no passphrase option is given.
+pub const PASSPHRASE_ENV: &str = "ZIMHIDE_PASSPHRASE";
+
+/// Environment variable holding the passphrase for a protected private key.
+pub const KEY_PASSPHRASE_ENV: &str = "ZIMHIDE_KEY_PASSPHRASE";
+
+/// Hint listing every way to supply a passphrase non-interactively.
+pub const PASSPHRASE_HINT: &str =
+    "Use --passphrase-file, --passphrase-fd, --passphrase-cmd or ZIMHIDE_PASSPHRASE";
+
+/// The mutually exclusive ways to pass a passphrase on the command line.
+#[derive(Args, Debug, Default, Clone)]
+#[group(id = "passphrase_source", multiple = false)]
+pub struct PassphraseArgs {
+    /// Passphrase (visible in shell history and `ps`; prefer the options below)
+    #[arg(long)]
+    pub passphrase: Option<String>,
+
+    /// Read the passphrase from the first line of a file
+    #[arg(long, value_name = "PATH")]
+    pub passphrase_file: Option<PathBuf>,
+
+    /// Read the passphrase from the first line of an open file descriptor
+    #[arg(long, value_name = "FD")]
+    pub passphrase_fd: Option<i32>,
+
+    /// Run a command (e.g. a password manager) and use the first line of its output
+    #[arg(long, value_name = "COMMAND")]
+    pub passphrase_cmd: Option<String>,
+}
+
+/// Whether to fall back to an interactive prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prompt {
+    Never,
+    Once,
+    /// Ask twice and require both entries to match
+    Confirm,
+}
+
+impl PassphraseArgs {
+    /// Whether any passphrase option was given.
+    pub fn is_given(&self) -> bool {
+        self.passphrase.is_some()
+            || self.passphrase_file.is_some()
+            || self.passphrase_fd.is_some()
+            || self.passphrase_cmd.is_some()
+    }
+
+    /// Find the passphrase: options first, then `ZIMHIDE_PASSPHRASE`, then a
+    /// no-echo prompt when stdin is a terminal. Returns `None` if there is no
+    /// source.
+    pub fn resolve(&self, prompt: Prompt) -> Result<Option<SecretString>> {
+        let passphrase = if let Some(ref passphrase) = self.passphrase {
+            Some(Zeroizing::new(passphrase.clone()))
+        } else {
+            read_source(
+                self.passphrase_file.as_deref(),
+                self.passphrase_fd,
+                self.passphrase_cmd.as_deref(),
+            )?
+        };
+        finish(passphrase, Some(PASSPHRASE_ENV), "Passphrase: ", prompt)
+    }
+}
+
+/// Where to find the passphrase of a protected private key.
+#[derive(Args, Debug, Default, Clone)]
+#[group(id = "key_passphrase_source", multiple = false)]
+pub struct KeyPassphraseArgs {
+    /// Read the private key passphrase from the first line of a file
+    #[arg(long, value_name = "PATH")]
+    pub key_passphrase_file: Option<PathBuf>,
+
+    /// Read the private key passphrase from the first line of an open file descriptor
+    #[arg(long, value_name = "FD")]
+    pub key_passphrase_fd: Option<i32>,
+
+    /// Run a command and use the first line of its output as the private key passphrase
+    #[arg(long, value_name = "COMMAND")]
+    pub key_passphrase_cmd: Option<String>,
+}
+
+impl KeyPassphraseArgs {
+    /// Whether any key passphrase option was given.
+    pub fn is_given(&self) -> bool {
+        self.key_passphrase_file.is_some()
+            || self.key_passphrase_fd.is_some()
+            || self.key_passphrase_cmd.is_some()
+    }
+
+    /// Find the passphrase for `key`: options first, then
+    /// `ZIMHIDE_KEY_PASSPHRASE`, then a no-echo prompt.
+    pub fn resolve(&self, key: &Path, prompt: Prompt) -> Result<Option<SecretString>> {
+        let passphrase = read_source(
+            self.key_passphrase_file.as_deref(),
+            self.key_passphrase_fd,
+            self.key_passphrase_cmd.as_deref(),
+        )?;
+        let label = format!("Passphrase for {}: ", key.display());
+        finish(passphrase, Some(KEY_PASSPHRASE_ENV), &label, prompt)
+    }
+
+    /// Load a private key, asking for its passphrase only if it is protected.
+    pub fn load_private_key(&self, path: &Path) -> Result<PrivateKey> {
+        PrivateKey::load_with(path, || {
+            self.resolve(path, Prompt::Once)?.ok_or_else(|| {
+                anyhow!(
+                    "Private key {} is passphrase-protected. Use --key-passphrase-file, --key-passphrase-fd, --key-passphrase-cmd or {}",
+                    path.display(),
+                    KEY_PASSPHRASE_ENV
+                )
+            })
+        })
+    }
+}
+
+/// A replacement passphrase for `key`, read from `file` or a confirmed
+/// prompt. The environment is not consulted, since it may hold the old one.
+pub fn new_key_passphrase(file: Option<&Path>, key: &Path) -> Result<SecretString> {
+    let passphrase = read_source(file, None, None)?;
+    let label = format!("New passphrase for {}: ", key.display());
+    finish(passphrase, None, &label, Prompt::Confirm)?.ok_or_else(|| {
+        anyhow!("No new passphrase given. Use --new-passphrase-file, or run in a terminal to be prompted.")
+    })
+}
+
+/// Read a passphrase from whichever of a file, descriptor or command is set.
+fn read_source(
+    file: Option<&Path>,
+    fd: Option<i32>,
+    command: Option<&str>,
+) -> Result<Option<SecretString>> {
+    if let Some(path) = file {
+        let file = fs::File::open(path)
+            .with_context(|| format!("Failed to open passphrase file: {}", path.display()))?;
+        Ok(Some(first_line(file, "passphrase file")?))
+    } else if let Some(fd) = fd {
+        Ok(Some(first_line(
+            open_fd(fd)?,
+            "passphrase file descriptor",
+        )?))
+    } else if let Some(command) = command {
+        Ok(Some(run_command(command)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Fall back to `env` and then a prompt, and reject empty passphrases.
+fn finish(
+    passphrase: Option<SecretString>,
+    env: Option<&str>,
+    label: &str,
+    prompt: Prompt,
+) -> Result<Option<SecretString>> {
+    let passphrase = if passphrase.is_some() {
+        passphrase
+    } else if let Some(passphrase) = env.and_then(|name| std::env::var(name).ok()) {
+        Some(Zeroizing::new(passphrase))
+    } else if prompt != Prompt::Never && std::io::stdin().is_terminal() {
+        Some(prompt_passphrase(label, prompt)?)
+    } else {
+        None
+    };
+
+    match passphrase {
+        Some(p) if p.is_empty() => Err(anyhow!("Passphrase is empty")),
+        other => Ok(other),
+    }
+}
+
+fn prompt_passphrase(label: &str, prompt: Prompt) -> Result<SecretString> {
+    let passphrase = Zeroizing::new(
+        rpassword::prompt_password(label)
+            .map_err(|e| anyhow!("Failed to read passphrase: {}", e))?,
+    );
+    if prompt == Prompt::Confirm {
+        let confirmation = Zeroizing::new(
+            rpassword::prompt_password("Confirm passphrase: ")
+                .map_err(|e| anyhow!("Failed to read passphrase: {}", e))?,
+        );
+        if passphrase != confirmation {
+            return Err(anyhow!("Passphrases do not match"));
+        }
+    }
+    Ok(passphrase)
+}
+
+/// First line of `reader`, without its line ending.
+fn first_line(reader: impl Read, source: &str) -> Result<SecretString> {
+    let mut line = Zeroizing::new(String::new());
+    BufReader::new(reader)
+        .read_line(&mut line)
+        .with_context(|| format!("Failed to read {source}"))?;
+    let trimmed = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(trimmed);
+    Ok(line)
+}
+
+#[cfg(unix)]
+fn open_fd(fd: i32) -> Result<fs::File> {
+    use std::os::fd::{BorrowedFd, OwnedFd};
+
+    if fd < 0 {
+        return Err(anyhow!("Invalid file descriptor: {}", fd));
+    }
+    // SAFETY: the descriptor is only borrowed long enough to duplicate it,
+    // so the caller's descriptor is never closed by us.
+    let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+    let owned: OwnedFd = borrowed
+        .try_clone_to_owned()
+        .map_err(|e| anyhow!("Cannot read file descriptor {}: {}", fd, e))?;
+    Ok(fs::File::from(owned))
+}
+
+#[cfg(not(unix))]
+fn open_fd(_fd: i32) -> Result<fs::File> {
+    Err(anyhow!("--passphrase-fd is only supported on Unix"))
+}
+
+fn run_command(command: &str) -> Result<SecretString> {
+    #[cfg(unix)]
+    let mut cmd = {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    };
+    #[cfg(not(unix))]
+    let mut cmd = {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    };
+
+    let mut output = cmd
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Failed to run passphrase command: {command}"))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Passphrase command failed ({}): {}",
+            output.status,
+            command
+        ));
+    }
+    let passphrase = first_line(output.stdout.as_slice(), "passphrase command output");
+    output.stdout.zeroize();
+    passphrase
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_first_line_strips_line_ending() {
+        assert_eq!(
+            first_line(b"secret\r\nrest".as_slice(), "test")
+                .unwrap()
+                .as_str(),
+            "secret"
+        );
+        assert_eq!(
+            first_line(b" spaced ".as_slice(), "test").unwrap().as_str(),
+            " spaced "
+        );
+    }
+
+    #[test]
+    fn test_passphrase_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("pass.txt");
+        fs::write(&path, "from file\n").unwrap();
+
+        let args = PassphraseArgs {
+            passphrase_file: Some(path),
+            ..Default::default()
+        };
+        assert_eq!(
+            args.resolve(Prompt::Never)
+                .unwrap()
+                .as_deref()
+                .map(String::as_str),
+            Some("from file")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_passphrase_command() {
+        let args = PassphraseArgs {
+            passphrase_cmd: Some("echo from-command".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            args.resolve(Prompt::Never)
+                .unwrap()
+                .as_deref()
+                .map(String::as_str),
+            Some("from-command")
+        );
+
+        let failing = PassphraseArgs {
+            passphrase_cmd: Some("exit 3".to_string()),
+            ..Default::default()
+        };
+        assert!(failing.resolve(Prompt::Never).is_err());
+    }
+
+    #[test]
+    fn test_empty_passphrase_rejected() {
+        let args = PassphraseArgs {
+            passphrase: Some(String::new()),
+            ..Default::default()
+        };
+        assert!(args.resolve(Prompt::Never).is_err());
+    }
+}
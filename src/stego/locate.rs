@@ -0,0 +1,62 @@
+//! Finding embedded data in a carrier, whichever method wrote it.
+
+use super::traits::{EmbedOptions, StegoMethod, StegoMethodType};
+use super::{LsbSteganography, MetadataSteganography};
+use crate::format::Header;
+use crate::format::integrity::looks_like_magic;
+use anyhow::Result;
+use std::path::Path;
+
+/// Embedded bytes found in a carrier, with the method that held them.
+pub struct Located {
+    pub data: Vec<u8>,
+    pub method: StegoMethodType,
+    pub capacity: usize,
+}
+
+/// Look for zimhide data, trying the metadata chunk first and then LSB.
+///
+/// Data whose magic bytes are recognisable despite a few bit errors is
+/// returned, so callers can report corruption instead of absence. Returns
+/// `Ok(None)` when nothing resembling zimhide data is present.
+pub fn locate(path: &Path, options: EmbedOptions) -> Result<Option<Located>> {
+    let metadata_stego = MetadataSteganography::new();
+    if let Ok(data) = metadata_stego.extract(path)
+        && looks_like_magic(&data)
+    {
+        return Ok(Some(Located {
+            data,
+            method: StegoMethodType::Metadata,
+            capacity: metadata_stego.capacity(path)?,
+        }));
+    }
+
+    let lsb_stego = LsbSteganography::new(options);
+    let capacity = lsb_stego.capacity(path)?;
+    if let Ok(data) = lsb_stego.extract(path)
+        && looks_like_magic(&data)
+    {
+        return Ok(Some(Located {
+            data,
+            method: StegoMethodType::Lsb,
+            capacity,
+        }));
+    }
+
+    // The LSB length prefix may be damaged while the header behind it is not
+    let raw = lsb_stego.extract_raw(path, Header::CHECKED_SIZE)?;
+    if !looks_like_magic(&raw) {
+        return Ok(None);
+    }
+
+    let data = match Header::from_bytes(&raw) {
+        Ok(header) => lsb_stego.extract_raw(path, header.embedded_size())?,
+        Err(_) => raw,
+    };
+
+    Ok(Some(Located {
+        data,
+        method: StegoMethodType::Lsb,
+        capacity,
+    }))
+}
@@ -55,6 +55,50 @@ impl LsbSteganography {
     }
 }
 
+impl LsbSteganography {
+    /// Read up to `max_bytes` of embedded data without trusting the length prefix.
+    ///
+    /// Used to look for a zimhide header when the prefix itself is damaged.
+    pub fn extract_raw(&self, input_path: &Path, max_bytes: usize) -> Result<Vec<u8>> {
+        let (spec, samples) = Self::get_spec_and_samples(input_path)?;
+
+        let bits_per_sample = self.options.bits_per_sample;
+        let mask = (1u32 << bits_per_sample) - 1;
+
+        let usable = self.usable_samples(samples.len(), spec.channels);
+        let available = ((usable * bits_per_sample as usize) / 8).saturating_sub(4);
+        let total_bits = (4 + max_bytes.min(available)) * 8;
+
+        let mut all_bits = Vec::with_capacity(total_bits);
+        for (sample_idx, sample) in samples.iter().enumerate() {
+            if all_bits.len() >= total_bits {
+                break;
+            }
+            if !self.should_use_sample(sample_idx, spec.channels) {
+                continue;
+            }
+
+            let bits = (*sample as u32) & mask;
+            for b in 0..bits_per_sample {
+                if all_bits.len() < total_bits {
+                    all_bits.push((bits >> b) & 1);
+                }
+            }
+        }
+
+        // Skip the length prefix bits
+        Ok(all_bits[32.min(all_bits.len())..]
+            .chunks_exact(8)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |byte, (bit_idx, &bit)| byte | ((bit as u8) << bit_idx))
+            })
+            .collect())
+    }
+}
+
 impl Default for LsbSteganography {
     fn default() -> Self {
         Self::new(EmbedOptions::default())
@@ -293,6 +337,20 @@ mod tests {
         assert_eq!(data.as_slice(), extracted.as_slice());
     }
 
+    #[test]
+    fn test_extract_raw_ignores_length_prefix() {
+        let input = create_test_wav();
+        let output = NamedTempFile::new().unwrap();
+
+        let stego = LsbSteganography::default();
+        stego
+            .embed(input.path(), output.path(), b"ZIMH-raw")
+            .unwrap();
+
+        let raw = stego.extract_raw(output.path(), 8).unwrap();
+        assert_eq!(raw, b"ZIMH-raw");
+    }
+
     #[test]
     fn test_capacity() {
         let input = create_test_wav();
@@ -1,7 +1,9 @@
+pub mod locate;
 pub mod lsb;
 pub mod metadata;
 pub mod traits;
 
+pub use locate::{Located, locate};
 pub use lsb::LsbSteganography;
 pub use metadata::MetadataSteganography;
 pub use traits::{StegoMethod, StegoMethodType};
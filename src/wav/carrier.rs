@@ -0,0 +1,61 @@
+//! Carrier fingerprints for signatures bound to a specific recording.
+
+use super::WavReader;
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// Low bits of each sample excluded from the hash; LSB embedding uses at most 4.
+const MASK: i32 = !0b1111;
+
+const DOMAIN: &[u8] = b"zimhide carrier v1\0";
+
+/// SHA-256 of the carrier's audio with embedding bits masked out.
+///
+/// The hash is identical before and after embedding, but changes if the
+/// payload is moved to a different recording.
+pub fn carrier_hash(path: &Path) -> Result<[u8; 32]> {
+    let reader = WavReader::open(path)?;
+    let mut hasher = Sha256::new();
+    hasher.update(DOMAIN);
+    hasher.update(reader.spec.channels.to_le_bytes());
+    hasher.update(reader.spec.sample_rate.to_le_bytes());
+    hasher.update(reader.spec.bits_per_sample.to_le_bytes());
+    hasher.update((reader.samples.len() as u64).to_le_bytes());
+    for sample in &reader.samples {
+        hasher.update((sample & MASK).to_le_bytes());
+    }
+    Ok(hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::WavWriter;
+    use hound::WavSpec;
+    use tempfile::tempdir;
+
+    fn write(path: &Path, samples: &[i32]) {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        WavWriter::write(path, spec, samples).unwrap();
+    }
+
+    #[test]
+    fn test_hash_ignores_embedding_bits() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.wav");
+        let b = dir.path().join("b.wav");
+        let c = dir.path().join("c.wav");
+        write(&a, &[1000, -2000, 3000]);
+        write(&b, &[1000 ^ 0b1011, -2000 ^ 0b1, 3000]);
+        write(&c, &[1016, -2000, 3000]);
+
+        assert_eq!(carrier_hash(&a).unwrap(), carrier_hash(&b).unwrap());
+        assert_ne!(carrier_hash(&a).unwrap(), carrier_hash(&c).unwrap());
+    }
+}
@@ -1,6 +1,8 @@
+mod carrier;
 mod reader;
 mod writer;
 
+pub use carrier::carrier_hash;
 #[allow(unused_imports)]
 pub use reader::WavReader;
 #[allow(unused_imports)]